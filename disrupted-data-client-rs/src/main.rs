@@ -1,54 +1,148 @@
+use std::time::Duration;
+
 use clap::Parser;
 use rustyline::DefaultEditor;
 
-use disrupted_data_sdk_rs::{Actions, Client, DisruptedDataError};
+use disrupted_data_sdk_rs::{Client, ClientOptions, DisruptedDataError, ErrorKind, GetRequest, NodeTransport, PutRequest, Visibility};
+use libp2p::identity::Keypair;
 use disrupted_data_sdk_rs::Identity;
 
-use crate::types::Args;
+use crate::types::command::USAGE;
+use crate::types::{Args, Command};
 
 mod types;
 
+const LOCAL_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[tokio::main]
 async fn main() {
     let mut arg = Args::parse();
     let identity = Identity::new(arg.key);
-    let ip = arg.ip.get_or_insert("127.0.0.1".to_string());
-    let new_client_result = Client::new(&identity.keypair, ip.clone(), "6969".to_string());
+    println!("Public key: {}", identity.get_user_public_key());
+    let (ip, port) = if arg.discover_local {
+        println!("Looking for a node on the local network...");
+        match Client::discover_local_node(LOCAL_DISCOVERY_TIMEOUT).await {
+            Ok((ip, port)) => {
+                println!("Found node at {}:{}", ip, port);
+                (ip, port)
+            }
+            Err(error) => {
+                println!("Aborting. {}", error.message);
+                return;
+            }
+        }
+    } else {
+        (arg.ip.get_or_insert("127.0.0.1".to_string()).clone(), arg.port.clone())
+    };
+    let node_transport = if arg.quic {
+        NodeTransport::Quic { port: arg.quic_port.clone().unwrap_or_else(|| port.clone()) }
+    } else {
+        NodeTransport::Tcp
+    };
+    let client_options = ClientOptions {
+        transport: node_transport,
+        connect_timeout: Duration::from_secs(arg.connect_timeout),
+        discover_nodes: arg.discover_nodes,
+    };
+    let entry_nodes = std::iter::once((ip, port)).chain(arg.nodes.clone()).collect();
+    let new_client_result = Client::new_with_options(&identity.keypair, entry_nodes, client_options);
     // let new_client_result = Client::new(&identity.keypair, "127.0.0.1".to_string(), "6969".to_string());
 
     match new_client_result {
         Ok(client) => {
-            prompt(client, &identity).await;
+            prompt(client, &identity.keypair).await;
         }
         Err(error) => {
-            println!("Aborting. Could not connect to the node");
+            println!("Aborting. Could not connect to the node: {}", error);
         }
     }
 }
 
-async fn prompt(mut client: Client, identity: &Identity) {
+async fn prompt(mut client: Client, keypair: &Keypair) {
     let mut line = DefaultEditor::new().unwrap();
 
     loop {
         let user_input = line.readline("disrupted-data >> ").unwrap();
 
-        let user_action: Actions = (user_input, identity).into();
-        if let Actions::Unknown = user_action {
-            println!("Usage:");
-            println!("put <<Data key>> <<Data value>>");
-            println!("get <<Data key>>");
-            continue;
+        let command = match Command::parse(&user_input) {
+            Some(command) => { command }
+            None => {
+                println!("Usage:");
+                for usage_line in USAGE {
+                    println!("{}", usage_line);
+                }
+                continue;
+            }
+        };
+
+        if let Err(err) = run_command(&mut client, keypair, command).await {
+            println!("Error executing action: {} ({:?})", err, err.kind)
         }
+    }
+}
 
-        let action_result = client.process_action(user_action).await;
-        match action_result {
-            Ok(action_result) => {
-                println!("Response: {:?}", action_result.get_message())
+async fn run_command(client: &mut Client, keypair: &Keypair, command: Command) -> Result<(), DisruptedDataError> {
+    match command {
+        Command::Put { key, value, encrypt, public, condition, ttl } => {
+            let visibility = if public { Visibility::Public } else { Visibility::Private };
+            let put_request = PutRequest::new_with_visibility(keypair, key.clone(), value.into_bytes(), visibility)
+                .with_condition(keypair, condition)
+                .with_ttl(keypair, ttl);
+            let version = match (encrypt, public) {
+                (true, true) => { return Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Encrypted values cannot be public".to_string())) }
+                (true, false) => { client.put_with(put_request.with_encryption()).await? }
+                (false, _) => { client.put_with(put_request).await? }
+            };
+            println!("Stored {} at version {}", key, version);
+        }
+        Command::Get { key, owner, version } => {
+            let get_request = match owner {
+                Some(owner) => { GetRequest::shared(keypair, owner.into_bytes(), key) }
+                None => { GetRequest::new(keypair, key) }
+            };
+            let value = client.get_with(get_request.with_version(keypair, version)).await?;
+            println!("{}", String::from_utf8_lossy(&value));
+        }
+        Command::GetPublic { owner, key } => {
+            let value = client.get_public(&owner, &key).await?;
+            println!("{}", String::from_utf8_lossy(&value));
+        }
+        Command::Delete { key } => {
+            client.delete(&key).await?;
+            println!("Deleted {}", key);
+        }
+        Command::History { key } => {
+            for record_version in client.history(&key).await? {
+                match record_version.value_hash {
+                    Some(value_hash) => { println!("Version {} at {}: {}", record_version.version, record_version.timestamp, value_hash) }
+                    None => { println!("Version {} at {}: deleted", record_version.version, record_version.timestamp) }
+                }
+            }
+        }
+        Command::List { prefix, cursor, limit } => {
+            let list_result = client.list(prefix.as_deref(), cursor.as_deref(), limit).await?;
+            for key in &list_result.keys {
+                println!("{}", key);
             }
-            Err(err) => {
-                println!("Error executing action: {}", err)
+            if let Some(next_cursor) = list_result.next_cursor {
+                println!("More keys available. Continue with --cursor {}", next_cursor);
             }
         }
-
+        Command::Proof { key, root } => {
+            let merkle_proof = match &root {
+                Some(root) => { client.prove_against(&key, root).await? }
+                None => { client.prove(&key).await? }
+            };
+            println!("Merkle root: {} value hash: {} verified: {} root trusted: {}", merkle_proof.root, merkle_proof.value_hash, merkle_proof.verify(), root.is_some());
+        }
+        Command::Grant { reader, scope } => {
+            client.grant(&reader, scope).await?;
+            println!("Access granted");
+        }
+        Command::Revoke { reader, scope } => {
+            client.revoke(&reader, scope).await?;
+            println!("Access revoked");
+        }
     }
+    Ok(())
 }
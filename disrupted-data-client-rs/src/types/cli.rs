@@ -7,7 +7,41 @@ pub struct Args {
     #[arg(long)]
     pub key: PathBuf,
 
-    #[arg(long)]
+    #[arg(long, conflicts_with = "discover_local")]
     pub ip: Option<String>,
 
-}
\ No newline at end of file
+    /// Find a node on the local network over mDNS instead of connecting to --ip
+    #[arg(long)]
+    pub discover_local: bool,
+
+    #[arg(long, default_value = "6969")]
+    pub port: String,
+
+    /// Connect over QUIC, falling back to TCP if the node cannot be reached
+    #[arg(long)]
+    pub quic: bool,
+
+    /// UDP port of the node's QUIC listener. Defaults to --port
+    #[arg(long)]
+    pub quic_port: Option<String>,
+
+    /// Seconds to wait for the connection to the node before failing a command
+    #[arg(long, default_value_t = 10)]
+    pub connect_timeout: u64,
+
+    /// Another node to fail over to, as <ip>:<port>. May be given more than once
+    #[arg(long = "node", value_parser = parse_node_address)]
+    pub nodes: Vec<(String, String)>,
+
+    /// Look up more nodes to fail over to through the connected node
+    #[arg(long)]
+    pub discover_nodes: bool,
+
+}
+
+fn parse_node_address(address: &str) -> Result<(String, String), String> {
+    match address.rsplit_once(':') {
+        Some((ip, port)) if !ip.is_empty() && port.parse::<u16>().is_ok() => { Ok((ip.to_string(), port.to_string())) }
+        _ => { Err(format!("expected <ip>:<port>, got {}", address)) }
+    }
+}
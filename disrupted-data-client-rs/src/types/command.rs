@@ -0,0 +1,194 @@
+use disrupted_data_sdk_rs::{GrantScope, PutCondition, VersionSelector};
+
+/// A line typed at the prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Put {
+        key: String,
+        value: String,
+        encrypt: bool,
+        public: bool,
+        condition: Option<PutCondition>,
+        /// Seconds until the value expires.
+        ttl: Option<u64>,
+    },
+    Get {
+        key: String,
+        owner: Option<String>,
+        version: Option<VersionSelector>,
+    },
+    GetPublic {
+        owner: String,
+        key: String,
+    },
+    Delete {
+        key: String,
+    },
+    History {
+        key: String,
+    },
+    List {
+        prefix: Option<String>,
+        cursor: Option<String>,
+        limit: Option<u32>,
+    },
+    Proof {
+        key: String,
+        /// Hex encoded Merkle root the proof must lead to.
+        root: Option<String>,
+    },
+    Grant {
+        reader: String,
+        scope: GrantScope,
+    },
+    Revoke {
+        reader: String,
+        scope: GrantScope,
+    },
+}
+
+pub const USAGE: &[&str] = &[
+    "put [--encrypt | --public] [--if-version <<Version>> | --if-absent] [--ttl <<Seconds>>] <<Data key>> <<Data value>>",
+    "get [--owner <<Owner public key>> | --public <<Owner public key>>] [--version <<Version>> | --at <<Unix time in milliseconds>>] <<Data key>>",
+    "delete <<Data key>>",
+    "history <<Data key>>",
+    "ls [<<Key prefix>>] [--cursor <<Last key of previous page>>] [--limit <<Count>>]",
+    "proof [--root <<Trusted Merkle root>>] <<Data key>>",
+    "grant <<Reader public key>> <<Data key>> | --prefix <<Key prefix>>",
+    "revoke <<Reader public key>> <<Data key>> | --prefix <<Key prefix>>",
+];
+
+impl Command {
+    /// Parses a prompt line. Words are separated by whitespace, except for the value of `put`, which is the rest
+    /// of the line as typed.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (action, arguments) = next_word(line)?;
+        match action.to_lowercase().as_str() {
+            "put" => { Self::parse_put(arguments) }
+            "get" => { Self::parse_get(&words(arguments)) }
+            "delete" => {
+                match words(arguments).as_slice() {
+                    [key] => { Some(Command::Delete { key: key.to_string() }) }
+                    _ => { None }
+                }
+            }
+            "history" => {
+                match words(arguments).as_slice() {
+                    [key] => { Some(Command::History { key: key.to_string() }) }
+                    _ => { None }
+                }
+            }
+            "ls" => { Self::parse_list(&words(arguments)) }
+            "proof" => {
+                match words(arguments).as_slice() {
+                    [key] => { Some(Command::Proof { key: key.to_string(), root: None }) }
+                    ["--root", root, key] => { Some(Command::Proof { key: key.to_string(), root: Some(root.to_string()) }) }
+                    _ => { None }
+                }
+            }
+            "grant" | "revoke" => {
+                let (reader, scope) = match words(arguments).as_slice() {
+                    [reader, "--prefix", prefix] => { (reader.to_string(), GrantScope::Prefix(prefix.to_string())) }
+                    [reader, key] => { (reader.to_string(), GrantScope::Key(key.to_string())) }
+                    _ => { return None }
+                };
+                if action.eq_ignore_ascii_case("grant") {
+                    Some(Command::Grant { reader, scope })
+                } else {
+                    Some(Command::Revoke { reader, scope })
+                }
+            }
+            _ => { None }
+        }
+    }
+
+    fn parse_put(mut arguments: &str) -> Option<Self> {
+        let (mut encrypt, mut public, mut condition, mut ttl) = (false, false, None, None);
+        loop {
+            let (word, mut rest) = next_word(arguments)?;
+            match word {
+                "--encrypt" => { encrypt = true }
+                "--public" => { public = true }
+                "--if-version" => {
+                    let (version, rest_after_version) = next_word(rest)?;
+                    condition = Some(PutCondition::IfVersion(version.parse().ok()?));
+                    rest = rest_after_version;
+                }
+                "--if-absent" => { condition = Some(PutCondition::IfAbsent) }
+                "--ttl" => {
+                    let (seconds, rest_after_ttl) = next_word(rest)?;
+                    ttl = Some(seconds.parse().ok().filter(|seconds| *seconds > 0)?);
+                    rest = rest_after_ttl;
+                }
+                _ => {
+                    let value = rest.trim_start();
+                    if value.is_empty() {
+                        return None;
+                    }
+                    return Some(Command::Put { key: word.to_string(), value: value.to_string(), encrypt, public, condition, ttl });
+                }
+            }
+            arguments = rest;
+        }
+    }
+
+    /// Parses `[--owner <owner> | --public <owner>] [--version <version> | --at <millis>] <key>`. Published records
+    /// have no history, so `--public` cannot be combined with a version.
+    fn parse_get(arguments: &[&str]) -> Option<Self> {
+        let mut key = None;
+        let mut owner = None;
+        let mut public_owner = None;
+        let mut version = None;
+
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            match *argument {
+                "--owner" => { owner = Some(arguments.next()?.to_string()) }
+                "--public" => { public_owner = Some(arguments.next()?.to_string()) }
+                "--version" => { version = Some(VersionSelector::Version(arguments.next()?.parse().ok()?)) }
+                "--at" => { version = Some(VersionSelector::At(arguments.next()?.parse().ok()?)) }
+                _ if key.is_none() => { key = Some(argument.to_string()) }
+                _ => { return None; }
+            }
+        }
+        match (key?, owner, public_owner, version) {
+            (key, owner, None, version) => { Some(Command::Get { key, owner, version }) }
+            (key, None, Some(owner), None) => { Some(Command::GetPublic { owner, key }) }
+            _ => { None }
+        }
+    }
+
+    /// Parses `[<prefix>] [--cursor <key>] [--limit <count>]`.
+    fn parse_list(arguments: &[&str]) -> Option<Self> {
+        let mut prefix = None;
+        let mut cursor = None;
+        let mut limit = None;
+
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            match *argument {
+                "--cursor" => { cursor = Some(arguments.next()?.to_string()) }
+                "--limit" => { limit = Some(arguments.next()?.parse().ok()?) }
+                _ if prefix.is_none() => { prefix = Some(argument.to_string()) }
+                _ => { return None; }
+            }
+        }
+        Some(Command::List { prefix, cursor, limit })
+    }
+}
+
+/// First word of `input` and the rest of it, which still starts with the separating whitespace.
+fn next_word(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return None;
+    }
+    Some(match input.find(char::is_whitespace) {
+        Some(end) => { input.split_at(end) }
+        None => { (input, "") }
+    })
+}
+
+fn words(input: &str) -> Vec<&str> {
+    input.split_whitespace().collect()
+}
@@ -1,3 +1,5 @@
 pub mod cli;
+pub mod command;
 
 pub(crate) use cli::Args;
+pub(crate) use command::Command;
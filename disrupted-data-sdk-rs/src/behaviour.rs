@@ -1,7 +1,9 @@
 use std::iter;
 use std::time::Duration;
-use libp2p::{ping, request_response, StreamProtocol};
+use libp2p::{kad, ping, request_response, PeerId, StreamProtocol};
+use libp2p::kad::store::MemoryStore;
 use libp2p::request_response::{json, ProtocolSupport};
+use libp2p::swarm::behaviour::toggle::Toggle;
 use libp2p::swarm::NetworkBehaviour;
 
 use crate::{ActionResult, Actions};
@@ -12,7 +14,9 @@ type RequestResponseEvent = request_response::Event<Actions, ActionResult>;
 #[behaviour(to_swarm = "Event")]
 pub struct UserNodeBehaviour {
     pub request_response: json::Behaviour<Actions, ActionResult>,
-    pub ping: ping::Behaviour
+    pub ping: ping::Behaviour,
+    /// Client mode Kademlia, only used to find more nodes.
+    pub kad: Toggle<kad::Behaviour<MemoryStore>>,
 }
 
 impl UserNodeBehaviour {
@@ -24,18 +28,30 @@ impl UserNodeBehaviour {
 
         Self {
             request_response: request_response_behaviour,
-            ping: ping_behaviour
+            ping: ping_behaviour,
+            kad: Toggle::from(None),
         }
 
 
     }
+
+    /// Also runs Kademlia in client mode, so the nodes of the network can be found through the connected node.
+    pub fn with_node_discovery(peer_id: PeerId) -> Self {
+        let mut kad = kad::Behaviour::with_config(peer_id, MemoryStore::new(peer_id), kad::Config::new(kad::PROTOCOL_NAME));
+        kad.set_mode(Some(kad::Mode::Client));
+
+        Self {
+            kad: Toggle::from(Some(kad)),
+            ..Self::new()
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Event {
     RequestResponse(RequestResponseEvent),
     Ping(ping::Event),
-
+    Kademlia(kad::Event),
 }
 
 
@@ -49,4 +65,10 @@ impl From<ping::Event> for Event {
     fn from(event: ping::Event) -> Self {
         Event::Ping(event)
     }
-}
\ No newline at end of file
+}
+
+impl From<kad::Event> for Event {
+    fn from(event: kad::Event) -> Self {
+        Event::Kademlia(event)
+    }
+}
@@ -1,88 +1,747 @@
-use futures::StreamExt;
+use futures::{stream, StreamExt};
 use libp2p::identity::Keypair;
-use libp2p::request_response::{Message, OutboundRequestId};
+use libp2p::request_response::{Message, OutboundFailure, OutboundRequestId};
 use libp2p::swarm::SwarmEvent;
-use libp2p::{request_response, PeerId, Swarm};
-use std::collections::HashMap;
+use libp2p::{request_response, PeerId};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::Duration;
 use tokio::select;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
+use tokio::time::{sleep_until, Instant};
 
-use crate::behaviour::UserNodeBehaviour;
-use crate::connection::Connection;
-use crate::types::error::DisruptedDataError;
-use crate::{behaviour, ActionResult, Actions};
+use crate::connection;
+use crate::connection::{Connection, NodeTransport};
+use crate::types::error::{DisruptedDataError, ErrorKind};
+use crate::types::merkle::{MerkleProof, MerkleTree, MERKLE_DEPTH};
+use crate::types::encryption::{EncryptedContent, EncryptedValue};
+use crate::types::value::{ChunkManifest, StoredValue, CHUNK_KEY_PREFIX, CHUNK_SIZE};
+use crate::types::actions::{GetPublicRequest, GrantRequest, GrantScope, PutCondition, Visibility};
+use crate::{behaviour, current_timestamp_millis, ActionResult, Actions, DeleteRequest, GetRequest, HistoryRequest, ListRequest, ListResult, ProofRequest, PutRequest, RecordVersion, SubtreeRequest};
+
+/// Number of chunk requests of a large value that are in flight at the same time.
+const MAX_PARALLEL_CHUNK_REQUESTS: usize = 16;
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Times an action is sent before the client gives up on it. Actions are sent again, to the next node if need
+/// be, when the connection fails before the node answered, see [`retry_action`].
+const MAX_ACTION_ATTEMPTS: u32 = 3;
+
+/// How the client reaches its nodes.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+	pub transport: NodeTransport,
+	/// How long an action waits for the connection to a node before it fails with [`ErrorKind::Timeout`].
+	pub connect_timeout: Duration,
+	/// Look up more nodes through the connected node with client mode Kademlia, to fail over to when the entry
+	/// nodes cannot be reached.
+	pub discover_nodes: bool,
+}
+
+impl Default for ClientOptions {
+	fn default() -> Self {
+		ClientOptions {
+			transport: NodeTransport::Tcp,
+			connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+			discover_nodes: false,
+		}
+	}
+}
+
+/// An action the caller is waiting on.
+struct PendingAction {
+	action: Actions,
+	response_sender: oneshot::Sender<ActionResult>,
+	attempts: u32,
+}
 
 pub struct Client {
+	user_keypair: Keypair,
 	user_command_sender: Sender<(Actions, oneshot::Sender<ActionResult>)>,
 }
 
 
 impl Client {
 	pub fn new(user_keypair: &Keypair, node_ip: String, node_port: String) -> Result<Self, DisruptedDataError> {
+		Self::new_with_transport(user_keypair, node_ip, node_port, NodeTransport::Tcp)
+	}
+
+	/// Connects to the node over the given transport. With [`NodeTransport::Quic`] the client falls back to
+	/// TCP on `node_port` when the node cannot be reached over QUIC.
+	pub fn new_with_transport(user_keypair: &Keypair, node_ip: String, node_port: String, node_transport: NodeTransport) -> Result<Self, DisruptedDataError> {
+		Self::new_with_options(user_keypair, vec![(node_ip, node_port)], ClientOptions { transport: node_transport, ..ClientOptions::default() })
+	}
+
+	/// Starts dialing the first of `entry_nodes`, given as IP and TCP port, and returns right away. When a node
+	/// cannot be reached or its connection drops, the client moves on to the next one and backs off after every
+	/// round. Actions sent while no node is connected wait for up to [`ClientOptions::connect_timeout`].
+	pub fn new_with_options(user_keypair: &Keypair, entry_nodes: Vec<(String, String)>, options: ClientOptions) -> Result<Self, DisruptedDataError> {
 		let (tx, rx) = channel(400);
 
-		let swarm = Connection::connect_swarm(user_keypair, node_ip.clone(), node_port.clone())?;
-		tokio::spawn(async move { Self::listen_for_user_input(swarm, rx).await });
+		let connection = Connection::new(user_keypair, entry_nodes, options.transport, options.discover_nodes)?;
+		let keypair = user_keypair.clone();
+		tokio::spawn(async move { Self::listen_for_user_input(connection, keypair, options.connect_timeout, rx).await });
 
 		Ok(Self {
+			user_keypair: user_keypair.clone(),
 			user_command_sender: tx
 		})
 	}
 
-	pub async fn process_action(&mut self, put_action: Actions) -> Result<ActionResult, DisruptedDataError> {
+	/// Finds a node on the local network over mDNS, for development clusters without a known node address.
+	/// Returns the node's IP and TCP port.
+	pub async fn discover_local_node(timeout: Duration) -> Result<(String, String), DisruptedDataError> {
+		connection::discover_local_node(timeout).await
+	}
+
+	/// Stores `value` under `key` and returns the version it is stored at.
+	pub async fn put(&mut self, key: &str, value: Vec<u8>) -> Result<u64, DisruptedDataError> {
+		let put_request = PutRequest::new(&self.user_keypair, key.to_string(), value);
+		self.put_with(put_request).await
+	}
+
+	/// Stores `value` under `key`, encrypted so that only the user and the readers it is shared with can read it.
+	pub async fn put_encrypted(&mut self, key: &str, value: Vec<u8>) -> Result<u64, DisruptedDataError> {
+		let put_request = PutRequest::new(&self.user_keypair, key.to_string(), value).with_encryption();
+		self.put_with(put_request).await
+	}
+
+	/// Stores `value` under `key` until `ttl` has passed, e.g. for a session token, and returns the version it is
+	/// stored at. The key is then dropped from the user's index.
+	pub async fn put_expiring(&mut self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<u64, DisruptedDataError> {
+		let put_request = PutRequest::new(&self.user_keypair, key.to_string(), value)
+			.with_ttl(&self.user_keypair, Some(ttl.as_secs().max(1)));
+		self.put_with(put_request).await
+	}
+
+	/// Stores `value` under `key` if the record is still at `version`, and returns the version it is stored at.
+	/// Fails with [`ErrorKind::VersionMismatch`], which carries the current version, when another write got there
+	/// first.
+	pub async fn put_if_version(&mut self, key: &str, value: Vec<u8>, version: u64) -> Result<u64, DisruptedDataError> {
+		let put_request = PutRequest::new(&self.user_keypair, key.to_string(), value).if_version(&self.user_keypair, version);
+		self.put_with(put_request).await
+	}
+
+	/// Stores `value` under `key` if the key holds no value, and returns the version it is stored at.
+	pub async fn put_if_absent(&mut self, key: &str, value: Vec<u8>) -> Result<u64, DisruptedDataError> {
+		let put_request = PutRequest::new(&self.user_keypair, key.to_string(), value).if_absent(&self.user_keypair);
+		self.put_with(put_request).await
+	}
+
+	/// Publishes `value` under `key`. Anyone can read it with [`Client::get_public`].
+	pub async fn put_public(&mut self, key: &str, value: Vec<u8>) -> Result<u64, DisruptedDataError> {
+		let put_request = PutRequest::new_with_visibility(&self.user_keypair, key.to_string(), value, Visibility::Public);
+		self.put_with(put_request).await
+	}
+
+	/// Stores a put built with the [`PutRequest`] options, e.g. an encrypted put with a condition, and returns
+	/// the version it is stored at.
+	pub async fn put_with(&mut self, put_request: PutRequest) -> Result<u64, DisruptedDataError> {
+		expect_stored(self.process_action(Actions::Put(put_request)).await?)
+	}
+
+	pub async fn get(&mut self, key: &str) -> Result<Vec<u8>, DisruptedDataError> {
+		let get_request = GetRequest::new(&self.user_keypair, key.to_string());
+		expect_value(self.process_action(Actions::Get(get_request)).await?)
+	}
+
+	/// Reads `key` as it was stored at `version`, e.g. to recover the value a bad write replaced. Fails with
+	/// [`ErrorKind::NotFound`] once the nodes no longer retain the version.
+	pub async fn get_version(&mut self, key: &str, version: u64) -> Result<Vec<u8>, DisruptedDataError> {
+		self.get_with(GetRequest::new(&self.user_keypair, key.to_string()).at_version(&self.user_keypair, version)).await
+	}
+
+	/// Reads the value `key` held at `timestamp`, in milliseconds since the Unix epoch.
+	pub async fn get_at(&mut self, key: &str, timestamp: u64) -> Result<Vec<u8>, DisruptedDataError> {
+		self.get_with(GetRequest::new(&self.user_keypair, key.to_string()).at_time(&self.user_keypair, timestamp)).await
+	}
+
+	/// Reads a record with the [`GetRequest`] options, e.g. an older version of a shared record.
+	pub async fn get_with(&mut self, get_request: GetRequest) -> Result<Vec<u8>, DisruptedDataError> {
+		expect_value(self.process_action(Actions::Get(get_request)).await?)
+	}
+
+	/// Lists the versions of `key` the nodes retain, newest first.
+	pub async fn history(&mut self, key: &str) -> Result<Vec<RecordVersion>, DisruptedDataError> {
+		let history_request = HistoryRequest::new(&self.user_keypair, key.to_string());
+		match self.process_action(Actions::History(history_request)).await? {
+			ActionResult::History(record_versions) => { Ok(record_versions) }
+			action_result => { Err(unexpected_result(action_result)) }
+		}
+	}
+
+	/// Reads `key` of the user with the hex encoded `owner_public_key`, who granted this user access to it.
+	pub async fn get_shared(&mut self, owner_public_key: &str, key: &str) -> Result<Vec<u8>, DisruptedDataError> {
+		let get_request = GetRequest::shared(&self.user_keypair, owner_public_key.as_bytes().to_vec(), key.to_string());
+		expect_value(self.process_action(Actions::Get(get_request)).await?)
+	}
+
+	/// Reads a record the user with the hex encoded `owner_public_key` published, after checking its signature.
+	pub async fn get_public(&mut self, owner_public_key: &str, key: &str) -> Result<Vec<u8>, DisruptedDataError> {
+		let get_public_request = GetPublicRequest::new(owner_public_key.as_bytes().to_vec(), key.to_string());
+		expect_value(self.process_action(Actions::GetPublic(get_public_request)).await?)
+	}
+
+	pub async fn delete(&mut self, key: &str) -> Result<(), DisruptedDataError> {
+		let delete_request = DeleteRequest::new(&self.user_keypair, key.to_string());
+		expect_success(self.process_action(Actions::Delete(delete_request)).await?)
+	}
+
+	/// Lists the user's keys starting with `prefix`, one page at a time. Pass the `next_cursor` of a page as the
+	/// `cursor` of the next call.
+	pub async fn list(&mut self, prefix: Option<&str>, cursor: Option<&str>, limit: Option<u32>) -> Result<ListResult, DisruptedDataError> {
+		let list_request = ListRequest::new(&self.user_keypair, prefix.map(str::to_string), cursor.map(str::to_string), limit);
+		match self.process_action(Actions::List(list_request)).await? {
+			ActionResult::List(list_result) => { Ok(list_result) }
+			action_result => { Err(unexpected_result(action_result)) }
+		}
+	}
+
+	/// Fetches the inclusion proof of `key` in the user's Merkle tree, checked against the stored value.
+	///
+	/// The proof is only checked against the root the node sent along with it, so a node can make up a proof
+	/// that is consistent with itself. Use [`Client::prove_against`] to check it against a root you trust.
+	pub async fn prove(&mut self, key: &str) -> Result<MerkleProof, DisruptedDataError> {
+		let proof_request = ProofRequest::new(&self.user_keypair, key.to_string());
+		match self.process_action(Actions::Proof(proof_request)).await? {
+			ActionResult::Proof(merkle_proof) => { Ok(merkle_proof) }
+			action_result => { Err(unexpected_result(action_result)) }
+		}
+	}
+
+	/// Like [`Client::prove`], but also fails unless the proof leads to `trusted_root`, a hex encoded root the
+	/// caller pinned itself, e.g. the [`MerkleTree::root`] of the keys and value hashes it wrote.
+	pub async fn prove_against(&mut self, key: &str, trusted_root: &str) -> Result<MerkleProof, DisruptedDataError> {
+		let merkle_proof = self.prove(key).await?;
+		if !merkle_proof.verify_against(trusted_root) {
+			return Err(DisruptedDataError::new(ErrorKind::Integrity, "Merkle proof does not lead to the trusted root"));
+		}
+		Ok(merkle_proof)
+	}
+
+	/// Lets the user with the hex encoded `reader_public_key` read the keys in `scope`.
+	pub async fn grant(&mut self, reader_public_key: &str, scope: GrantScope) -> Result<(), DisruptedDataError> {
+		let grant_request = GrantRequest::grant(&self.user_keypair, reader_public_key.as_bytes().to_vec(), scope);
+		expect_success(self.process_action(Actions::Grant(grant_request)).await?)
+	}
+
+	/// Withdraws a grant made with [`Client::grant`] for the same scope.
+	pub async fn revoke(&mut self, reader_public_key: &str, scope: GrantScope) -> Result<(), DisruptedDataError> {
+		let grant_request = GrantRequest::revoke(&self.user_keypair, reader_public_key.as_bytes().to_vec(), scope);
+		expect_success(self.process_action(Actions::Grant(grant_request)).await?)
+	}
+
+	/// Sends the action to the connected node. Values larger than [`CHUNK_SIZE`] are transparently split into
+	/// chunks on PUT and reassembled on GET. Values are encrypted on PUT when the request asks for it and
+	/// decrypted on GET. Public records are checked against their owner's signature before they are returned.
+	/// Granting access to encrypted values also wraps their content keys for the reader.
+	pub async fn process_action(&mut self, action: Actions) -> Result<ActionResult, DisruptedDataError> {
+		match action {
+			Actions::Put(put_request) => { self.put_value(put_request).await }
+			Actions::Get(get_request) => { self.get_value(get_request).await }
+			Actions::Delete(delete_request) => { self.delete_value(delete_request).await }
+			Actions::List(list_request) => {
+				let action_result = self.send_action(Actions::List(list_request)).await?;
+				Ok(hide_chunk_keys(action_result))
+			}
+			Actions::Proof(proof_request) => { self.prove_value(proof_request).await }
+			Actions::Grant(grant_request) => { self.grant_access(grant_request).await }
+			Actions::GetPublic(get_public_request) => { self.get_public_value(get_public_request).await }
+			action => { self.send_action(action).await }
+		}
+	}
+
+	/// Fetches the inclusion proof of a record together with its stored value, and fails if either the proof
+	/// or the value does not check out.
+	async fn prove_value(&self, proof_request: ProofRequest) -> Result<ActionResult, DisruptedDataError> {
+		let get_request = GetRequest::new(&self.user_keypair, proof_request.record_key.clone());
+		let merkle_proof = match self.send_action(Actions::Proof(proof_request)).await? {
+			ActionResult::Proof(merkle_proof) => { merkle_proof }
+			action_result => { return Ok(action_result) }
+		};
+		if !merkle_proof.verify() {
+			return Ok(ActionResult::failure(ErrorKind::Integrity, "Merkle proof does not match its root"));
+		}
+
+		// The tree hashes the value as stored, so the value is compared before chunked values are reassembled.
+		match self.send_action(Actions::Get(get_request)).await? {
+			ActionResult::Value(stored_value) if merkle_proof.matches_value(&stored_value) => {
+				Ok(ActionResult::Proof(merkle_proof))
+			}
+			ActionResult::Value(_) => {
+				Ok(ActionResult::failure(ErrorKind::Integrity, "Stored value does not match the Merkle proof"))
+			}
+			action_result => { Ok(action_result) }
+		}
+	}
+
+	/// Compares `local_entries`, record keys mapped to the [`merkle::value_hash`](crate::merkle::value_hash)
+	/// of their stored value, against the user's tree on the node and returns the keys that differ.
+	///
+	/// Only subtrees whose hashes differ are fetched, one level at a time.
+	pub async fn diff_index(&self, local_entries: &BTreeMap<String, String>) -> Result<Vec<String>, DisruptedDataError> {
+		let local_tree = MerkleTree::new(local_entries);
+		let mut differing_keys = Vec::new();
+		let mut positions = vec![0u32];
+
+		for depth in 0..=MERKLE_DEPTH {
+			if positions.is_empty() {
+				break;
+			}
+			let subtree_results: Vec<Result<ActionResult, DisruptedDataError>> = stream::iter(positions.clone())
+				.map(|index| self.send_action(Actions::Subtree(SubtreeRequest::new(&self.user_keypair, depth, index))))
+				.buffered(MAX_PARALLEL_CHUNK_REQUESTS)
+				.collect()
+				.await;
+
+			let mut next_positions = Vec::new();
+			for (index, subtree_result) in positions.iter().zip(subtree_results) {
+				let remote_subtree = match subtree_result? {
+					ActionResult::Subtree(remote_subtree) => { remote_subtree }
+					action_result => {
+						return Err(failure_with_context(action_result, format!("Could not get subtree {} at depth {}", index, depth)));
+					}
+				};
+				if local_tree.node(depth, *index).as_ref() == Some(&remote_subtree.hash) {
+					continue;
+				}
+
+				if depth == MERKLE_DEPTH {
+					let local_entries: HashSet<&(String, String)> = local_tree.bucket(*index).iter().collect();
+					let remote_entries: HashSet<&(String, String)> = remote_subtree.entries.iter().collect();
+					differing_keys.extend(local_entries.symmetric_difference(&remote_entries).map(|(record_key, _)| record_key.clone()));
+				} else {
+					for (child, remote_child_hash) in remote_subtree.children.iter().enumerate() {
+						let child_index = index * 2 + child as u32;
+						if local_tree.node(depth + 1, child_index).as_ref() != Some(remote_child_hash) {
+							next_positions.push(child_index);
+						}
+					}
+				}
+			}
+			positions = next_positions;
+		}
+
+		differing_keys.sort();
+		differing_keys.dedup();
+		Ok(differing_keys)
+	}
+
+	/// The chunks of a public value are public as well, so readers without a grant can reassemble it, and the
+	/// chunks of an expiring value expire with it. The condition only applies to the record under its key.
+	///
+	/// Chunks of the value the put replaces are removed once the new value is stored, see [`Client::replace_value`].
+	async fn put_value(&self, put_request: PutRequest) -> Result<ActionResult, DisruptedDataError> {
+		let (visibility, condition, ttl) = (put_request.visibility, put_request.condition, put_request.ttl);
+		if put_request.encrypt {
+			if visibility == Visibility::Public {
+				return Ok(ActionResult::failure(ErrorKind::InvalidRequest, "Encrypted values cannot be public"));
+			}
+			return self.put_encrypted_value(put_request).await;
+		}
+		if put_request.record_value.len() <= CHUNK_SIZE {
+			let stored_value = StoredValue::Inline(put_request.record_value.clone()).to_bytes();
+			if stored_value == put_request.record_value {
+				return self.replace_value(put_request, &[]).await;
+			}
+			let escaped_put_request = PutRequest::new_with_visibility(&self.user_keypair, put_request.record_key, stored_value, visibility)
+				.with_condition(&self.user_keypair, condition)
+				.with_ttl(&self.user_keypair, ttl);
+			return self.replace_value(escaped_put_request, &[]).await;
+		}
+
+		let (manifest, chunks) = ChunkManifest::split(&put_request.record_key, &put_request.record_value);
+		let chunk_keys = manifest.chunk_keys(&put_request.record_key);
+		if let Some(failure) = self.put_chunks(chunks, visibility, ttl).await? {
+			return Ok(failure);
+		}
+
+		let manifest_put_request = PutRequest::new_with_visibility(&self.user_keypair, put_request.record_key, StoredValue::Chunked(manifest).to_bytes(), visibility)
+			.with_condition(&self.user_keypair, condition)
+			.with_ttl(&self.user_keypair, ttl);
+		self.replace_value(manifest_put_request, &chunk_keys).await
+	}
+
+	/// Stores the record of a value whose chunks, if any, are `chunk_keys`. Once it is stored, the chunks of the
+	/// value it replaced that the new value does not use are removed. Removing them is best effort, a chunk that
+	/// could not be removed is left behind without failing the put.
+	async fn replace_value(&self, put_request: PutRequest, chunk_keys: &[String]) -> Result<ActionResult, DisruptedDataError> {
+		let replaced_chunk_keys = self.stored_chunk_keys(&put_request.record_key).await?;
+		let action_result = self.send_action(Actions::Put(put_request)).await?;
+		if let ActionResult::Stored { .. } = action_result {
+			self.remove_chunks(replaced_chunk_keys.into_iter().filter(|chunk_key| !chunk_keys.contains(chunk_key)).collect()).await;
+		}
+		Ok(action_result)
+	}
+
+	/// Deletes the record, then the chunks of the value it held.
+	async fn delete_value(&self, delete_request: DeleteRequest) -> Result<ActionResult, DisruptedDataError> {
+		let replaced_chunk_keys = self.stored_chunk_keys(&delete_request.record_key).await?;
+		let action_result = self.send_action(Actions::Delete(delete_request)).await?;
+		if let ActionResult::Deleted = action_result {
+			self.remove_chunks(replaced_chunk_keys).await;
+		}
+		Ok(action_result)
+	}
+
+	/// The chunk keys of the user's stored value of `record_key`, empty unless it holds a large value.
+	async fn stored_chunk_keys(&self, record_key: &str) -> Result<Vec<String>, DisruptedDataError> {
+		let manifest = match self.send_action(Actions::Get(GetRequest::new(&self.user_keypair, record_key.to_string()))).await? {
+			ActionResult::Value(value) => {
+				match StoredValue::from_bytes(&value) {
+					StoredValue::Chunked(manifest) => { Some(manifest) }
+					StoredValue::Encrypted(EncryptedValue { content: EncryptedContent::Chunked(manifest), .. }) => { Some(manifest) }
+					StoredValue::Inline(_) | StoredValue::Encrypted(_) => { None }
+				}
+			}
+			_ => { None }
+		};
+		Ok(manifest.map_or_else(Vec::new, |manifest| manifest.chunk_keys(record_key)))
+	}
+
+	async fn remove_chunks(&self, mut chunk_keys: Vec<String>) {
+		chunk_keys.sort();
+		chunk_keys.dedup();
+		let _: Vec<Result<ActionResult, DisruptedDataError>> = stream::iter(chunk_keys)
+			.map(|chunk_key| self.send_action(Actions::Delete(DeleteRequest::new(&self.user_keypair, chunk_key))))
+			.buffered(MAX_PARALLEL_CHUNK_REQUESTS)
+			.collect()
+			.await;
+	}
+
+	/// Encrypts the value before it is stored. Readers the previous value of the key was shared with keep
+	/// access to the new value.
+	async fn put_encrypted_value(&self, put_request: PutRequest) -> Result<ActionResult, DisruptedDataError> {
+		let readers = match self.get_encrypted_envelope(&put_request.record_key).await? {
+			Some(encrypted_value) => { encrypted_value.readers(&self.user_keypair)? }
+			None => { vec![] }
+		};
+		self.seal_value(put_request.record_key, &put_request.record_value, &readers, Visibility::Private, put_request.condition, put_request.ttl).await
+	}
+
+	/// Stores `plaintext` encrypted under a fresh content key, wrapped for the owner and `readers`. A ciphertext
+	/// too large for one record is chunked, so nodes only ever see encrypted chunks.
+	async fn seal_value(&self, record_key: String, plaintext: &[u8], readers: &[Vec<u8>], visibility: Visibility, condition: Option<PutCondition>, ttl: Option<u64>) -> Result<ActionResult, DisruptedDataError> {
+		let mut encrypted_value = EncryptedValue::seal(&self.user_keypair, &record_key, plaintext)?;
+		for reader_public_key in readers {
+			encrypted_value.add_reader(&self.user_keypair, reader_public_key, &record_key)?;
+		}
+		if StoredValue::Encrypted(encrypted_value.clone()).to_bytes().len() > CHUNK_SIZE {
+			if let EncryptedContent::Inline(ciphertext) = &encrypted_value.content {
+				let (manifest, chunks) = ChunkManifest::split(&record_key, ciphertext);
+				if let Some(failure) = self.put_chunks(chunks, visibility, ttl).await? {
+					return Ok(failure);
+				}
+				encrypted_value.content = EncryptedContent::Chunked(manifest);
+			}
+		}
+		let chunk_keys = match &encrypted_value.content {
+			EncryptedContent::Chunked(manifest) => { manifest.chunk_keys(&record_key) }
+			EncryptedContent::Inline(_) => { vec![] }
+		};
+
+		let encrypted_put_request = PutRequest::new_with_visibility(&self.user_keypair, record_key, StoredValue::Encrypted(encrypted_value).to_bytes(), visibility)
+			.with_condition(&self.user_keypair, condition)
+			.with_ttl(&self.user_keypair, ttl);
+		self.replace_value(encrypted_put_request, &chunk_keys).await
+	}
+
+	/// The user's own stored [`EncryptedValue`] for `record_key`, if the key holds one.
+	async fn get_encrypted_envelope(&self, record_key: &str) -> Result<Option<EncryptedValue>, DisruptedDataError> {
+		match self.send_action(Actions::Get(GetRequest::new(&self.user_keypair, record_key.to_string()))).await? {
+			ActionResult::Value(value) => {
+				match StoredValue::from_bytes(&value) {
+					StoredValue::Encrypted(encrypted_value) => { Ok(Some(encrypted_value)) }
+					_ => { Ok(None) }
+				}
+			}
+			_ => { Ok(None) }
+		}
+	}
+
+	/// Stores the grant on the node, then updates the encrypted values in its scope. A grant wraps their content
+	/// keys for the reader. A revoke re-encrypts them under a new content key that the reader never saw. The values
+	/// are stored again with the visibility they had and expire when they would have.
+	async fn grant_access(&self, grant_request: GrantRequest) -> Result<ActionResult, DisruptedDataError> {
+		let (reader_public_key, scope, revoke) = (grant_request.reader_public_key.clone(), grant_request.scope.clone(), grant_request.revoke);
+		let action_result = self.send_action(Actions::Grant(grant_request)).await?;
+		if let ActionResult::Failure(_) = action_result {
+			return Ok(action_result);
+		}
+
+		for record_key in self.keys_in_scope(&scope).await? {
+			let mut encrypted_value = match self.get_encrypted_envelope(&record_key).await? {
+				Some(encrypted_value) => { encrypted_value }
+				None => { continue }
+			};
+			let (visibility, ttl) = match self.current_record_version(&record_key).await? {
+				Some(record_version) => { (record_version.visibility, record_version.remaining_ttl(current_timestamp_millis())) }
+				None => { (Visibility::Private, None) }
+			};
+			if ttl == Some(0) {
+				continue;
+			}
+			let update_result = if revoke {
+				let own_public_key = self.own_public_key()?;
+				match self.get_encrypted_value(encrypted_value.clone(), &own_public_key, &record_key).await? {
+					ActionResult::Value(plaintext) => {
+						let readers: Vec<Vec<u8>> = encrypted_value.readers(&self.user_keypair)?.into_iter()
+							.filter(|reader| *reader != reader_public_key)
+							.collect();
+						self.seal_value(record_key.clone(), &plaintext, &readers, visibility, None, ttl).await?
+					}
+					action_result => { action_result }
+				}
+			} else {
+				encrypted_value.add_reader(&self.user_keypair, &reader_public_key, &record_key)?;
+				let put_request = PutRequest::new_with_visibility(&self.user_keypair, record_key.clone(), StoredValue::Encrypted(encrypted_value).to_bytes(), visibility)
+					.with_ttl(&self.user_keypair, ttl);
+				self.send_action(Actions::Put(put_request)).await?
+			};
+			if let ActionResult::Failure(_) = update_result {
+				return Ok(ActionResult::Failure(failure_with_context(update_result, format!("Could not update the encrypted value of {}", record_key))));
+			}
+		}
+		Ok(action_result)
+	}
+
+	/// The current version of the user's record under `record_key`, as listed by its history.
+	async fn current_record_version(&self, record_key: &str) -> Result<Option<RecordVersion>, DisruptedDataError> {
+		match self.send_action(Actions::History(HistoryRequest::new(&self.user_keypair, record_key.to_string()))).await? {
+			ActionResult::History(record_versions) => { Ok(record_versions.into_iter().next()) }
+			_ => { Ok(None) }
+		}
+	}
+
+	/// The user's keys a grant applies to, without chunk keys.
+	async fn keys_in_scope(&self, scope: &GrantScope) -> Result<Vec<String>, DisruptedDataError> {
+		let prefix = match scope {
+			GrantScope::Key(record_key) => { return Ok(vec![record_key.clone()]) }
+			GrantScope::Prefix(prefix) => { prefix.clone() }
+		};
+
+		let mut keys = Vec::new();
+		let mut cursor = None;
+		loop {
+			let list_request = ListRequest::new(&self.user_keypair, Some(prefix.clone()), cursor, None);
+			match hide_chunk_keys(self.send_action(Actions::List(list_request)).await?) {
+				ActionResult::List(list_result) => {
+					keys.extend(list_result.keys);
+					match list_result.next_cursor {
+						Some(next_cursor) => { cursor = Some(next_cursor) }
+						None => { return Ok(keys) }
+					}
+				}
+				action_result => {
+					return Err(failure_with_context(action_result, format!("Could not list keys with prefix {}", prefix)));
+				}
+			}
+		}
+	}
+
+	fn own_public_key(&self) -> Result<Vec<u8>, DisruptedDataError> {
+		let secp256k1_key_pair = self.user_keypair.clone().try_into_secp256k1()
+			.map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Encryption needs a secp256k1 key".to_string()))?;
+		Ok(hex::encode(secp256k1_key_pair.public().to_bytes()).into_bytes())
+	}
+
+	/// Stores the chunks of a large value, returning the failure of the first chunk that could not be stored.
+	/// Nodes keep chunks out of the user's key index, so they are stored in parallel. Chunks expire after `ttl`, like
+	/// the value they belong to.
+	async fn put_chunks(&self, chunks: Vec<(String, Vec<u8>)>, visibility: Visibility, ttl: Option<u64>) -> Result<Option<ActionResult>, DisruptedDataError> {
+		let mut stored_chunk_keys = HashSet::new();
+		let chunk_put_requests: Vec<PutRequest> = chunks.into_iter()
+			.filter(|(chunk_key, _)| stored_chunk_keys.insert(chunk_key.clone()))
+			.map(|(chunk_key, chunk)| PutRequest::new_with_visibility(&self.user_keypair, chunk_key, chunk, visibility).with_ttl(&self.user_keypair, ttl))
+			.collect();
+		let chunk_keys: Vec<String> = chunk_put_requests.iter().map(|chunk_put_request| chunk_put_request.record_key.clone()).collect();
+		let chunk_put_results: Vec<Result<ActionResult, DisruptedDataError>> = stream::iter(chunk_put_requests)
+			.map(|chunk_put_request| self.send_action(Actions::Put(chunk_put_request)))
+			.buffered(MAX_PARALLEL_CHUNK_REQUESTS)
+			.collect()
+			.await;
+
+		for (chunk_key, chunk_put_result) in chunk_keys.iter().zip(chunk_put_results) {
+			let chunk_put_result = chunk_put_result?;
+			if let ActionResult::Failure(_) = chunk_put_result {
+				return Ok(Some(ActionResult::Failure(failure_with_context(chunk_put_result, format!("Could not store chunk {}", chunk_key)))));
+			}
+		}
+		Ok(None)
+	}
+
+	async fn get_value(&self, get_request: GetRequest) -> Result<ActionResult, DisruptedDataError> {
+		let (owner_public_key, record_key) = (get_request.get_owner_public_key().clone(), get_request.record_key.clone());
+		match self.send_action(Actions::Get(get_request)).await? {
+			ActionResult::Value(value) => {
+				match StoredValue::from_bytes(&value) {
+					StoredValue::Inline(value) => { Ok(ActionResult::Value(value)) }
+					StoredValue::Chunked(manifest) => { self.get_chunked_value(manifest, &owner_public_key, &record_key).await }
+					StoredValue::Encrypted(encrypted_value) => { self.get_encrypted_value(encrypted_value, &owner_public_key, &record_key).await }
+				}
+			}
+			action_result => { Ok(action_result) }
+		}
+	}
+
+	async fn get_encrypted_value(&self, encrypted_value: EncryptedValue, owner_public_key: &[u8], record_key: &str) -> Result<ActionResult, DisruptedDataError> {
+		let ciphertext = match &encrypted_value.content {
+			EncryptedContent::Inline(ciphertext) => { ciphertext.clone() }
+			EncryptedContent::Chunked(manifest) => {
+				match self.get_chunked_value(manifest.clone(), owner_public_key, record_key).await? {
+					ActionResult::Value(ciphertext) => { ciphertext }
+					action_result => { return Ok(action_result) }
+				}
+			}
+		};
+
+		match encrypted_value.open(&self.user_keypair, owner_public_key, record_key, &ciphertext) {
+			Ok(value) => { Ok(ActionResult::Value(value)) }
+			Err(error) => { Ok(ActionResult::Failure(error)) }
+		}
+	}
+
+	/// Chunks are stored under the keys of `owner_public_key`, who may have shared the value with this user. They
+	/// are not in the owner's index, so they are read as chunks of `record_key` and nodes check the grant for it.
+	async fn get_chunked_value(&self, manifest: ChunkManifest, owner_public_key: &[u8], record_key: &str) -> Result<ActionResult, DisruptedDataError> {
+		let chunk_keys = manifest.chunk_keys(record_key);
+		let chunk_results: Vec<Result<ActionResult, DisruptedDataError>> = stream::iter(chunk_keys.clone())
+			.map(|chunk_key| {
+				let get_request = GetRequest::shared(&self.user_keypair, owner_public_key.to_vec(), chunk_key).chunk_of(&self.user_keypair, record_key.to_string());
+				self.send_action(Actions::Get(get_request))
+			})
+			.buffered(MAX_PARALLEL_CHUNK_REQUESTS)
+			.collect()
+			.await;
+
+		let mut chunks = Vec::with_capacity(chunk_results.len());
+		for (chunk_key, chunk_result) in chunk_keys.iter().zip(chunk_results) {
+			match chunk_result? {
+				ActionResult::Value(chunk) => { chunks.push(chunk) }
+				action_result => {
+					return Ok(ActionResult::Failure(failure_with_context(action_result, format!("Could not get chunk {}", chunk_key))));
+				}
+			}
+		}
+
+		match manifest.join(chunks) {
+			Ok(value) => { Ok(ActionResult::Value(value)) }
+			Err(error) => { Ok(ActionResult::Failure(error)) }
+		}
+	}
+
+	/// Fetches a published record and checks that its owner signed it under the requested key. Chunks of a
+	/// large value are fetched the same way and checked against the signed manifest.
+	async fn get_public_value(&self, get_public_request: GetPublicRequest) -> Result<ActionResult, DisruptedDataError> {
+		let (owner_public_key, record_key) = (get_public_request.owner_public_key.clone(), get_public_request.record_key.clone());
+		let public_record = match self.send_action(Actions::GetPublic(get_public_request)).await? {
+			ActionResult::PublicValue(public_record) => { public_record }
+			action_result => { return Ok(action_result) }
+		};
+		if public_record.owner_public_key != owner_public_key || public_record.record_key != record_key || !public_record.verify() {
+			return Ok(ActionResult::failure(ErrorKind::InvalidSignature, "Public record is not signed by its owner"));
+		}
+
+		match StoredValue::from_bytes(&public_record.value) {
+			StoredValue::Inline(value) => { Ok(ActionResult::Value(value)) }
+			StoredValue::Chunked(manifest) => {
+				let chunk_keys = manifest.chunk_keys(&record_key);
+				let chunk_results: Vec<Result<ActionResult, DisruptedDataError>> = stream::iter(chunk_keys.clone())
+					.map(|chunk_key| self.send_action(Actions::GetPublic(GetPublicRequest::new(owner_public_key.clone(), chunk_key))))
+					.buffered(MAX_PARALLEL_CHUNK_REQUESTS)
+					.collect()
+					.await;
+
+				let mut chunks = Vec::with_capacity(chunk_results.len());
+				for (chunk_key, chunk_result) in chunk_keys.iter().zip(chunk_results) {
+					match chunk_result? {
+						ActionResult::PublicValue(public_chunk) => { chunks.push(public_chunk.value) }
+						action_result => {
+							return Ok(ActionResult::Failure(failure_with_context(action_result, format!("Could not get chunk {}", chunk_key))));
+						}
+					}
+				}
+				match manifest.join(chunks) {
+					Ok(value) => { Ok(ActionResult::Value(value)) }
+					Err(error) => { Ok(ActionResult::Failure(error)) }
+				}
+			}
+			StoredValue::Encrypted(_) => { Ok(ActionResult::failure(ErrorKind::Encryption, "Public record is encrypted")) }
+		}
+	}
+
+	async fn send_action(&self, action: Actions) -> Result<ActionResult, DisruptedDataError> {
 		let (user_command_response_sender, user_command_response_receiver) = oneshot::channel::<ActionResult>();
-		let send_result = self.user_command_sender.send((put_action, user_command_response_sender)).await;
+		let send_result = self.user_command_sender.send((action, user_command_response_sender)).await;
 
 		match send_result {
 			Ok(_) => {
-				Ok(user_command_response_receiver.await.unwrap())
-				// Ok(())
+				user_command_response_receiver.await
+					.map_err(|_| DisruptedDataError::new(ErrorKind::Connection, "The connection to the node was shut down"))
 			}
 			Err(error) => {
 				// println!("Error sending command: {}", error);
-				Err(DisruptedDataError { message: format!("Error sending command: {}", error) })
+				Err(DisruptedDataError::new(ErrorKind::Connection, format!("Error sending command: {}", error)))
 			}
 		}
 	}
 
-	async fn listen_for_user_input(mut swarm: Swarm<UserNodeBehaviour>, mut user_command_receiver: Receiver<(Actions, oneshot::Sender<ActionResult>)>) {
-		let mut connected_peer_id: Option<PeerId> = None;
-		let mut request_id_response_channel_map = HashMap::<OutboundRequestId, oneshot::Sender<ActionResult>>::new();
+	async fn listen_for_user_input(mut connection: Connection, user_keypair: Keypair, connect_timeout: Duration, mut user_command_receiver: Receiver<(Actions, oneshot::Sender<ActionResult>)>) {
+		let mut request_id_response_channel_map = HashMap::<OutboundRequestId, PendingAction>::new();
+		// Actions sent while no node is connected, oldest first, with the time they stop waiting.
+		let mut queued_actions = VecDeque::<(PendingAction, Instant)>::new();
 
 		loop {
+			let next_deadline = queued_actions.front().map(|(_, deadline)| *deadline);
+			let next_dial_time = connection.next_dial_time();
 			select! {
-                Some((action, user_command_response_sender)) = user_command_receiver.recv() => {
-                    // println!("user action: {:?} connected peer: {:?}", action, connected_peer_id);
-                     if connected_peer_id.is_some() {
-                         // println!("found connected peer...sending request");
-                        let request_id = swarm.behaviour_mut().request_response.send_request(&connected_peer_id.unwrap(), action.clone());
-                        request_id_response_channel_map.insert(request_id, user_command_response_sender);
-
-                    } else {
-                         // println!("could not find connected peer");
+                Some((action, response_sender)) = user_command_receiver.recv() => {
+                    let pending_action = PendingAction { action, response_sender, attempts: 0 };
+                    match connection.connected_node() {
+                        Some(peer_id) => { send_pending_action(&mut connection, peer_id, pending_action, &mut request_id_response_channel_map) }
+                        None => {
+                            queued_actions.push_back((pending_action, Instant::now() + connect_timeout));
+                            connection.ensure_connecting();
+                        }
+                    }
+                },
 
-                     }
+                _ = sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                    let now = Instant::now();
+                    while queued_actions.front().is_some_and(|(_, deadline)| *deadline <= now) {
+                        let (pending_action, _) = queued_actions.pop_front().unwrap();
+                        let message = match connection.last_error() {
+                            Some(last_error) => { format!("Could not connect to a node within {} seconds. {}", connect_timeout.as_secs(), last_error) }
+                            None => { format!("Could not connect to a node within {} seconds", connect_timeout.as_secs()) }
+                        };
+                        let _ = pending_action.response_sender.send(ActionResult::failure(ErrorKind::Timeout, message));
+                    }
+                },
 
+                _ = sleep_until(next_dial_time.unwrap_or_else(Instant::now)), if next_dial_time.is_some() => {
+                    connection.dial_next();
                 },
 
-                swarm_event = swarm.select_next_some() => {
+                swarm_event = connection.swarm().select_next_some() => {
+                    if let Some(peer_id) = connection.on_swarm_event(&swarm_event) {
+                        for (pending_action, _) in queued_actions.drain(..) {
+                            send_pending_action(&mut connection, peer_id, pending_action, &mut request_id_response_channel_map);
+                        }
+                    }
                     match swarm_event {
-                        SwarmEvent::ConnectionEstablished {peer_id, ..} => {
-                            connected_peer_id = Some(peer_id);
-                        },
                         SwarmEvent::Behaviour(behaviour::Event::RequestResponse(request_response::Event::Message {message, .. } ) ) => {
                             match message {
-                                Message::Request { request_id, request, .. } => {
+                                Message::Request { request_id: _, request: _, .. } => {
                                     // println!("Request received: {:?}", request);
                                 }
                                 Message::Response { request_id, response } => {
                                     // eprintln!("Received response from {:?}: {:?}", request_id, response);
                                     let response_channel_option = request_id_response_channel_map.remove(&request_id);
                                     match response_channel_option {
-                                        Some(response_channel) => {
-                                            // eprintln!("Sending response on chnanel: {:?} with outbound request id: {:?}", response, request_id);
-                                            response_channel.send(response).unwrap()
+                                        Some(pending_action) => {
+                                            // The caller may have stopped waiting for the response.
+                                            let _ = pending_action.response_sender.send(response);
                                         },
                                         None => {
                                             eprintln!("Could not find command response channel");
@@ -92,6 +751,19 @@ impl Client {
                                 }
                             }
                         },
+                        SwarmEvent::Behaviour(behaviour::Event::RequestResponse(request_response::Event::OutboundFailure {request_id, error, .. } ) ) => {
+                            let pending_action = request_id_response_channel_map.remove(&request_id)
+                                .and_then(|pending_action| retry_action(pending_action, error, &user_keypair));
+                            if let Some(pending_action) = pending_action {
+                                match connection.connected_node() {
+                                    Some(peer_id) => { send_pending_action(&mut connection, peer_id, pending_action, &mut request_id_response_channel_map) }
+                                    None => {
+                                        queued_actions.push_back((pending_action, Instant::now() + connect_timeout));
+                                        connection.ensure_connecting();
+                                    }
+                                }
+                            }
+                        },
                         _ => {
                         }
                     }
@@ -100,3 +772,95 @@ impl Client {
 		}
 	}
 }
+
+fn send_pending_action(connection: &mut Connection, peer_id: PeerId, mut pending_action: PendingAction, request_id_response_channel_map: &mut HashMap<OutboundRequestId, PendingAction>) {
+	pending_action.attempts += 1;
+	let request_id = connection.swarm().behaviour_mut().request_response.send_request(&peer_id, pending_action.action.clone());
+	request_id_response_channel_map.insert(request_id, pending_action);
+}
+
+/// The action to send again after its request failed, or `None` once the caller was told about the failure.
+///
+/// A request that could not be dialed never reached a node, so the same signed action is sent again, to the next
+/// node if need be. When the connection dropped after the request was sent, the node may already have served it.
+/// Reads are then signed again with a fresh nonce, since nodes reject a nonce they have seen, while writes fail
+/// with [`ErrorKind::OutcomeUnknown`] rather than risk being applied twice.
+fn retry_action(mut pending_action: PendingAction, error: OutboundFailure, user_keypair: &Keypair) -> Option<PendingAction> {
+	let has_attempts_left = pending_action.attempts < MAX_ACTION_ATTEMPTS;
+	let error = match error {
+		OutboundFailure::DialFailure if has_attempts_left => { return Some(pending_action) }
+		OutboundFailure::ConnectionClosed | OutboundFailure::Io(_) => {
+			match pending_action.action.clone().resign(user_keypair) {
+				Some(action) if has_attempts_left => {
+					pending_action.action = action;
+					return Some(pending_action);
+				}
+				Some(_) => { outbound_failure_error(error) }
+				None => {
+					DisruptedDataError::new(ErrorKind::OutcomeUnknown, format!("The connection closed before the node answered, the write may or may not have been applied: {}", error))
+				}
+			}
+		}
+		error => { outbound_failure_error(error) }
+	};
+	let _ = pending_action.response_sender.send(ActionResult::Failure(error));
+	None
+}
+
+/// The node never answered the request, because it timed out, the connection closed or the node does not speak
+/// the protocol.
+fn outbound_failure_error(error: OutboundFailure) -> DisruptedDataError {
+	let kind = match error {
+		OutboundFailure::Timeout => { ErrorKind::Timeout }
+		OutboundFailure::DialFailure | OutboundFailure::ConnectionClosed | OutboundFailure::UnsupportedProtocols | OutboundFailure::Io(_) => { ErrorKind::Connection }
+	};
+	DisruptedDataError::new(kind, format!("Request failed: {}", error))
+}
+
+fn expect_success(action_result: ActionResult) -> Result<(), DisruptedDataError> {
+	match action_result {
+		ActionResult::Deleted | ActionResult::Granted | ActionResult::Revoked => { Ok(()) }
+		action_result => { Err(unexpected_result(action_result)) }
+	}
+}
+
+fn expect_stored(action_result: ActionResult) -> Result<u64, DisruptedDataError> {
+	match action_result {
+		ActionResult::Stored { version } => { Ok(version) }
+		action_result => { Err(unexpected_result(action_result)) }
+	}
+}
+
+fn expect_value(action_result: ActionResult) -> Result<Vec<u8>, DisruptedDataError> {
+	match action_result {
+		ActionResult::Value(value) => { Ok(value) }
+		action_result => { Err(unexpected_result(action_result)) }
+	}
+}
+
+/// Failures carry the node's error. Any other result means the node answered with the wrong kind of result.
+fn unexpected_result(action_result: ActionResult) -> DisruptedDataError {
+	match action_result {
+		ActionResult::Failure(error) => { error }
+		action_result => { DisruptedDataError::new(ErrorKind::Internal, format!("Unexpected response: {:?}", action_result)) }
+	}
+}
+
+/// Prefixes the error of `action_result` with `context`, keeping its kind.
+fn failure_with_context(action_result: ActionResult, context: String) -> DisruptedDataError {
+	let error = unexpected_result(action_result);
+	DisruptedDataError::new(error.kind, format!("{}: {}", context, error.message))
+}
+
+/// Nodes keep chunks out of the user's index, but indexes written before they did may still list chunk keys.
+fn hide_chunk_keys(action_result: ActionResult) -> ActionResult {
+	match action_result {
+		ActionResult::List(list_result) => {
+			ActionResult::List(ListResult {
+				keys: list_result.keys.into_iter().filter(|key| !key.starts_with(CHUNK_KEY_PREFIX)).collect(),
+				next_cursor: list_result.next_cursor,
+			})
+		}
+		action_result => { action_result }
+	}
+}
@@ -1,44 +1,243 @@
 use std::time::Duration;
 
 use futures::StreamExt;
-use libp2p::{Multiaddr, noise, PeerId, Swarm, swarm, tcp, Transport, yamux};
+use libp2p::{kad, mdns, Multiaddr, noise, PeerId, quic, Swarm, swarm, tcp, Transport, yamux};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::dummy::DummyTransport;
 use libp2p::core::upgrade::Version;
 use libp2p::identity::Keypair;
-use libp2p::swarm::DialError;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionId, SwarmEvent};
+use tokio::time::Instant;
 
+use crate::behaviour;
 use crate::behaviour::UserNodeBehaviour;
-use crate::types::error::DisruptedDataError;
+use crate::types::error::{DisruptedDataError, ErrorKind};
 
+/// Transport used to reach the node.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum NodeTransport {
+    #[default]
+    Tcp,
+    /// Dial the node's QUIC listener on the given UDP port and fall back to TCP if that fails.
+    Quic { port: String },
+}
+
+/// Wait before dialing the nodes again after none of them could be reached. Doubles after every round, up to
+/// `MAX_RECONNECT_DELAY`.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+enum DialState {
+    /// Not connected. The next node is dialed once an action needs it.
+    Idle,
+    /// Dial the next node at the given time.
+    Waiting(Instant),
+    Dialing(ConnectionId),
+    Connected(PeerId, ConnectionId),
+}
+
+/// The client's connection to one node out of a list of entry nodes. When the node cannot be reached or the
+/// connection drops, the next node is dialed.
 pub struct Connection {
     swarm: Swarm<UserNodeBehaviour>,
+    /// Addresses of the entry nodes in the order they are tried, followed by the nodes found through Kademlia.
+    node_addresses: Vec<Multiaddr>,
+    next_address_index: usize,
+    /// Dials that failed since the last connection, to back off after every address was tried.
+    failed_dials: usize,
+    reconnect_delay: Duration,
+    dial_state: DialState,
+    last_error: Option<String>,
 }
 
 impl Connection {
-    pub fn connect_swarm(user_keypair: &Keypair, node_ip: String, node_port: String) -> Result<Swarm<UserNodeBehaviour>, DisruptedDataError> {
-        let behaviour = UserNodeBehaviour::new();
+    /// Builds the swarm and dials the first entry node, given as IP and TCP port. With [`NodeTransport::Quic`]
+    /// each node is dialed over QUIC first and over TCP if that fails.
+    pub fn new(user_keypair: &Keypair, entry_nodes: Vec<(String, String)>, node_transport: NodeTransport, discover_nodes: bool) -> Result<Self, DisruptedDataError> {
+        if entry_nodes.is_empty() {
+            return Err(DisruptedDataError::new(ErrorKind::Config, "No entry nodes given"));
+        }
         let peer_id = PeerId::random();
+        let behaviour = if discover_nodes {
+            UserNodeBehaviour::with_node_discovery(peer_id)
+        } else {
+            UserNodeBehaviour::new()
+        };
 
-        let transport = tcp::tokio::Transport::default().upgrade(Version::V1).authenticate(noise::Config::new(&user_keypair).expect("Could not initialise noise")).multiplex(yamux::Config::default()).boxed();
+        let tcp_transport = tcp::tokio::Transport::default()
+            .upgrade(Version::V1)
+            .authenticate(noise::Config::new(user_keypair).expect("Could not initialise noise"))
+            .multiplex(yamux::Config::default())
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+        let quic_transport = quic::tokio::Transport::new(quic::Config::new(user_keypair))
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+        let transport = tcp_transport.or_transport(quic_transport).map(|either, _| either.into_inner()).boxed();
         let swarm_config = swarm::Config::with_tokio_executor().with_idle_connection_timeout(Duration::from_secs(60));
-        let mut swarm = Swarm::new(transport, behaviour, peer_id, swarm_config);
+        let swarm = Swarm::new(transport, behaviour, peer_id, swarm_config);
 
-        let address_result: libp2p::multiaddr::Result<Multiaddr> = format!("/ip4/{}/tcp/{}", node_ip, node_port).parse();
+        let mut node_addresses = Vec::new();
+        for (node_ip, node_port) in entry_nodes {
+            if let NodeTransport::Quic { port } = &node_transport {
+                node_addresses.push(parse_address(format!("/ip4/{}/udp/{}/quic-v1", node_ip, port))?);
+            }
+            node_addresses.push(parse_address(format!("/ip4/{}/tcp/{}", node_ip, node_port))?);
+        }
 
-        match address_result {
-            Ok(address) => {
-                match swarm.dial(address).map_err(|dial_error: DialError| { DisruptedDataError { message: format!("Could not dial the node{}", dial_error.to_string()) } }) {
-                    Ok(_) => { println!("Successfully dialed node") }
-                    Err(error) => { return Err(error) }
-                }
+        let mut connection = Self {
+            swarm,
+            node_addresses,
+            next_address_index: 0,
+            failed_dials: 0,
+            reconnect_delay: INITIAL_RECONNECT_DELAY,
+            dial_state: DialState::Idle,
+            last_error: None,
+        };
+        connection.dial_next();
+        Ok(connection)
+    }
+
+    pub(crate) fn swarm(&mut self) -> &mut Swarm<UserNodeBehaviour> {
+        &mut self.swarm
+    }
+
+    pub(crate) fn connected_node(&self) -> Option<PeerId> {
+        match self.dial_state {
+            DialState::Connected(peer_id, _) => { Some(peer_id) }
+            _ => { None }
+        }
+    }
+
+    /// Why the last dial failed or the last connection closed.
+    pub(crate) fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// When to call [`Connection::dial_next`] while backing off.
+    pub(crate) fn next_dial_time(&self) -> Option<Instant> {
+        match self.dial_state {
+            DialState::Waiting(dial_time) => { Some(dial_time) }
+            _ => { None }
+        }
+    }
+
+    /// Dials the next node if the client is neither connected nor already dialing.
+    pub(crate) fn ensure_connecting(&mut self) {
+        if let DialState::Idle = self.dial_state {
+            self.dial_next();
+        }
+    }
 
-                let connection = Self {
-                    swarm
+    pub(crate) fn dial_next(&mut self) {
+        let address = self.node_addresses[self.next_address_index % self.node_addresses.len()].clone();
+        self.next_address_index = (self.next_address_index + 1) % self.node_addresses.len();
+        let dial_opts = DialOpts::unknown_peer_id().address(address.clone()).build();
+        let connection_id = dial_opts.connection_id();
+        match self.swarm.dial(dial_opts) {
+            Ok(_) => {
+                println!("Dialing node {}", address);
+                self.dial_state = DialState::Dialing(connection_id);
+            }
+            Err(dial_error) => { self.dial_failed(format!("Could not dial the node {}: {}", address, dial_error)) }
+        }
+    }
+
+    fn dial_failed(&mut self, error: String) {
+        println!("{}", error);
+        self.last_error = Some(error);
+        self.failed_dials += 1;
+        if self.failed_dials % self.node_addresses.len() == 0 {
+            self.dial_state = DialState::Waiting(Instant::now() + self.reconnect_delay);
+            self.reconnect_delay = (self.reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+        } else {
+            self.dial_state = DialState::Waiting(Instant::now());
+        }
+    }
+
+    /// Follows the dials and the connection to the node. Returns the node's peer id when a connection was
+    /// established.
+    pub(crate) fn on_swarm_event(&mut self, swarm_event: &SwarmEvent<behaviour::Event>) -> Option<PeerId> {
+        match (swarm_event, self.dial_state) {
+            (SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. }, DialState::Dialing(dial_connection_id)) if *connection_id == dial_connection_id => {
+                println!("Connected to node {}", peer_id);
+                self.dial_state = DialState::Connected(*peer_id, *connection_id);
+                self.failed_dials = 0;
+                self.reconnect_delay = INITIAL_RECONNECT_DELAY;
+                // Reconnect to the same node first after the connection closes.
+                self.next_address_index = (self.next_address_index + self.node_addresses.len() - 1) % self.node_addresses.len();
+                if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+                    kad.add_address(peer_id, endpoint.get_remote_address().clone());
+                    if let Err(error) = kad.bootstrap() {
+                        println!("Could not look for more nodes: {}", error);
+                    }
+                }
+                Some(*peer_id)
+            }
+            (SwarmEvent::OutgoingConnectionError { connection_id, error, .. }, DialState::Dialing(dial_connection_id)) if *connection_id == dial_connection_id => {
+                self.dial_failed(format!("Could not connect to the node: {}", error));
+                None
+            }
+            (SwarmEvent::ConnectionClosed { peer_id, connection_id, cause, .. }, DialState::Connected(_, node_connection_id)) if *connection_id == node_connection_id => {
+                let error = match cause {
+                    Some(cause) => { format!("Connection to node {} closed: {}", peer_id, cause) }
+                    None => { format!("Connection to node {} closed", peer_id) }
                 };
-                Ok(connection.swarm)
+                println!("{}", error);
+                self.last_error = Some(error);
+                self.dial_state = DialState::Idle;
+                None
             }
-            Err(error) => {
-                return Err(DisruptedDataError { message: format!("Error parsing multiaddress: {}", error) })
+            (SwarmEvent::Behaviour(behaviour::Event::Kademlia(kad::Event::RoutingUpdated { peer, addresses, .. })), _) => {
+                for address in addresses.iter() {
+                    let node_address = address.clone().with_p2p(*peer).unwrap_or_else(|address| address);
+                    // Entry nodes are known by their address without the peer id.
+                    let mut plain_address = node_address.clone();
+                    plain_address.pop();
+                    if !self.node_addresses.contains(&plain_address) && !self.node_addresses.contains(&node_address) {
+                        println!("Found node {}", node_address);
+                        self.node_addresses.push(node_address);
+                    }
+                }
+                None
             }
+            _ => { None }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Looks for a node on the local network over mDNS and returns the IP and TCP port of the first one found.
+pub async fn discover_local_node(timeout: Duration) -> Result<(String, String), DisruptedDataError> {
+    let peer_id = PeerId::random();
+    let mdns_behaviour = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)
+        .map_err(|error| DisruptedDataError::new(ErrorKind::Connection, format!("Could not start mDNS: {}", error)))?;
+    // Discovery only listens for mDNS responses, so the swarm never opens connections.
+    let transport = DummyTransport::<(PeerId, StreamMuxerBox)>::new().boxed();
+    let mut swarm = Swarm::new(transport, mdns_behaviour, peer_id, swarm::Config::with_tokio_executor());
+
+    let discovery = async {
+        loop {
+            if let SwarmEvent::Behaviour(mdns::Event::Discovered(discovered_nodes)) = swarm.select_next_some().await {
+                if let Some(node_address) = discovered_nodes.iter().find_map(|(_, address)| tcp_ip_and_port(address)) {
+                    return node_address;
+                }
+            }
+        }
+    };
+    tokio::time::timeout(timeout, discovery).await
+        .map_err(|_| DisruptedDataError::new(ErrorKind::Connection, format!("No node found on the local network within {} seconds", timeout.as_secs())))
+}
+
+/// IP and port of a plain `/ip4/<ip>/tcp/<port>` address, optionally followed by `/p2p/<peer id>`.
+fn tcp_ip_and_port(address: &Multiaddr) -> Option<(String, String)> {
+    let mut protocols = address.iter();
+    match (protocols.next(), protocols.next(), protocols.next()) {
+        (Some(Protocol::Ip4(ip)), Some(Protocol::Tcp(port)), None | Some(Protocol::P2p(_))) => { Some((ip.to_string(), port.to_string())) }
+        _ => { None }
+    }
+}
+
+fn parse_address(address: String) -> Result<Multiaddr, DisruptedDataError> {
+    address.parse().map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Error parsing multiaddress: {}", error)))
+}
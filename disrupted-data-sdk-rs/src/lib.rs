@@ -1,17 +1,37 @@
+use web_time::{SystemTime, UNIX_EPOCH};
+
 use sha2::{Digest, Sha256};
 
-pub use client::Client;
+pub use behaviour::UserNodeBehaviour;
+#[cfg(not(target_arch = "wasm32"))]
+pub use client::{Client, ClientOptions};
+#[cfg(not(target_arch = "wasm32"))]
+pub use connection::NodeTransport;
 pub use types::actions::ActionResult;
 pub use types::actions::Actions;
-pub use types::DisruptedDataError;
-pub use types::GetRequest;
+pub use types::DeleteRequest;
+pub use types::{DisruptedDataError, ErrorKind};
+pub use types::{GetRequest, VersionSelector};
+pub use types::{GrantRequest, GrantScope};
+pub use types::{GetPublicRequest, PublicRecord, Visibility};
 pub use types::Identity;
-pub use types::PutRequest;
+pub use types::ListRequest;
+pub use types::ListResult;
+pub use types::ProofRequest;
+pub use types::SubtreeRequest;
+pub use types::{encryption, EncryptedValue};
+pub use types::{merkle, MerkleProof, MerkleSubtree, MerkleTree};
+pub use types::{payload, SignedPayload};
+pub use types::{PutCondition, PutRequest};
+pub use types::{HistoryRequest, RecordVersion};
+pub use types::value::{chunk_hash, is_chunk_of, CHUNK_KEY_PREFIX};
 
+#[cfg(not(target_arch = "wasm32"))]
 mod client;
 mod types;
+#[cfg(not(target_arch = "wasm32"))]
 mod connection;
-mod behaviour;
+pub mod behaviour;
 
 pub fn hash_message(message: &String) -> [u8; 32] {
     let mut sha256_hasher = Sha256::new();
@@ -33,4 +53,8 @@ pub fn get_message(parts: Vec<String>) -> String {
     parts.into_iter()
         .map(|s| s.trim().to_string())
         .collect()
-}
\ No newline at end of file
+}
+
+pub fn current_timestamp_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("System time is before the unix epoch").as_millis() as u64
+}
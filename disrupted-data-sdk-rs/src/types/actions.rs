@@ -1,90 +1,164 @@
-use std::str::FromStr;
-
 use hex::encode;
+use libp2p::identity::Keypair;
 use libp2p::kad::{Record, RecordKey};
 use serde::{Deserialize, Serialize};
 
 use crate::types::actions::Actions::Unknown;
-use crate::{DisruptedDataError, Identity};
+use crate::types::encoding::base64_bytes;
+use crate::types::merkle::{MerkleProof, MerkleSubtree};
+use crate::types::payload::{new_timestamp_and_nonce, SignedPayload};
+use crate::{DisruptedDataError, ErrorKind, Identity};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Actions {
 	Put(PutRequest),
 	Get(GetRequest),
+	Delete(DeleteRequest),
+	List(ListRequest),
+	Proof(ProofRequest),
+	Subtree(SubtreeRequest),
+	Grant(GrantRequest),
+	/// Reads a record its owner published. Needs no signature, so any client can send it.
+	GetPublic(GetPublicRequest),
+	/// Lists the versions of a record the nodes retain.
+	History(HistoryRequest),
 	Unknown,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ActionResult {
-	Success(String),
-	Failure(String),
+	/// The value of a `Put` was stored along with the user's index, at `version`.
+	Stored { version: u64 },
+	/// The record of a `Delete` was replaced with a tombstone.
+	Deleted,
+	/// A `Grant` gave the reader access.
+	Granted,
+	/// A `Grant` took the reader's access away.
+	Revoked,
+	Value(#[serde(with = "base64_bytes")] Vec<u8>),
+	List(ListResult),
+	Proof(MerkleProof),
+	Subtree(MerkleSubtree),
+	/// A published record with the owner's signature, see [`PublicRecord::verify`].
+	PublicValue(PublicRecord),
+	/// The retained versions of a record, newest first.
+	History(Vec<RecordVersion>),
+	Failure(DisruptedDataError),
 }
 
 impl ActionResult {
+	pub fn failure(kind: ErrorKind, message: impl Into<String>) -> Self {
+		ActionResult::Failure(DisruptedDataError::new(kind, message))
+	}
+
 	pub fn get_message(&self) -> String {
 		match self {
-			ActionResult::Success(message) | ActionResult::Failure(message) => {
-				message.clone()
+			ActionResult::Stored { version } => { format!("Data added at version {}", version) }
+			ActionResult::Deleted => { "Data deleted".to_string() }
+			ActionResult::Granted => { "Access granted".to_string() }
+			ActionResult::Revoked => { "Access revoked".to_string() }
+			ActionResult::Failure(error) => {
+				error.message.clone()
+			}
+			ActionResult::Value(value) => {
+				String::from_utf8_lossy(value).to_string()
+			}
+			ActionResult::List(list_result) => {
+				list_result.keys.join("\n")
+			}
+			ActionResult::Proof(merkle_proof) => {
+				format!("Merkle root: {} value hash: {} verified: {}", merkle_proof.root, merkle_proof.value_hash, merkle_proof.verify())
+			}
+			ActionResult::Subtree(merkle_subtree) => {
+				merkle_subtree.hash.clone()
+			}
+			ActionResult::PublicValue(public_record) => {
+				String::from_utf8_lossy(&public_record.value).to_string()
+			}
+			ActionResult::History(record_versions) => {
+				record_versions.iter()
+					.map(|record_version| format!("{} {}", record_version.version, record_version.timestamp))
+					.collect::<Vec<String>>()
+					.join("\n")
 			}
 		}
 	}
 }
 
-impl From<(String, &Identity)> for Actions {
-	fn from(value: (String, &Identity)) -> Self {
-		let (record_data, identity) = value;
-		let parts: Vec<&str> = record_data.trim().split_whitespace().collect();
-		if parts.len() < 2 {
-			return Unknown;
-		}
+/// Hex encoded public key (as sent in requests) and raw secret key of the user's secp256k1 identity.
+fn user_keys(keypair: &Keypair) -> (Vec<u8>, Vec<u8>) {
+	let secp256k1_key_pair: libp2p::identity::secp256k1::Keypair = keypair.clone().try_into_secp256k1().unwrap();
+	let hex_user_key = encode(secp256k1_key_pair.public().to_bytes().to_vec());
+	(hex_user_key.into_bytes(), secp256k1_key_pair.secret().to_bytes().to_vec())
+}
 
-		let action = parts[0];
-		let secp256k1_key_pair: libp2p::identity::secp256k1::Keypair = identity.keypair.clone().try_into_secp256k1().unwrap();
-		let public_key = secp256k1_key_pair.public().clone();
-		let secret_key = secp256k1_key_pair.secret().to_bytes().to_vec();
-
-		match action.to_lowercase().as_str() {
-			"put" => {
-				let message_parts: Vec<String> = vec![
-					parts[1].to_string(),
-					parts[2].to_string(),
-				];
-				let signature = Identity::sign(secret_key, parts[2].to_string());
-				let hex_user_key = encode(public_key.to_bytes().to_vec());
-
-				Actions::Put(PutRequest {
-					user_public_key: hex_user_key.into_bytes(),
-					record_key: parts[1].to_string(),
-					record_value: parts[2].to_string(),
-					signature,
-				})
-			}
-			"get" => {
-				let signature = Identity::sign(secret_key, parts[1].to_string());
-				let hex_user_key = encode(public_key.to_bytes().to_vec());
-
-				Actions::Get(GetRequest {
-					user_public_key: hex_user_key.into_bytes(),
-					record_key: parts[1].to_string(),
-					signature,
-				})
-			}
-			_ => { Unknown }
+impl Actions {
+	/// The action signed again with a fresh timestamp and nonce, so that a node which already saw the first copy
+	/// does not reject it as replayed. Only reads are signed again, as they can be served twice without changing
+	/// what is stored. `None` for writes.
+	pub fn resign(self, keypair: &Keypair) -> Option<Actions> {
+		let (_, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		match self {
+			Actions::Get(mut get_request) => {
+				(get_request.timestamp, get_request.nonce) = (timestamp, nonce);
+				get_request.signature = Identity::sign_payload(secret_key, &get_request.signed_payload());
+				Some(Actions::Get(get_request))
+			}
+			Actions::List(mut list_request) => {
+				(list_request.timestamp, list_request.nonce) = (timestamp, nonce);
+				list_request.signature = Identity::sign_payload(secret_key, &list_request.signed_payload());
+				Some(Actions::List(list_request))
+			}
+			Actions::Proof(mut proof_request) => {
+				(proof_request.timestamp, proof_request.nonce) = (timestamp, nonce);
+				proof_request.signature = Identity::sign_payload(secret_key, &proof_request.signed_payload());
+				Some(Actions::Proof(proof_request))
+			}
+			Actions::Subtree(mut subtree_request) => {
+				(subtree_request.timestamp, subtree_request.nonce) = (timestamp, nonce);
+				subtree_request.signature = Identity::sign_payload(secret_key, &subtree_request.signed_payload());
+				Some(Actions::Subtree(subtree_request))
+			}
+			Actions::History(mut history_request) => {
+				(history_request.timestamp, history_request.nonce) = (timestamp, nonce);
+				history_request.signature = Identity::sign_payload(secret_key, &history_request.signed_payload());
+				Some(Actions::History(history_request))
+			}
+			// Public reads are not signed, so they are sent again as they are.
+			Actions::GetPublic(get_public_request) => { Some(Actions::GetPublic(get_public_request)) }
+			Actions::Put(_) | Actions::Delete(_) | Actions::Grant(_) | Unknown => { None }
 		}
 	}
-}
 
-impl Actions {
 	pub fn get_record(self) -> Result<Record, DisruptedDataError> {
 		match self {
 			Actions::Put(put_request) => {
 				Ok(put_request.to_record())
 			}
-			Actions::Get(get_request) => {
-				Err(DisruptedDataError { message: "Record not available for Get actions".to_string() })
+			Actions::Get(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for Get actions".to_string()))
+			}
+			Actions::Delete(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for Delete actions".to_string()))
+			}
+			Actions::List(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for List actions".to_string()))
+			}
+			Actions::Proof(_) | Actions::Subtree(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for Merkle tree actions".to_string()))
+			}
+			Actions::Grant(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for Grant actions".to_string()))
+			}
+			Actions::GetPublic(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for GetPublic actions".to_string()))
+			}
+			Actions::History(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record not available for History actions".to_string()))
 			}
 			Unknown => {
-				Err(DisruptedDataError { message: "Unknown action".to_string() })
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Unknown action".to_string()))
 			}
 		}
 	}
@@ -97,8 +171,29 @@ impl Actions {
 			Actions::Get(get_request) => {
 				Ok(get_request.user_public_key)
 			}
+			Actions::Delete(delete_request) => {
+				Ok(delete_request.user_public_key)
+			}
+			Actions::List(list_request) => {
+				Ok(list_request.user_public_key)
+			}
+			Actions::Proof(proof_request) => {
+				Ok(proof_request.user_public_key)
+			}
+			Actions::Subtree(subtree_request) => {
+				Ok(subtree_request.user_public_key)
+			}
+			Actions::Grant(grant_request) => {
+				Ok(grant_request.user_public_key)
+			}
+			Actions::GetPublic(get_public_request) => {
+				Ok(get_public_request.owner_public_key)
+			}
+			Actions::History(history_request) => {
+				Ok(history_request.user_public_key)
+			}
 			Unknown => {
-				Err(DisruptedDataError { message: "Unknown action".to_string() })
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Unknown action".to_string()))
 			}
 		}
 	}
@@ -111,13 +206,40 @@ impl Actions {
 				Ok(encode(raw_record_key))
 			}
 			Actions::Get(get_request) => {
-				let mut raw_record_key = get_request.user_public_key.clone();
+				let mut raw_record_key = get_request.owner_public_key.unwrap_or(get_request.user_public_key);
 				raw_record_key.append(&mut get_request.record_key.into_bytes());
 
 				Ok(encode(raw_record_key))
 			}
+			Actions::Delete(delete_request) => {
+				let mut raw_record_key = delete_request.user_public_key.clone();
+				raw_record_key.append(&mut delete_request.record_key.into_bytes());
+
+				Ok(encode(raw_record_key))
+			}
+			Actions::List(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record key not available for List actions".to_string()))
+			}
+			Actions::Proof(_) | Actions::Subtree(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record key not available for Merkle tree actions".to_string()))
+			}
+			Actions::GetPublic(get_public_request) => {
+				let mut raw_record_key = get_public_request.owner_public_key;
+				raw_record_key.append(&mut get_public_request.record_key.into_bytes());
+
+				Ok(encode(raw_record_key))
+			}
+			Actions::History(history_request) => {
+				let mut raw_record_key = history_request.user_public_key;
+				raw_record_key.append(&mut history_request.record_key.into_bytes());
+
+				Ok(encode(raw_record_key))
+			}
+			Actions::Grant(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record key not available for Grant actions".to_string()))
+			}
 			Unknown => {
-				Err(DisruptedDataError { message: "Unknown action".to_string() })
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Unknown action".to_string()))
 			}
 		}
 	}
@@ -130,8 +252,26 @@ impl Actions {
 			Actions::Get(get_request) => {
 				Ok(get_request.to_record_key())
 			}
+			Actions::Delete(delete_request) => {
+				Ok(delete_request.to_record_key())
+			}
+			Actions::List(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record key not available for List actions".to_string()))
+			}
+			Actions::Proof(_) | Actions::Subtree(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record key not available for Merkle tree actions".to_string()))
+			}
+			Actions::GetPublic(get_public_request) => {
+				Ok(get_public_request.to_record_key())
+			}
+			Actions::History(history_request) => {
+				Ok(history_request.to_record_key())
+			}
+			Actions::Grant(_) => {
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record key not available for Grant actions".to_string()))
+			}
 			Unknown => {
-				Err(DisruptedDataError { message: "Unknown action".to_string() })
+				Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Unknown action".to_string()))
 			}
 		}
 	}
@@ -141,28 +281,621 @@ impl Actions {
 pub struct PutRequest {
 	pub user_public_key: Vec<u8>,
 	pub record_key: String,
-	pub record_value: String,
+	#[serde(with = "base64_bytes")]
+	pub record_value: Vec<u8>,
+	/// Public records can be read by anyone with [`Actions::GetPublic`].
+	#[serde(default)]
+	pub visibility: Visibility,
+	pub timestamp: u64,
+	pub nonce: u64,
 	pub signature: Vec<u8>,
+	/// Asks the client to encrypt the value before it leaves the SDK. Only read by the client and never sent
+	/// to nodes.
+	#[serde(skip)]
+	pub encrypt: bool,
+	/// Checked by the node against the stored record before it writes the value, see [`PutCondition`] for what it
+	/// guarantees. The node answers with [`ErrorKind::VersionMismatch`] when the condition does not hold.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub condition: Option<PutCondition>,
+	/// Seconds after `timestamp` until the record expires and its key is dropped from the user's index. Records
+	/// without a TTL never expire.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ttl: Option<u64>,
+}
+
+/// Condition of a compare-and-swap put, see [`PutRequest::if_version`] and [`PutRequest::if_absent`]. It is signed
+/// with the put, so a node cannot store the value without checking it.
+///
+/// The node serving the put checks the condition against the first copy of the record it finds, not against a
+/// majority of the replicas. It is not atomic across nodes: two puts with the same condition sent through
+/// different nodes at the same time may both be stored at the same version, and replicas then keep the one signed
+/// last. Writers that need a single winner should send their conditional puts through the same node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PutCondition {
+	/// The record must be at this version. Deleting a key also moves it to a new version.
+	IfVersion(u64),
+	/// The key must hold no value, because it was never written or was deleted.
+	IfAbsent,
 }
 
 impl PutRequest {
-	pub fn to_record(mut self) -> Record {
+	pub fn new(keypair: &Keypair, record_key: String, record_value: Vec<u8>) -> Self {
+		Self::new_with_visibility(keypair, record_key, record_value, Visibility::Private)
+	}
+
+	pub fn new_with_visibility(keypair: &Keypair, record_key: String, record_value: Vec<u8>, visibility: Visibility) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut put_request = Self {
+			user_public_key,
+			record_key,
+			record_value,
+			visibility,
+			timestamp,
+			nonce,
+			signature: vec![],
+			encrypt: false,
+			condition: None,
+			ttl: None,
+		};
+		put_request.signature = Identity::sign_payload(secret_key, &put_request.signed_payload());
+		put_request
+	}
+
+	pub fn with_encryption(mut self) -> Self {
+		self.encrypt = true;
+		self
+	}
+
+	/// Only stores the value if the record is still at `version`, e.g. the version returned when it was last
+	/// stored. Version 0 stands for a key that was never written, or was last written before records carried a
+	/// version.
+	pub fn if_version(self, keypair: &Keypair, version: u64) -> Self {
+		self.with_condition(keypair, Some(PutCondition::IfVersion(version)))
+	}
+
+	/// Only stores the value if the key holds none.
+	pub fn if_absent(self, keypair: &Keypair) -> Self {
+		self.with_condition(keypair, Some(PutCondition::IfAbsent))
+	}
+
+	/// The condition is signed, so the request is signed again.
+	pub fn with_condition(mut self, keypair: &Keypair, condition: Option<PutCondition>) -> Self {
+		let (_, secret_key) = user_keys(keypair);
+		self.condition = condition;
+		self.signature = Identity::sign_payload(secret_key, &self.signed_payload());
+		self
+	}
+
+	/// Lets the record expire `ttl` seconds after the request was built. Like the condition, the TTL is signed, so
+	/// the request is signed again.
+	pub fn with_ttl(mut self, keypair: &Keypair, ttl: Option<u64>) -> Self {
+		let (_, secret_key) = user_keys(keypair);
+		self.ttl = ttl;
+		self.signature = Identity::sign_payload(secret_key, &self.signed_payload());
+		self
+	}
+
+	/// Values can be arbitrary bytes, so the owner signs the sha256 of the value.
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("put", &self.user_public_key, &self.record_key, self.timestamp, self.nonce)
+			.with_value(&self.record_value)
+			.with_visibility(self.visibility)
+			.with_ttl(self.ttl)
+			.with_condition(self.condition)
+	}
+
+	pub fn to_record(self) -> Record {
 		let mut record_key_bytes: Vec<u8> = self.user_public_key.clone();
 		record_key_bytes.append(&mut self.record_key.into_bytes());
-		Record::new(RecordKey::new(&encode(record_key_bytes)), self.record_value.as_bytes().to_vec())
+		Record::new(RecordKey::new(&encode(record_key_bytes)), self.record_value)
 	}
 }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visibility {
+	#[default]
+	Private,
+	Public,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GetRequest {
 	pub user_public_key: Vec<u8>,
 	pub record_key: String,
+	/// Hex encoded public key of the user who owns the record, when reading a record another user shared.
+	/// The owner must have granted `user_public_key` access to the key.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub owner_public_key: Option<Vec<u8>>,
+	/// Reads an older version of the record instead of the current one.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub version: Option<VersionSelector>,
+	/// Key of the value `record_key` is a chunk of, when reading a chunk of a shared value. The owner's grant is
+	/// checked against this key, see [`GetRequest::chunk_of`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub value_key: Option<String>,
+	pub timestamp: u64,
+	pub nonce: u64,
 	pub signature: Vec<u8>,
 }
 
+/// Which version of a record a [`GetRequest`] reads, see [`GetRequest::at_version`] and [`GetRequest::at_time`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VersionSelector {
+	Version(u64),
+	/// The version that was current at this time, in milliseconds since the Unix epoch.
+	At(u64),
+}
+
 impl GetRequest {
-	pub fn to_record_key(mut self) -> RecordKey {
+	pub fn new(keypair: &Keypair, record_key: String) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut get_request = Self {
+			user_public_key,
+			record_key,
+			owner_public_key: None,
+			version: None,
+			value_key: None,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		get_request.signature = Identity::sign_payload(secret_key, &get_request.signed_payload());
+		get_request
+	}
+
+	/// Reads `record_key` of the user with the hex encoded `owner_public_key`, who granted this user access.
+	pub fn shared(keypair: &Keypair, owner_public_key: Vec<u8>, record_key: String) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut get_request = Self {
+			owner_public_key: Some(owner_public_key).filter(|owner_public_key| *owner_public_key != user_public_key),
+			user_public_key,
+			record_key,
+			version: None,
+			value_key: None,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		get_request.signature = Identity::sign_payload(secret_key, &get_request.signed_payload());
+		get_request
+	}
+
+	/// Reads the record as it was stored at `version`, as long as the nodes still retain it.
+	pub fn at_version(self, keypair: &Keypair, version: u64) -> Self {
+		self.with_version(keypair, Some(VersionSelector::Version(version)))
+	}
+
+	/// Reads the version of the record that was current at `timestamp`, in milliseconds since the Unix epoch.
+	pub fn at_time(self, keypair: &Keypair, timestamp: u64) -> Self {
+		self.with_version(keypair, Some(VersionSelector::At(timestamp)))
+	}
+
+	/// The version is signed, so the request is signed again.
+	pub fn with_version(mut self, keypair: &Keypair, version: Option<VersionSelector>) -> Self {
+		let (_, secret_key) = user_keys(keypair);
+		self.version = version;
+		self.signature = Identity::sign_payload(secret_key, &self.signed_payload());
+		self
+	}
+
+	/// Reads `record_key` as a chunk of the value under `value_key`. Nodes only let readers of a shared value read
+	/// its chunks if the owner granted them access to `value_key`. The value key is signed, so the request is
+	/// signed again.
+	pub fn chunk_of(mut self, keypair: &Keypair, value_key: String) -> Self {
+		let (_, secret_key) = user_keys(keypair);
+		self.value_key = Some(value_key);
+		self.signature = Identity::sign_payload(secret_key, &self.signed_payload());
+		self
+	}
+
+	/// The owner is only signed when reading a shared record, the version only when reading an older one and the
+	/// value key only when reading a chunk, so requests for the current version of the user's own records keep
+	/// their payload.
+	pub fn signed_payload(&self) -> SignedPayload {
+		let signed_payload = SignedPayload::new("get", &self.user_public_key, &self.record_key, self.timestamp, self.nonce);
+		let signed_payload = match &self.owner_public_key {
+			Some(owner_public_key) => { signed_payload.with_arguments(vec![String::from_utf8_lossy(owner_public_key).to_string()]) }
+			None => { signed_payload }
+		};
+		signed_payload.with_version_selector(self.version).with_value_key(self.value_key.as_deref())
+	}
+
+	/// Hex encoded public key of the user who owns the record.
+	pub fn get_owner_public_key(&self) -> &Vec<u8> {
+		self.owner_public_key.as_ref().unwrap_or(&self.user_public_key)
+	}
+
+	pub fn to_record_key(self) -> RecordKey {
+		let mut record_key_bytes: Vec<u8> = self.get_owner_public_key().clone();
+		record_key_bytes.append(&mut self.record_key.into_bytes());
+		RecordKey::new(&encode(record_key_bytes))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteRequest {
+	pub user_public_key: Vec<u8>,
+	pub record_key: String,
+	/// Time of the delete. Also recorded in the tombstone that replaces the data record.
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+impl DeleteRequest {
+	pub fn new(keypair: &Keypair, record_key: String) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut delete_request = Self {
+			user_public_key,
+			record_key,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		delete_request.signature = Identity::sign_payload(secret_key, &delete_request.signed_payload());
+		delete_request
+	}
+
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("delete", &self.user_public_key, &self.record_key, self.timestamp, self.nonce)
+	}
+
+	pub fn to_record_key(self) -> RecordKey {
 		let mut record_key_bytes: Vec<u8> = self.user_public_key.clone();
 		record_key_bytes.append(&mut self.record_key.into_bytes());
 		RecordKey::new(&encode(record_key_bytes))
 	}
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListRequest {
+	pub user_public_key: Vec<u8>,
+	pub prefix: Option<String>,
+	/// Last key of the previous page. Only keys sorting after it are returned.
+	pub cursor: Option<String>,
+	pub limit: Option<u32>,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+impl ListRequest {
+	pub fn new(keypair: &Keypair, prefix: Option<String>, cursor: Option<String>, limit: Option<u32>) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut list_request = Self {
+			user_public_key,
+			prefix,
+			cursor,
+			limit,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		list_request.signature = Identity::sign_payload(secret_key, &list_request.signed_payload());
+		list_request
+	}
+
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("list", &self.user_public_key, "", self.timestamp, self.nonce)
+			.with_arguments(vec![
+				self.prefix.clone().unwrap_or_default(),
+				self.cursor.clone().unwrap_or_default(),
+				self.limit.map(|limit| limit.to_string()).unwrap_or_default(),
+			])
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListResult {
+	pub keys: Vec<String>,
+	/// Set when more keys are available. Pass it as the cursor of the next `ListRequest`.
+	pub next_cursor: Option<String>,
+}
+
+/// Asks for the inclusion proof of a record key in the user's Merkle tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofRequest {
+	pub user_public_key: Vec<u8>,
+	pub record_key: String,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+impl ProofRequest {
+	pub fn new(keypair: &Keypair, record_key: String) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut proof_request = Self {
+			user_public_key,
+			record_key,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		proof_request.signature = Identity::sign_payload(secret_key, &proof_request.signed_payload());
+		proof_request
+	}
+
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("proof", &self.user_public_key, &self.record_key, self.timestamp, self.nonce)
+	}
+}
+
+/// Asks for a single node of the user's Merkle tree, `depth` levels below the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubtreeRequest {
+	pub user_public_key: Vec<u8>,
+	pub depth: u8,
+	pub index: u32,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+impl SubtreeRequest {
+	pub fn new(keypair: &Keypair, depth: u8, index: u32) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut subtree_request = Self {
+			user_public_key,
+			depth,
+			index,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		subtree_request.signature = Identity::sign_payload(secret_key, &subtree_request.signed_payload());
+		subtree_request
+	}
+
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("subtree", &self.user_public_key, "", self.timestamp, self.nonce)
+			.with_arguments(vec![self.depth.to_string(), self.index.to_string()])
+	}
+}
+
+/// Keys of the owner a grant gives access to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GrantScope {
+	Key(String),
+	Prefix(String),
+}
+
+impl GrantScope {
+	pub fn covers(&self, record_key: &str) -> bool {
+		match self {
+			GrantScope::Key(key) => { key == record_key }
+			GrantScope::Prefix(prefix) => { record_key.starts_with(prefix.as_str()) }
+		}
+	}
+
+	fn kind(&self) -> &'static str {
+		match self {
+			GrantScope::Key(_) => { "key" }
+			GrantScope::Prefix(_) => { "prefix" }
+		}
+	}
+
+	fn value(&self) -> &str {
+		match self {
+			GrantScope::Key(value) | GrantScope::Prefix(value) => { value }
+		}
+	}
+}
+
+/// Grants, or revokes, read access for `reader_public_key` to the keys of the owner in `scope`. The owner
+/// signs the grant and nodes keep the signature, so every node can check a grant before serving a shared GET.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrantRequest {
+	pub user_public_key: Vec<u8>,
+	/// Hex encoded public key of the reader.
+	pub reader_public_key: Vec<u8>,
+	pub scope: GrantScope,
+	pub revoke: bool,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+impl GrantRequest {
+	pub fn grant(keypair: &Keypair, reader_public_key: Vec<u8>, scope: GrantScope) -> Self {
+		Self::new(keypair, reader_public_key, scope, false)
+	}
+
+	pub fn revoke(keypair: &Keypair, reader_public_key: Vec<u8>, scope: GrantScope) -> Self {
+		Self::new(keypair, reader_public_key, scope, true)
+	}
+
+	fn new(keypair: &Keypair, reader_public_key: Vec<u8>, scope: GrantScope, revoke: bool) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut grant_request = Self {
+			user_public_key,
+			reader_public_key,
+			scope,
+			revoke,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		grant_request.signature = Identity::sign_payload(secret_key, &grant_request.signed_payload());
+		grant_request
+	}
+
+	pub fn signed_payload(&self) -> SignedPayload {
+		Self::payload(&self.user_public_key, &self.reader_public_key, &self.scope, self.revoke, self.timestamp, self.nonce)
+	}
+
+	/// Payload the owner signs for a grant. Nodes rebuild it from stored grants to check their signatures.
+	pub fn payload(user_public_key: &[u8], reader_public_key: &[u8], scope: &GrantScope, revoke: bool, timestamp: u64, nonce: u64) -> SignedPayload {
+		SignedPayload::new(if revoke { "revoke" } else { "grant" }, user_public_key, scope.value(), timestamp, nonce)
+			.with_arguments(vec![String::from_utf8_lossy(reader_public_key).to_string(), scope.kind().to_string()])
+	}
+}
+
+/// Lists the versions of one of the user's records that the nodes retain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryRequest {
+	pub user_public_key: Vec<u8>,
+	pub record_key: String,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+impl HistoryRequest {
+	pub fn new(keypair: &Keypair, record_key: String) -> Self {
+		let (user_public_key, secret_key) = user_keys(keypair);
+		let (timestamp, nonce) = new_timestamp_and_nonce();
+		let mut history_request = Self {
+			user_public_key,
+			record_key,
+			timestamp,
+			nonce,
+			signature: vec![],
+		};
+		history_request.signature = Identity::sign_payload(secret_key, &history_request.signed_payload());
+		history_request
+	}
+
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("history", &self.user_public_key, &self.record_key, self.timestamp, self.nonce)
+	}
+
+	pub fn to_record_key(self) -> RecordKey {
+		let mut record_key_bytes: Vec<u8> = self.user_public_key;
+		record_key_bytes.append(&mut self.record_key.into_bytes());
+		RecordKey::new(&encode(record_key_bytes))
+	}
+}
+
+/// One retained version of a record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordVersion {
+	pub version: u64,
+	/// Time of the put or delete that wrote the version, as signed by the owner.
+	pub timestamp: u64,
+	/// Hex encoded sha256 of the stored value, `None` if the version is a delete.
+	pub value_hash: Option<String>,
+	/// TTL of the put that wrote the version, in seconds from `timestamp`, see [`PutRequest::ttl`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ttl: Option<u64>,
+	#[serde(default)]
+	pub visibility: Visibility,
+}
+
+impl RecordVersion {
+	/// Seconds from `now`, in milliseconds since the Unix epoch, until the version expires. Rounded up, so it is
+	/// `Some(0)` only once the version expired. `None` if it never expires.
+	pub fn remaining_ttl(&self, now: u64) -> Option<u64> {
+		let expires_at = self.timestamp.saturating_add(self.ttl?.saturating_mul(1000));
+		Some(expires_at.saturating_sub(now).div_ceil(1000))
+	}
+}
+
+/// Reads a published record of the user with the hex encoded `owner_public_key`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetPublicRequest {
+	pub owner_public_key: Vec<u8>,
+	pub record_key: String,
+}
+
+impl GetPublicRequest {
+	pub fn new(owner_public_key: Vec<u8>, record_key: String) -> Self {
+		Self { owner_public_key, record_key }
+	}
+
+	pub fn to_record_key(self) -> RecordKey {
+		let mut record_key_bytes: Vec<u8> = self.owner_public_key;
+		record_key_bytes.append(&mut self.record_key.into_bytes());
+		RecordKey::new(&encode(record_key_bytes))
+	}
+}
+
+/// A published record as stored by its owner, with the signature of the put that stored it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicRecord {
+	pub owner_public_key: Vec<u8>,
+	pub record_key: String,
+	#[serde(with = "base64_bytes")]
+	pub value: Vec<u8>,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+	/// TTL of the put, in seconds, see [`PutRequest::ttl`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub ttl: Option<u64>,
+	/// Condition of the put, see [`PutRequest::condition`].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub condition: Option<PutCondition>,
+}
+
+impl PublicRecord {
+	pub fn signed_payload(&self) -> SignedPayload {
+		SignedPayload::new("put", &self.owner_public_key, &self.record_key, self.timestamp, self.nonce)
+			.with_value(&self.value)
+			.with_visibility(Visibility::Public)
+			.with_ttl(self.ttl)
+			.with_condition(self.condition)
+	}
+
+	/// Whether the owner published this value under this key. Nodes are not trusted to check it for the reader.
+	pub fn verify(&self) -> bool {
+		self.signed_payload().verify(&self.signature)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn remaining_ttl_counts_down_from_the_signed_timestamp() {
+		let record_version = RecordVersion { version: 1, timestamp: 1_000_000, value_hash: None, ttl: Some(60), visibility: Visibility::Private };
+
+		assert_eq!(record_version.remaining_ttl(1_000_000), Some(60));
+		assert_eq!(record_version.remaining_ttl(1_030_500), Some(30));
+		assert_eq!(record_version.remaining_ttl(1_060_000), Some(0));
+		assert_eq!(record_version.remaining_ttl(2_000_000), Some(0));
+		assert_eq!(RecordVersion { ttl: None, ..record_version }.remaining_ttl(1_000_000), None);
+	}
+
+	#[test]
+	fn only_reads_are_signed_again() {
+		let keypair = Keypair::generate_secp256k1();
+		let get_request = GetRequest::new(&keypair, "key".to_string()).at_version(&keypair, 2);
+
+		let Some(Actions::Get(resigned_request)) = Actions::Get(get_request.clone()).resign(&keypair) else { panic!("Reads are signed again") };
+		assert_ne!(resigned_request.nonce, get_request.nonce);
+		assert_eq!((resigned_request.record_key.as_str(), resigned_request.version), ("key", get_request.version));
+		assert!(resigned_request.signed_payload().verify(&resigned_request.signature));
+
+		assert!(Actions::Put(PutRequest::new(&keypair, "key".to_string(), b"value".to_vec())).resign(&keypair).is_none());
+		assert!(Actions::Delete(DeleteRequest::new(&keypair, "key".to_string())).resign(&keypair).is_none());
+	}
+
+	#[test]
+	fn public_records_of_conditional_puts_verify() {
+		let keypair = Keypair::generate_secp256k1();
+		let put_request = PutRequest::new_with_visibility(&keypair, "profile".to_string(), b"value".to_vec(), Visibility::Public).if_absent(&keypair);
+		let public_record = PublicRecord {
+			owner_public_key: put_request.user_public_key.clone(),
+			record_key: put_request.record_key.clone(),
+			value: put_request.record_value.clone(),
+			timestamp: put_request.timestamp,
+			nonce: put_request.nonce,
+			signature: put_request.signature.clone(),
+			ttl: put_request.ttl,
+			condition: put_request.condition,
+		};
+
+		assert!(public_record.verify());
+		assert!(!PublicRecord { condition: None, ..public_record.clone() }.verify());
+		assert!(!PublicRecord { condition: Some(PutCondition::IfVersion(0)), ..public_record }.verify());
+	}
+}
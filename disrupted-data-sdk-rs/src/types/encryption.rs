@@ -0,0 +1,223 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hex::{decode, encode};
+use hkdf::Hkdf;
+use libp2p::identity::Keypair;
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{rand, PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::types::value::ChunkManifest;
+use crate::{DisruptedDataError, ErrorKind};
+
+/// HKDF info for keys that wrap content keys. Changing it makes every wrapped key unreadable.
+const WRAPPING_KEY_INFO: &[u8] = b"disrupted-data/wrapping-key/1";
+
+/// A value encrypted on the client before it is stored.
+///
+/// The value is encrypted with a random content key, which is wrapped for the owner and for every reader the
+/// value is shared with. A wrapped key is encrypted with a key derived from the secp256k1 ECDH secret of the
+/// owner and the reader, so either of them can unwrap it and storage nodes cannot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedValue {
+    pub nonce: Vec<u8>,
+    pub content: EncryptedContent,
+    pub wrapped_keys: Vec<WrappedKey>,
+}
+
+/// Ciphertext of an [`EncryptedValue`]. Ciphertexts larger than a record are chunked like plain values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EncryptedContent {
+    Inline(Vec<u8>),
+    Chunked(ChunkManifest),
+}
+
+/// Content key of an [`EncryptedValue`], wrapped for one reader.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    /// Hex encoded public key of the reader, as sent in requests.
+    pub reader_public_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+}
+
+impl EncryptedValue {
+    /// Encrypts `plaintext` under a fresh content key wrapped for the owner. The ciphertext is bound to
+    /// `record_key`, so a node cannot serve it for another key.
+    pub fn seal(owner: &Keypair, record_key: &str, plaintext: &[u8]) -> Result<Self, DisruptedDataError> {
+        let content_key: [u8; 32] = rand::random();
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&content_key))
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: record_key.as_bytes() })
+            .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Could not encrypt the value".to_string()))?;
+        let owner_public_key = hex_public_key(owner)?;
+        let wrapped_key = wrap_key(owner, &owner_public_key, record_key, &content_key)?;
+
+        Ok(Self {
+            nonce: nonce.to_vec(),
+            content: EncryptedContent::Inline(ciphertext),
+            wrapped_keys: vec![wrapped_key],
+        })
+    }
+
+    /// Decrypts `ciphertext`, the resolved [`EncryptedContent`], as `reader`. `owner_public_key` is the hex
+    /// encoded public key of the user who stored the value.
+    pub fn open(&self, reader: &Keypair, owner_public_key: &[u8], record_key: &str, ciphertext: &[u8]) -> Result<Vec<u8>, DisruptedDataError> {
+        let content_key = self.content_key(reader, owner_public_key, record_key)?;
+        ChaCha20Poly1305::new(Key::from_slice(&content_key))
+            .decrypt(Nonce::from_slice(&self.nonce), Payload { msg: ciphertext, aad: record_key.as_bytes() })
+            .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Could not decrypt the value".to_string()))
+    }
+
+    /// Unwraps the content key with the key `reader` shares with the owner.
+    pub fn content_key(&self, reader: &Keypair, owner_public_key: &[u8], record_key: &str) -> Result<[u8; 32], DisruptedDataError> {
+        let reader_public_key = hex_public_key(reader)?;
+        let wrapped_key = self.wrapped_keys.iter()
+            .find(|wrapped_key| wrapped_key.reader_public_key == reader_public_key)
+            .ok_or(DisruptedDataError::new(ErrorKind::Encryption, "The value is not encrypted for this key".to_string()))?;
+        if self.nonce.len() != 12 || wrapped_key.nonce.len() != 12 {
+            return Err(DisruptedDataError::new(ErrorKind::Encryption, "Invalid encryption nonce".to_string()));
+        }
+
+        let wrapping_key = wrapping_key(reader, owner_public_key)?;
+        let content_key = ChaCha20Poly1305::new(&wrapping_key)
+            .decrypt(Nonce::from_slice(&wrapped_key.nonce), Payload { msg: &wrapped_key.wrapped_key, aad: &wrapping_aad(record_key, &reader_public_key) })
+            .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Could not unwrap the content key".to_string()))?;
+        content_key.try_into().map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Invalid content key".to_string()))
+    }
+
+    /// Wraps the content key for `reader_public_key` (hex encoded), replacing an earlier wrapped key for it.
+    pub fn add_reader(&mut self, owner: &Keypair, reader_public_key: &[u8], record_key: &str) -> Result<(), DisruptedDataError> {
+        let content_key = self.content_key(owner, &hex_public_key(owner)?, record_key)?;
+        let wrapped_key = wrap_key(owner, reader_public_key, record_key, &content_key)?;
+        self.wrapped_keys.retain(|wrapped_key| wrapped_key.reader_public_key != reader_public_key);
+        self.wrapped_keys.push(wrapped_key);
+        Ok(())
+    }
+
+    /// Hex encoded public keys of the readers the value is shared with, other than `owner`.
+    pub fn readers(&self, owner: &Keypair) -> Result<Vec<Vec<u8>>, DisruptedDataError> {
+        let owner_public_key = hex_public_key(owner)?;
+        Ok(self.wrapped_keys.iter()
+            .map(|wrapped_key| wrapped_key.reader_public_key.clone())
+            .filter(|reader_public_key| *reader_public_key != owner_public_key)
+            .collect())
+    }
+}
+
+/// Wraps `content_key` for `reader_public_key` (hex encoded) with the key it shares with `owner`.
+pub fn wrap_key(owner: &Keypair, reader_public_key: &[u8], record_key: &str, content_key: &[u8; 32]) -> Result<WrappedKey, DisruptedDataError> {
+    let nonce: [u8; 12] = rand::random();
+    let wrapped_key = ChaCha20Poly1305::new(&wrapping_key(owner, reader_public_key)?)
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: content_key, aad: &wrapping_aad(record_key, reader_public_key) })
+        .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Could not wrap the content key".to_string()))?;
+
+    Ok(WrappedKey {
+        reader_public_key: reader_public_key.to_vec(),
+        nonce: nonce.to_vec(),
+        wrapped_key,
+    })
+}
+
+/// Key derived from the ECDH secret of `keypair` and `other_public_key`. Both sides derive the same key.
+fn wrapping_key(keypair: &Keypair, other_public_key: &[u8]) -> Result<Key, DisruptedDataError> {
+    let secp256k1_key_pair = keypair.clone().try_into_secp256k1()
+        .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Encryption needs a secp256k1 key".to_string()))?;
+    let secret_key = SecretKey::from_slice(&secp256k1_key_pair.secret().to_bytes())
+        .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Invalid secret key".to_string()))?;
+    let other_public_key = decode(other_public_key).ok()
+        .and_then(|public_key| PublicKey::from_slice(&public_key).ok())
+        .ok_or(DisruptedDataError::new(ErrorKind::Encryption, "Invalid public key".to_string()))?;
+
+    let shared_secret = SharedSecret::new(&other_public_key, &secret_key);
+    let mut wrapping_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, &shared_secret.secret_bytes())
+        .expand(WRAPPING_KEY_INFO, &mut wrapping_key)
+        .expect("32 bytes is a valid HKDF output length");
+    Ok(Key::from(wrapping_key))
+}
+
+fn wrapping_aad(record_key: &str, reader_public_key: &[u8]) -> Vec<u8> {
+    let mut aad = (record_key.len() as u32).to_le_bytes().to_vec();
+    aad.extend_from_slice(record_key.as_bytes());
+    aad.extend_from_slice(reader_public_key);
+    aad
+}
+
+/// Hex encoded public key of `keypair`, as sent in requests.
+fn hex_public_key(keypair: &Keypair) -> Result<Vec<u8>, DisruptedDataError> {
+    let secp256k1_key_pair = keypair.clone().try_into_secp256k1()
+        .map_err(|_| DisruptedDataError::new(ErrorKind::Encryption, "Encryption needs a secp256k1 key".to_string()))?;
+    Ok(encode(secp256k1_key_pair.public().to_bytes()).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline_ciphertext(encrypted_value: &EncryptedValue) -> Vec<u8> {
+        match &encrypted_value.content {
+            EncryptedContent::Inline(ciphertext) => { ciphertext.clone() }
+            EncryptedContent::Chunked(_) => { panic!("Expected an inline ciphertext") }
+        }
+    }
+
+    #[test]
+    fn the_owner_opens_a_sealed_value() {
+        let owner = Keypair::generate_secp256k1();
+        let owner_public_key = hex_public_key(&owner).unwrap();
+        let encrypted_value = EncryptedValue::seal(&owner, "notes", b"secret").unwrap();
+        let ciphertext = inline_ciphertext(&encrypted_value);
+
+        assert_ne!(ciphertext, b"secret".to_vec());
+        assert_eq!(encrypted_value.open(&owner, &owner_public_key, "notes", &ciphertext).unwrap(), b"secret".to_vec());
+        assert!(encrypted_value.readers(&owner).unwrap().is_empty());
+    }
+
+    #[test]
+    fn added_readers_open_the_value() {
+        let owner = Keypair::generate_secp256k1();
+        let owner_public_key = hex_public_key(&owner).unwrap();
+        let reader = Keypair::generate_secp256k1();
+        let reader_public_key = hex_public_key(&reader).unwrap();
+        let mut encrypted_value = EncryptedValue::seal(&owner, "notes", b"secret").unwrap();
+        encrypted_value.add_reader(&owner, &reader_public_key, "notes").unwrap();
+        encrypted_value.add_reader(&owner, &reader_public_key, "notes").unwrap();
+        let ciphertext = inline_ciphertext(&encrypted_value);
+
+        assert_eq!(encrypted_value.readers(&owner).unwrap(), vec![reader_public_key]);
+        assert_eq!(encrypted_value.open(&reader, &owner_public_key, "notes", &ciphertext).unwrap(), b"secret".to_vec());
+    }
+
+    #[test]
+    fn other_keys_cannot_open_the_value() {
+        let owner = Keypair::generate_secp256k1();
+        let owner_public_key = hex_public_key(&owner).unwrap();
+        let stranger = Keypair::generate_secp256k1();
+        let mut encrypted_value = EncryptedValue::seal(&owner, "notes", b"secret").unwrap();
+        let ciphertext = inline_ciphertext(&encrypted_value);
+
+        let error = encrypted_value.open(&stranger, &owner_public_key, "notes", &ciphertext).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Encryption);
+
+        // A wrapped key listed under the stranger's public key was still wrapped with the owner's shared secret.
+        let mut forged_key = encrypted_value.wrapped_keys[0].clone();
+        forged_key.reader_public_key = hex_public_key(&stranger).unwrap();
+        encrypted_value.wrapped_keys.push(forged_key);
+        let error = encrypted_value.open(&stranger, &owner_public_key, "notes", &ciphertext).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Encryption);
+    }
+
+    #[test]
+    fn tampered_or_moved_values_do_not_open() {
+        let owner = Keypair::generate_secp256k1();
+        let owner_public_key = hex_public_key(&owner).unwrap();
+        let encrypted_value = EncryptedValue::seal(&owner, "notes", b"secret").unwrap();
+        let mut ciphertext = inline_ciphertext(&encrypted_value);
+
+        assert!(encrypted_value.open(&owner, &owner_public_key, "other", &ciphertext).is_err());
+        ciphertext[0] ^= 1;
+        assert!(encrypted_value.open(&owner, &owner_public_key, "notes", &ciphertext).is_err());
+    }
+}
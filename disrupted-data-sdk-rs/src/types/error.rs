@@ -1,11 +1,62 @@
 use std::error::Error;
 use std::fmt;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+/// Machine readable reason of a [`DisruptedDataError`], sent by nodes along with the message so clients can act on
+/// a failure without parsing its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// No record exists for the key, or the key is not in the user's index.
+    NotFound,
+    /// The record was deleted and only its tombstone is left.
+    Deleted,
+    /// The requester is not allowed to read the record, e.g. it was never shared with them or is not public.
+    Unauthorized,
+    /// A request or record is not signed by the key it claims to come from.
+    InvalidSignature,
+    /// The request is malformed or names an action that does not apply to it.
+    InvalidRequest,
+    /// The request is too old or its nonce was already used.
+    Replayed,
+    /// The write would replace a newer copy of the record.
+    Conflict,
+    /// The condition of a conditional put did not hold. Carries the version the record is at, 0 if the key
+    /// was never written.
+    VersionMismatch { current_version: u64 },
+    /// Too few peers stored the record.
+    QuorumFailed,
+    /// The network did not answer in time.
+    Timeout,
+    /// The node refused to store the record because of its size or record limits.
+    QuotaExceeded,
+    /// Data returned by the network failed verification against its hash, proof or signature.
+    Integrity,
+    /// The value could not be encrypted or decrypted with the given keys.
+    Encryption,
+    /// The node could not be reached or the request could not be delivered.
+    Connection,
+    /// The connection dropped after a write was sent, so the node may or may not have applied it. Read the record
+    /// to find out before writing again.
+    OutcomeUnknown,
+    /// Invalid configuration.
+    Config,
+    /// A bug or unexpected state on the node or in the SDK.
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DisruptedDataError {
+    pub kind: ErrorKind,
     pub message: String,
 }
 
+impl DisruptedDataError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        DisruptedDataError { kind, message: message.into() }
+    }
+}
+
 impl fmt::Display for DisruptedDataError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.message)
@@ -7,6 +7,7 @@ use libp2p::PeerId;
 use secp256k1::{Message, Secp256k1};
 
 use crate::hash_message;
+use crate::types::payload::SignedPayload;
 
 pub struct Identity {
     pub key_location: PathBuf,
@@ -26,6 +27,12 @@ impl Identity {
         PeerId::from(self.keypair.public())
     }
 
+    /// Hex encoded secp256k1 public key that identifies the user in requests. Other users grant access to it.
+    pub fn get_user_public_key(&self) -> String {
+        let secp256k1_key_pair = self.keypair.clone().try_into_secp256k1().expect("User keys are secp256k1 keys");
+        hex::encode(secp256k1_key_pair.public().to_bytes())
+    }
+
     pub fn sign(secret_key: Vec<u8>, message: String) -> Vec<u8> {
         let secp256k1 = Secp256k1::new();
         let message = Message::from_digest(<[u8; 32]>::try_from(hash_message(&message)).unwrap());
@@ -37,6 +44,11 @@ impl Identity {
         signature.serialize().as_slice().to_vec()
     }
 
+    /// Signs the canonical encoding of a request payload.
+    pub fn sign_payload(secret_key: Vec<u8>, payload: &SignedPayload) -> Vec<u8> {
+        Self::sign(secret_key, payload.to_message())
+    }
+
 }
 
 pub fn get_key_pair(key_location: &PathBuf) -> Keypair {
@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+
+use hex::encode;
+use serde::{Deserialize, Serialize};
+
+use crate::hash_message_u8;
+
+/// Depth of the tree below the root. Keys are spread over `2^MERKLE_DEPTH` buckets by the first byte of
+/// their sha256, so a key always lives at the same position and two trees can be diffed subtree by subtree.
+pub const MERKLE_DEPTH: u8 = 8;
+
+const LEAF_TAG: u8 = 0;
+const BUCKET_TAG: u8 = 1;
+const NODE_TAG: u8 = 2;
+
+/// Merkle tree over a user's `(record key, value hash)` pairs.
+///
+/// Leaves are grouped into fixed buckets instead of being laid out in key order, so adding a key only changes
+/// the hashes on the path of its bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    /// `levels[depth]` holds the `2^depth` node hashes at that depth. `levels[0]` is the root.
+    levels: Vec<Vec<[u8; 32]>>,
+    buckets: Vec<Vec<(String, String)>>,
+}
+
+impl MerkleTree {
+    /// Builds the tree from record keys mapped to the hex encoded sha256 of their stored value.
+    pub fn new(leaves: &BTreeMap<String, String>) -> Self {
+        let mut buckets = vec![Vec::new(); 1 << MERKLE_DEPTH];
+        for (record_key, value_hash) in leaves {
+            buckets[bucket_index(record_key)].push((record_key.clone(), value_hash.clone()));
+        }
+
+        let mut levels = vec![buckets.iter().map(|bucket| bucket_hash(bucket)).collect::<Vec<[u8; 32]>>()];
+        while levels.last().unwrap().len() > 1 {
+            let parent_level = levels.last().unwrap().chunks(2).map(|pair| node_hash(&pair[0], &pair[1])).collect();
+            levels.push(parent_level);
+        }
+        levels.reverse();
+
+        Self { levels, buckets }
+    }
+
+    pub fn root(&self) -> String {
+        encode(self.levels[0][0])
+    }
+
+    pub fn node(&self, depth: u8, index: u32) -> Option<String> {
+        self.levels.get(depth as usize)?.get(index as usize).map(encode)
+    }
+
+    pub fn subtree(&self, depth: u8, index: u32) -> Option<MerkleSubtree> {
+        let hash = self.node(depth, index)?;
+        let (children, entries) = if depth == MERKLE_DEPTH {
+            (vec![], self.buckets[index as usize].clone())
+        } else {
+            let children = vec![self.node(depth + 1, index * 2)?, self.node(depth + 1, index * 2 + 1)?];
+            (children, vec![])
+        };
+
+        Some(MerkleSubtree { depth, index, hash, children, entries })
+    }
+
+    pub fn proof(&self, record_key: &str) -> Option<MerkleProof> {
+        let bucket = bucket_index(record_key);
+        let bucket_entries = self.buckets[bucket].clone();
+        let value_hash = bucket_entries.iter().find(|(key, _)| key == record_key)?.1.clone();
+
+        let mut siblings = Vec::with_capacity(MERKLE_DEPTH as usize);
+        let mut index = bucket;
+        for depth in (1..=MERKLE_DEPTH as usize).rev() {
+            siblings.push(encode(self.levels[depth][index ^ 1]));
+            index /= 2;
+        }
+
+        Some(MerkleProof {
+            root: self.root(),
+            record_key: record_key.to_string(),
+            value_hash,
+            bucket_entries,
+            siblings,
+        })
+    }
+
+    /// Entries of the bucket at `index`, for comparing against a remote [`MerkleSubtree`].
+    pub fn bucket(&self, index: u32) -> &[(String, String)] {
+        &self.buckets[index as usize]
+    }
+}
+
+/// Proof that a record key and value hash are part of the tree with the given root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub root: String,
+    pub record_key: String,
+    pub value_hash: String,
+    /// All entries of the bucket the record key falls in.
+    pub bucket_entries: Vec<(String, String)>,
+    /// Sibling hashes from the bucket up to the root.
+    pub siblings: Vec<String>,
+}
+
+impl MerkleProof {
+    /// Checks that the proof hashes up to its own root.
+    pub fn verify(&self) -> bool {
+        if self.siblings.len() != MERKLE_DEPTH as usize {
+            return false;
+        }
+        if !self.bucket_entries.iter().any(|(key, value_hash)| key == &self.record_key && value_hash == &self.value_hash) {
+            return false;
+        }
+        let bucket = bucket_index(&self.record_key);
+        if self.bucket_entries.iter().any(|(key, _)| bucket_index(key) != bucket) {
+            return false;
+        }
+
+        let mut hash = bucket_hash(&self.bucket_entries);
+        let mut index = bucket;
+        for sibling in &self.siblings {
+            let sibling: [u8; 32] = match hex::decode(sibling).ok().and_then(|sibling| sibling.try_into().ok()) {
+                Some(sibling) => { sibling }
+                None => { return false; }
+            };
+            hash = if index % 2 == 0 { node_hash(&hash, &sibling) } else { node_hash(&sibling, &hash) };
+            index /= 2;
+        }
+        encode(hash) == self.root
+    }
+
+    /// Checks the proof against a root the caller already trusts, e.g. one pinned after its own write.
+    pub fn verify_against(&self, trusted_root: &str) -> bool {
+        self.root == trusted_root && self.verify()
+    }
+
+    /// Checks that `stored_value` is the value the proof was issued for.
+    pub fn matches_value(&self, stored_value: &[u8]) -> bool {
+        value_hash(stored_value) == self.value_hash
+    }
+}
+
+/// A node of a user's tree, used to diff the tree one level at a time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleSubtree {
+    pub depth: u8,
+    pub index: u32,
+    pub hash: String,
+    /// Hashes of the two children. Empty for buckets.
+    pub children: Vec<String>,
+    /// Entries of the bucket. Only set at [`MERKLE_DEPTH`].
+    pub entries: Vec<(String, String)>,
+}
+
+/// Hex encoded sha256 of a stored record value, as used for the tree leaves.
+pub fn value_hash(stored_value: &[u8]) -> String {
+    encode(hash_message_u8(stored_value.to_vec()))
+}
+
+pub fn bucket_index(record_key: &str) -> usize {
+    hash_message_u8(record_key.as_bytes().to_vec())[0] as usize >> (8 - MERKLE_DEPTH)
+}
+
+fn leaf_hash(record_key: &str, value_hash: &str) -> [u8; 32] {
+    let mut leaf = vec![LEAF_TAG];
+    leaf.extend_from_slice(&(record_key.len() as u32).to_le_bytes());
+    leaf.extend_from_slice(record_key.as_bytes());
+    leaf.extend_from_slice(value_hash.as_bytes());
+    hash_message_u8(leaf)
+}
+
+fn bucket_hash(entries: &[(String, String)]) -> [u8; 32] {
+    let mut sorted_entries = entries.to_vec();
+    sorted_entries.sort();
+
+    let mut bucket = vec![BUCKET_TAG];
+    for (record_key, value_hash) in &sorted_entries {
+        bucket.extend_from_slice(&leaf_hash(record_key, value_hash));
+    }
+    hash_message_u8(bucket)
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut node = vec![NODE_TAG];
+    node.extend_from_slice(left);
+    node.extend_from_slice(right);
+    hash_message_u8(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves() -> BTreeMap<String, String> {
+        (0..20).map(|index| (format!("key-{}", index), value_hash(format!("value-{}", index).as_bytes()))).collect()
+    }
+
+    #[test]
+    fn proofs_of_stored_keys_verify() {
+        let tree = MerkleTree::new(&leaves());
+        for (record_key, value_hash) in leaves() {
+            let proof = tree.proof(&record_key).unwrap();
+            assert_eq!(proof.value_hash, value_hash);
+            assert_eq!(proof.siblings.len(), MERKLE_DEPTH as usize);
+            assert!(proof.verify());
+            assert!(proof.verify_against(&tree.root()));
+        }
+        assert!(tree.proof("missing").is_none());
+    }
+
+    #[test]
+    fn tampered_proofs_do_not_verify() {
+        let tree = MerkleTree::new(&leaves());
+        let proof = tree.proof("key-3").unwrap();
+
+        let mut wrong_value = proof.clone();
+        wrong_value.value_hash = value_hash(b"other");
+        assert!(!wrong_value.verify());
+
+        let mut wrong_sibling = proof.clone();
+        wrong_sibling.siblings[0] = encode([0u8; 32]);
+        assert!(!wrong_sibling.verify());
+
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify());
+
+        assert!(proof.matches_value(b"value-3"));
+        assert!(!proof.matches_value(b"value-4"));
+    }
+
+    #[test]
+    fn self_consistent_proofs_fail_against_a_trusted_root() {
+        let tree = MerkleTree::new(&leaves());
+
+        let mut forged_leaves = leaves();
+        forged_leaves.insert("key-3".to_string(), value_hash(b"forged"));
+        let forged_proof = MerkleTree::new(&forged_leaves).proof("key-3").unwrap();
+
+        assert!(forged_proof.verify());
+        assert!(!forged_proof.verify_against(&tree.root()));
+    }
+
+    #[test]
+    fn changing_a_key_only_changes_its_path() {
+        let tree = MerkleTree::new(&leaves());
+        let mut changed_leaves = leaves();
+        changed_leaves.insert("key-5".to_string(), value_hash(b"changed"));
+        let changed_tree = MerkleTree::new(&changed_leaves);
+
+        assert_ne!(tree.root(), changed_tree.root());
+        let bucket = bucket_index("key-5") as u32;
+        for index in 0..1u32 << MERKLE_DEPTH {
+            let same = tree.node(MERKLE_DEPTH, index) == changed_tree.node(MERKLE_DEPTH, index);
+            assert_eq!(same, index != bucket);
+        }
+        assert_eq!(changed_tree.subtree(MERKLE_DEPTH, bucket).unwrap().entries, changed_tree.bucket(bucket));
+    }
+}
@@ -1,10 +1,26 @@
 pub mod error;
 pub mod actions;
 pub mod identity;
+pub mod encoding;
+pub mod value;
+pub mod merkle;
+pub mod payload;
+pub mod encryption;
 
-pub use actions::GetRequest;
-pub use actions::PutRequest;
-pub use error::DisruptedDataError;
+pub use actions::DeleteRequest;
+pub use actions::{GetRequest, VersionSelector};
+pub use actions::{GrantRequest, GrantScope};
+pub use actions::{GetPublicRequest, PublicRecord, Visibility};
+pub use actions::ListRequest;
+pub use actions::ListResult;
+pub use actions::ProofRequest;
+pub use actions::SubtreeRequest;
+pub use encryption::EncryptedValue;
+pub use merkle::{MerkleProof, MerkleSubtree, MerkleTree};
+pub use payload::SignedPayload;
+pub use actions::{PutCondition, PutRequest};
+pub use actions::{HistoryRequest, RecordVersion};
+pub use error::{DisruptedDataError, ErrorKind};
 pub use identity::Identity;
 use sha2::digest::Update;
 use sha2::Digest;
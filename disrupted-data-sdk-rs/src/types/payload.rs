@@ -0,0 +1,182 @@
+use hex::{decode, encode};
+use secp256k1::schnorr::Signature;
+use secp256k1::{rand, Message, PublicKey, Secp256k1};
+
+use crate::types::actions::{PutCondition, VersionSelector, Visibility};
+use crate::{current_timestamp_millis, hash_message, hash_message_u8};
+
+/// Version of the signed payload layout. Bumping it invalidates every signature made for another version.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How far a request timestamp may be from the node's clock before the request is rejected. Nodes remember
+/// the nonces they have seen for this long, so a captured request cannot be replayed.
+pub const MAX_REQUEST_AGE_MILLIS: u64 = 5 * 60 * 1000;
+
+/// What the owner of a key signs for every request.
+///
+/// The payload names the action, the owner, the record key and the hash of the value, so a signature made for
+/// one request cannot be reused for another action, key, value or user. The timestamp and nonce make every
+/// signature unique, which lets nodes reject requests they have already served.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedPayload {
+	pub version: u32,
+	pub action: String,
+	/// Hex encoded public key of the owner, as sent in requests.
+	pub user_public_key: Vec<u8>,
+	pub record_key: String,
+	/// Hex encoded sha256 of the record value. Empty for actions without a value.
+	pub value_hash: String,
+	/// Action specific parameters, e.g. the prefix and limit of a listing.
+	pub arguments: Vec<String>,
+	pub timestamp: u64,
+	pub nonce: u64,
+}
+
+impl SignedPayload {
+	pub fn new(action: &str, user_public_key: &[u8], record_key: &str, timestamp: u64, nonce: u64) -> Self {
+		Self {
+			version: PROTOCOL_VERSION,
+			action: action.to_string(),
+			user_public_key: user_public_key.to_vec(),
+			record_key: record_key.to_string(),
+			value_hash: String::new(),
+			arguments: vec![],
+			timestamp,
+			nonce,
+		}
+	}
+
+	pub fn with_value(mut self, record_value: &[u8]) -> Self {
+		self.value_hash = encode(hash_message_u8(record_value.to_vec()));
+		self
+	}
+
+	pub fn with_arguments(mut self, arguments: Vec<String>) -> Self {
+		self.arguments = arguments;
+		self
+	}
+
+	/// Public puts are signed with a `public` argument, so nobody but the owner can publish a record.
+	pub fn with_visibility(self, visibility: Visibility) -> Self {
+		match visibility {
+			Visibility::Private => { self }
+			Visibility::Public => { self.with_arguments(vec!["public".to_string()]) }
+		}
+	}
+
+	/// Expiring puts are signed with a `ttl=<seconds>` argument, so nobody but the owner can change when the record
+	/// expires.
+	pub fn with_ttl(mut self, ttl: Option<u64>) -> Self {
+		if let Some(ttl) = ttl {
+			self.arguments.push(format!("ttl={}", ttl));
+		}
+		self
+	}
+
+	/// Conditional puts are signed with an `if_version=<version>` or `if_absent` argument, so a node cannot drop or
+	/// change the condition of a put.
+	pub fn with_condition(mut self, condition: Option<PutCondition>) -> Self {
+		match condition {
+			Some(PutCondition::IfVersion(version)) => { self.arguments.push(format!("if_version={}", version)) }
+			Some(PutCondition::IfAbsent) => { self.arguments.push("if_absent".to_string()) }
+			None => {}
+		}
+		self
+	}
+
+	/// Reads of an older version are signed with a `version=<version>` or `at=<timestamp>` argument, so a node
+	/// cannot serve another version than the one the reader asked for.
+	pub fn with_version_selector(mut self, version: Option<VersionSelector>) -> Self {
+		match version {
+			Some(VersionSelector::Version(version)) => { self.arguments.push(format!("version={}", version)) }
+			Some(VersionSelector::At(timestamp)) => { self.arguments.push(format!("at={}", timestamp)) }
+			None => {}
+		}
+		self
+	}
+
+	/// Reads of a chunk of a shared value are signed with a `chunk_of=<value key>` argument, so a node cannot check
+	/// the reader's grant against another key than the one the reader named.
+	pub fn with_value_key(mut self, value_key: Option<&str>) -> Self {
+		if let Some(value_key) = value_key {
+			self.arguments.push(format!("chunk_of={}", value_key));
+		}
+		self
+	}
+
+	/// Checks the owner's schnorr signature over the payload, e.g. of a public record served by a node.
+	pub fn verify(&self, signature: &[u8]) -> bool {
+		let public_key = match decode(&self.user_public_key).ok().and_then(|public_key| PublicKey::from_slice(&public_key).ok()) {
+			Some(public_key) => { public_key }
+			None => { return false }
+		};
+		let signature = match Signature::from_slice(signature) {
+			Ok(signature) => { signature }
+			Err(_) => { return false }
+		};
+		let message = Message::from_digest(hash_message(&self.to_message()));
+		Secp256k1::verification_only().verify_schnorr(&signature, &message, &public_key.x_only_public_key().0).is_ok()
+	}
+
+	/// Canonical encoding of the payload. Every field is written as `<length>:<field>`, so no two payloads
+	/// share an encoding however their fields are chosen.
+	pub fn to_message(&self) -> String {
+		let mut fields = vec![
+			"disrupted-data".to_string(),
+			self.version.to_string(),
+			self.action.clone(),
+			String::from_utf8_lossy(&self.user_public_key).to_string(),
+			self.record_key.clone(),
+			self.value_hash.clone(),
+			self.arguments.len().to_string(),
+		];
+		fields.extend(self.arguments.iter().cloned());
+		fields.push(self.timestamp.to_string());
+		fields.push(self.nonce.to_string());
+
+		fields.iter().map(|field| format!("{}:{}", field.len(), field)).collect()
+	}
+}
+
+/// Timestamp and random nonce for a new request.
+pub fn new_timestamp_and_nonce() -> (u64, u64) {
+	(current_timestamp_millis(), rand::random())
+}
+
+#[cfg(test)]
+mod tests {
+	use libp2p::identity::Keypair;
+
+	use super::*;
+	use crate::GetRequest;
+
+	#[test]
+	fn prefixes_every_field_with_its_length() {
+		let signed_payload = SignedPayload::new("put", b"02ab", "key", 1_700_000_000_000, 42)
+			.with_arguments(vec!["public".to_string(), "ttl=60".to_string()]);
+
+		assert_eq!(signed_payload.to_message(), "14:disrupted-data1:13:put4:02ab3:key0:1:26:public6:ttl=6013:17000000000002:42");
+	}
+
+	#[test]
+	fn field_boundaries_change_the_message() {
+		let signed_payload = SignedPayload::new("get", b"02ab", "a", 1, 2).with_arguments(vec!["b".to_string()]);
+		let shifted_payload = SignedPayload::new("get", b"02ab", "ab", 1, 2);
+
+		assert_ne!(signed_payload.to_message(), shifted_payload.to_message());
+	}
+
+	#[test]
+	fn signs_the_version_a_read_asks_for() {
+		let keypair = Keypair::generate_secp256k1();
+		let get_request = GetRequest::new(&keypair, "key".to_string()).at_version(&keypair, 3);
+		assert!(get_request.signed_payload().verify(&get_request.signature));
+
+		let mut tampered_request = get_request.clone();
+		tampered_request.version = Some(VersionSelector::Version(2));
+		assert!(!tampered_request.signed_payload().verify(&tampered_request.signature));
+
+		tampered_request.version = None;
+		assert!(!tampered_request.signed_payload().verify(&tampered_request.signature));
+	}
+}
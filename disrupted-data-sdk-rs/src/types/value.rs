@@ -0,0 +1,175 @@
+use hex::encode;
+use serde::{Deserialize, Serialize};
+
+use crate::types::encryption::EncryptedValue;
+use crate::{hash_message_u8, DisruptedDataError, ErrorKind};
+
+/// Largest value stored in a single record. Kademlia nodes reject records of 65 KiB and above, so this
+/// leaves room for the node's record framing.
+pub const CHUNK_SIZE: usize = 60 * 1024;
+
+/// Key prefix for the content addressed chunks of large values.
+pub const CHUNK_KEY_PREFIX: &str = ".chunks/";
+
+/// The hex encoded sha256 a chunk key names, or `None` if `record_key` is not a chunk key.
+pub fn chunk_hash(record_key: &str) -> Option<&str> {
+    record_key.strip_prefix(CHUNK_KEY_PREFIX)?.rsplit_once('/').map(|(_, chunk_hash)| chunk_hash)
+}
+
+/// Whether `chunk_key` names a chunk of the value stored under `record_key`, see [`ChunkManifest::chunk_key`].
+pub fn is_chunk_of(chunk_key: &str, record_key: &str) -> bool {
+    chunk_key.strip_prefix(CHUNK_KEY_PREFIX)
+        .and_then(|chunk_path| chunk_path.rsplit_once('/'))
+        .is_some_and(|(record_key_hash, _)| record_key_hash == encode(hash_message_u8(record_key.as_bytes().to_vec())))
+}
+
+/// Prefix of values that are not stored as plain bytes.
+const STORED_VALUE_MAGIC: &[u8; 4] = b"DDV1";
+
+/// What the SDK writes as the record value for a user key.
+///
+/// Small values are written as the plain bytes. Values larger than [`CHUNK_SIZE`] are split into chunks and
+/// the user key holds a [`ChunkManifest`] instead. Values encrypted on the client are stored as an
+/// [`EncryptedValue`], so plaintext and encrypted records can live side by side.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StoredValue {
+    Inline(Vec<u8>),
+    Chunked(ChunkManifest),
+    Encrypted(EncryptedValue),
+}
+
+impl StoredValue {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        if let Some(encoded_value) = bytes.strip_prefix(STORED_VALUE_MAGIC) {
+            if let Ok(stored_value) = bincode::deserialize::<StoredValue>(encoded_value) {
+                return stored_value;
+            }
+        }
+        StoredValue::Inline(bytes.to_vec())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            // Plain values are written as is, unless they happen to start with the magic prefix.
+            StoredValue::Inline(value) if !value.starts_with(STORED_VALUE_MAGIC) => { value.clone() }
+            _ => {
+                let mut bytes = STORED_VALUE_MAGIC.to_vec();
+                bytes.append(&mut bincode::serialize(self).expect("Stored values are always serializable"));
+                bytes
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub total_size: u64,
+    /// Hex encoded sha256 of the whole value.
+    pub sha256: String,
+    /// Hex encoded sha256 of every chunk, in order.
+    pub chunks: Vec<String>,
+}
+
+impl ChunkManifest {
+    /// Splits the value of `record_key` into chunks, returning the manifest and the `(chunk key, chunk)` pairs to
+    /// store.
+    pub fn split(record_key: &str, value: &[u8]) -> (Self, Vec<(String, Vec<u8>)>) {
+        let chunks: Vec<(String, Vec<u8>)> = value.chunks(CHUNK_SIZE)
+            .map(|chunk| (encode(hash_message_u8(chunk.to_vec())), chunk.to_vec()))
+            .collect();
+
+        let manifest = Self {
+            total_size: value.len() as u64,
+            sha256: encode(hash_message_u8(value.to_vec())),
+            chunks: chunks.iter().map(|(chunk_hash, _)| chunk_hash.clone()).collect(),
+        };
+        let keyed_chunks = chunks.into_iter()
+            .map(|(chunk_hash, chunk)| (Self::chunk_key(record_key, &chunk_hash), chunk))
+            .collect();
+
+        (manifest, keyed_chunks)
+    }
+
+    /// Chunks are addressed by their sha256 under the key of the value they belong to, so removing the chunks of
+    /// one value never breaks another value that happens to contain the same chunk.
+    pub fn chunk_key(record_key: &str, chunk_hash: &str) -> String {
+        format!("{}{}/{}", CHUNK_KEY_PREFIX, encode(hash_message_u8(record_key.as_bytes().to_vec())), chunk_hash)
+    }
+
+    pub fn chunk_keys(&self, record_key: &str) -> Vec<String> {
+        self.chunks.iter().map(|chunk_hash| Self::chunk_key(record_key, chunk_hash)).collect()
+    }
+
+    /// Reassembles the value from its chunks, checking every chunk and the result against the manifest.
+    pub fn join(&self, chunks: Vec<Vec<u8>>) -> Result<Vec<u8>, DisruptedDataError> {
+        if chunks.len() != self.chunks.len() {
+            return Err(DisruptedDataError::new(ErrorKind::Integrity, format!("Expected {} chunks but got {}", self.chunks.len(), chunks.len())));
+        }
+
+        let mut value = Vec::with_capacity(self.total_size as usize);
+        for (expected_hash, chunk) in self.chunks.iter().zip(chunks) {
+            if &encode(hash_message_u8(chunk.clone())) != expected_hash {
+                return Err(DisruptedDataError::new(ErrorKind::Integrity, format!("Chunk {} failed verification", expected_hash)));
+            }
+            value.extend_from_slice(&chunk);
+        }
+
+        if value.len() as u64 != self.total_size || encode(hash_message_u8(value.clone())) != self.sha256 {
+            return Err(DisruptedDataError::new(ErrorKind::Integrity, "Reassembled value failed verification".to_string()));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_and_join_round_trip() {
+        let value: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|index| (index % 251) as u8).collect();
+        let (manifest, chunks) = ChunkManifest::split("video", &value);
+
+        assert_eq!(manifest.total_size, value.len() as u64);
+        assert_eq!(manifest.chunks.len(), 3);
+        assert_eq!(chunks.iter().map(|(chunk_key, _)| chunk_key.clone()).collect::<Vec<_>>(), manifest.chunk_keys("video"));
+        assert_eq!(manifest.join(chunks.into_iter().map(|(_, chunk)| chunk).collect()).unwrap(), value);
+    }
+
+    #[test]
+    fn chunk_keys_name_their_content_under_the_value_key() {
+        let (manifest, chunks) = ChunkManifest::split("video", &[7u8; CHUNK_SIZE + 1]);
+
+        for ((chunk_key, chunk), expected_hash) in chunks.iter().zip(&manifest.chunks) {
+            assert_eq!(chunk_hash(chunk_key), Some(expected_hash.as_str()));
+            assert_eq!(expected_hash, &encode(hash_message_u8(chunk.clone())));
+        }
+        assert_ne!(manifest.chunk_keys("video"), manifest.chunk_keys("backup"));
+        assert_eq!(chunk_hash("video"), None);
+        for chunk_key in manifest.chunk_keys("video") {
+            assert!(is_chunk_of(&chunk_key, "video"));
+            assert!(!is_chunk_of(&chunk_key, "backup"));
+        }
+        assert!(!is_chunk_of("video", "video"));
+    }
+
+    #[test]
+    fn join_rejects_altered_or_missing_chunks() {
+        let (manifest, chunks) = ChunkManifest::split("video", &[1u8; CHUNK_SIZE + 1]);
+        let mut chunks: Vec<Vec<u8>> = chunks.into_iter().map(|(_, chunk)| chunk).collect();
+
+        assert_eq!(manifest.join(chunks[..1].to_vec()).unwrap_err().kind, ErrorKind::Integrity);
+        chunks[1][0] ^= 1;
+        assert_eq!(manifest.join(chunks).unwrap_err().kind, ErrorKind::Integrity);
+    }
+
+    #[test]
+    fn inline_values_starting_with_the_magic_are_escaped() {
+        let value = [STORED_VALUE_MAGIC.as_slice(), b"not a manifest"].concat();
+        let stored_value = StoredValue::Inline(value.clone());
+
+        assert_ne!(stored_value.to_bytes(), value);
+        assert_eq!(StoredValue::from_bytes(&stored_value.to_bytes()), stored_value);
+        assert_eq!(StoredValue::from_bytes(b"plain"), StoredValue::Inline(b"plain".to_vec()));
+    }
+}
@@ -1,10 +1,12 @@
 extern crate core;
 
-use std::fs;
+use std::{fs, process};
 
 use clap::Parser;
 use tokio::sync::mpsc;
 
+use disrupted_data_sdk_rs::{DisruptedDataError, ErrorKind};
+
 use p2p::{FromDisruptedDataSwarmEvent, ToDisruptedDataSwarmEvent};
 
 use crate::p2p::DisruptedDataSwarm;
@@ -20,19 +22,27 @@ async fn main() {
     let (from_swarm_sender, mut from_swarm_receiver) = mpsc::channel::<FromDisruptedDataSwarmEvent>(50);
     let (to_swarm_sender, mut to_swarm_receiver) = mpsc::channel::<ToDisruptedDataSwarmEvent>(50);
 
-    let mut swarm = DisruptedDataSwarm::new(get_node_config(), from_swarm_sender.clone(), to_swarm_receiver);
-
-    swarm.start().await;
+    let swarm = get_node_config().and_then(|node_config| {
+        DisruptedDataSwarm::new(node_config, from_swarm_sender.clone(), to_swarm_receiver)
+    });
+    match swarm {
+        Ok(mut swarm) => { swarm.start().await }
+        Err(error) => {
+            eprintln!("Could not start node: {}", error.message);
+            process::exit(1);
+        }
+    }
 }
 
-fn get_node_config() -> NodeConfig {
+fn get_node_config() -> Result<NodeConfig, DisruptedDataError> {
     let args = Args::parse();
     match args.key_location {
-        None => { NodeConfig::empty() }
+        None => { Ok(NodeConfig::empty()) }
         Some(config_file_location) => {
-            let config_file_string = fs::read_to_string(config_file_location);
-            let config: NodeConfig = toml::from_str(config_file_string.unwrap().as_str()).unwrap();
-            config
+            let config_file_string = fs::read_to_string(&config_file_location)
+                .map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Could not read config file {:?}: {}", config_file_location, error)))?;
+            toml::from_str(config_file_string.as_str())
+                .map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Invalid config file {:?}: {}", config_file_location, error)))
         }
     }
 }
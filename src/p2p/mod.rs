@@ -1,13 +1,21 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use libp2p::kad::{Record, RecordKey};
 use libp2p::request_response::InboundRequestId;
 use serde::{Deserialize, Serialize};
 
-use disrupted_data_sdk_rs::{ActionResult, Actions, DisruptedDataError};
+use disrupted_data_sdk_rs::{current_timestamp_millis, ActionResult, Actions, DisruptedDataError, ErrorKind, ListResult, MerkleTree, PutCondition, Visibility};
+use hex::decode;
+
+use record::{DataRecord, GrantRecord, OwnerSignature};
 pub use swarm::DisruptedDataSwarm;
 
 pub mod node;
 mod protocol;
+mod record;
+mod store;
 mod swarm;
+mod validation;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FromDisruptedDataSwarmEvent {
@@ -31,10 +39,116 @@ pub enum ToDisruptedDataSwarmEvent {
 
 }
 
+/// Prefix written in front of every encoded user index record, followed by [`USER_RECORD_FORMAT`], see
+/// [`EncodedUser`]. Index records written before it was introduced join their entries with `|` and are still read,
+/// see [`decode_legacy_entries`].
+const USER_RECORD_MAGIC: &[u8; 3] = b"DDU";
+
+/// Format of the index records this node writes. It is bumped whenever [`IndexEntry`] gains a variant, so that
+/// nodes which do not know the variant reject the record instead of misreading it. Format 2 added
+/// [`IndexEntry::ExpiringPut`] and format 3 [`IndexEntry::ConditionalPut`]. Variants are only ever appended, so every
+/// format is read with the current layout.
+const USER_RECORD_FORMAT: u8 = b'3';
+
+/// Prefix of the first entry of a legacy user record, holding the Merkle root of the user's data.
+const MERKLE_ROOT_PREFIX: &str = "merkle_root=";
+
+/// Value hash field of a legacy index entry for a deleted key.
+const DELETED_ENTRY_MARKER: &str = "-";
+
+/// Suffix of the value hash field of a legacy index entry for a public key.
+const PUBLIC_ENTRY_SUFFIX: &str = "/public";
+
+/// How long the signed delete of a key stays in the index, in milliseconds. A copy of the index that still lists
+/// the key and only shows up after its delete was dropped brings the key back, so this is far longer than peers
+/// take to republish their records.
+const DELETED_KEY_RETENTION_MILLIS: u64 = 30 * 24 * 60 * 60 * 1000;
+
+/// Binary form of a [`User`] index record, written after [`USER_RECORD_MAGIC`]. Keys are length prefixed, so they
+/// may hold any character.
+#[derive(Serialize, Deserialize, Debug)]
+struct EncodedUser {
+    /// Root of the Merkle tree over the user's data, for peers inspecting the record.
+    merkle_root: String,
+    entries: Vec<IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum IndexEntry {
+    Put {
+        data_record_key: String,
+        value_hash: String,
+        visibility: Visibility,
+        signature: EntrySignature,
+    },
+    Deleted {
+        data_record_key: String,
+        signature: EntrySignature,
+    },
+    /// Key written before index entries were signed, with the hash of its value if it was recorded.
+    Unsigned {
+        data_record_key: String,
+        value_hash: Option<String>,
+    },
+    /// Put of a value that expires `ttl` seconds after it was signed.
+    ExpiringPut {
+        data_record_key: String,
+        value_hash: String,
+        visibility: Visibility,
+        ttl: u64,
+        signature: EntrySignature,
+    },
+    /// Put that was only to be stored under a condition, which the owner signed along with the value.
+    ConditionalPut {
+        data_record_key: String,
+        value_hash: String,
+        visibility: Visibility,
+        ttl: Option<u64>,
+        condition: PutCondition,
+        signature: EntrySignature,
+    },
+}
+
+/// [`OwnerSignature`] of an index entry. The owner's public key is left out because it is the key of the index
+/// record, so an entry cannot claim to be signed by anyone else.
+#[derive(Serialize, Deserialize, Debug)]
+struct EntrySignature {
+    timestamp: u64,
+    nonce: u64,
+    signature: Vec<u8>,
+}
+
+impl EntrySignature {
+    fn to_owner_signature(&self, user_key: &str) -> OwnerSignature {
+        OwnerSignature { user_public_key: user_key.as_bytes().to_vec(), timestamp: self.timestamp, nonce: self.nonce, signature: self.signature.clone() }
+    }
+}
+
+impl From<&OwnerSignature> for EntrySignature {
+    fn from(owner_signature: &OwnerSignature) -> Self {
+        EntrySignature { timestamp: owner_signature.timestamp, nonce: owner_signature.nonce, signature: owner_signature.signature.clone() }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct User {
     pub key: String,
     pub data_record_keys: Vec<String>,
+    /// Hex encoded sha256 of the value stored under each data record key. Keys written before values were
+    /// hashed have no entry.
+    pub value_hashes: BTreeMap<String, String>,
+    /// Owner signature of the put that stored each key, so peers can check that the index lists only keys
+    /// the owner wrote. Keys written before index entries were signed have no entry.
+    pub signatures: BTreeMap<String, OwnerSignature>,
+    /// Owner signature of the delete of every key removed from the index. They are kept so that an older
+    /// copy of the index still listing the key can be told apart from a newer one.
+    pub deleted_keys: BTreeMap<String, OwnerSignature>,
+    /// Keys the owner published. Their index signature covers the visibility.
+    pub public_keys: BTreeSet<String>,
+    /// Seconds from the put of each expiring key until its value expires. Their index signature covers the TTL.
+    pub ttls: BTreeMap<String, u64>,
+    /// Condition of the put that stored each key written with one. Their index signature covers the condition.
+    pub conditions: BTreeMap<String, PutCondition>,
 }
 
 impl User {
@@ -42,30 +156,170 @@ impl User {
         Self {
             key: String::from_utf8(key.to_vec()).expect("Invalid key when creating new user"),
             data_record_keys: vec![],
+            value_hashes: BTreeMap::new(),
+            signatures: BTreeMap::new(),
+            deleted_keys: BTreeMap::new(),
+            public_keys: BTreeSet::new(),
+            ttls: BTreeMap::new(),
+            conditions: BTreeMap::new(),
+        }
+    }
+
+    /// Legacy index records have no magic prefix, so they are told apart from data and grant records by elimination.
+    pub(crate) fn is_user_record(bytes: &[u8]) -> bool {
+        !DataRecord::is_data_record(bytes) && !GrantRecord::is_grant_record(bytes)
+    }
+
+    /// Decodes a user's index record, failing instead of panicking on malformed records from other peers.
+    /// Legacy `|` joined records are read as well and are written back in the current encoding. Keys whose value
+    /// expired and deletes older than [`DELETED_KEY_RETENTION_MILLIS`] are left out.
+    pub(crate) fn decode(record: &Record) -> Result<Self, DisruptedDataError> {
+        let key = String::from_utf8(record.key.to_vec()).map_err(|_| DisruptedDataError::new(ErrorKind::InvalidRequest, "Invalid user key".to_string()))?;
+        let mut user = Self::new(&RecordKey::new(&key));
+
+        match record.value.strip_prefix(USER_RECORD_MAGIC).and_then(<[u8]>::split_first) {
+            Some((b'1'..=USER_RECORD_FORMAT, encoded_user)) => {
+                let encoded_user: EncodedUser = bincode::deserialize(encoded_user)
+                    .map_err(|_| DisruptedDataError::new(ErrorKind::InvalidRequest, "Invalid user record"))?;
+                decode_entries(&mut user, encoded_user.entries);
+            }
+            _ => {
+                let raw_data_record_keys = String::from_utf8(record.value.clone()).map_err(|_| DisruptedDataError::new(ErrorKind::InvalidRequest, "Invalid data record keys".to_string()))?;
+                decode_legacy_entries(&mut user, &raw_data_record_keys);
+            }
+        }
+        user.remove_expired_keys();
+        user.remove_expired_deletes();
+        Ok(user)
+    }
+
+    pub fn add_data_record(&mut self, data_record_key: String, value_hash: String, owner_signature: OwnerSignature, visibility: Visibility, ttl: Option<u64>, condition: Option<PutCondition>) {
+        if !self.data_record_keys.contains(&data_record_key) {
+            self.data_record_keys.push(data_record_key.clone());
+        }
+        match visibility {
+            Visibility::Public => { self.public_keys.insert(data_record_key.clone()); }
+            Visibility::Private => { self.public_keys.remove(&data_record_key); }
+        }
+        match ttl {
+            Some(ttl) => { self.ttls.insert(data_record_key.clone(), ttl); }
+            None => { self.ttls.remove(&data_record_key); }
+        }
+        match condition {
+            Some(condition) => { self.conditions.insert(data_record_key.clone(), condition); }
+            None => { self.conditions.remove(&data_record_key); }
+        }
+        self.value_hashes.insert(data_record_key.clone(), value_hash);
+        self.signatures.insert(data_record_key.clone(), owner_signature);
+        self.deleted_keys.remove(&data_record_key);
+    }
+
+    pub fn remove_data_record_key(&mut self, data_record_key: &String, owner_signature: OwnerSignature) {
+        self.forget_data_record_key(data_record_key);
+        self.deleted_keys.insert(data_record_key.clone(), owner_signature);
+    }
+
+    /// Removes the keys whose value expired. Unlike a delete, nothing is kept about them, since every copy of the
+    /// index drops them as well once it is read.
+    fn remove_expired_keys(&mut self) {
+        let now = current_timestamp_millis();
+        let expired_keys: Vec<String> = self.ttls.keys()
+            .filter(|data_record_key| self.expires_at(data_record_key).is_some_and(|expires_at| expires_at <= now))
+            .cloned()
+            .collect();
+        for data_record_key in &expired_keys {
+            self.forget_data_record_key(data_record_key);
         }
     }
-    pub fn add_data_record_keys(&mut self, data_record_key: String) {
-        self.data_record_keys.push(data_record_key)
+
+    /// Drops the deletes signed more than [`DELETED_KEY_RETENTION_MILLIS`] ago, so the index does not grow with
+    /// every key the user ever deleted. Their versions can no longer be read back once the delete is dropped.
+    fn remove_expired_deletes(&mut self) {
+        let oldest_kept_timestamp = current_timestamp_millis().saturating_sub(DELETED_KEY_RETENTION_MILLIS);
+        self.deleted_keys.retain(|_, owner_signature| owner_signature.timestamp >= oldest_kept_timestamp);
+    }
+
+    fn forget_data_record_key(&mut self, data_record_key: &String) {
+        self.data_record_keys.retain(|existing_key| existing_key != data_record_key);
+        self.value_hashes.remove(data_record_key);
+        self.signatures.remove(data_record_key);
+        self.public_keys.remove(data_record_key);
+        self.ttls.remove(data_record_key);
+        self.conditions.remove(data_record_key);
+    }
+
+    /// Unix time in milliseconds at which the value of `data_record_key` expires, `None` if it never does.
+    fn expires_at(&self, data_record_key: &str) -> Option<u64> {
+        let ttl = self.ttls.get(data_record_key)?;
+        let owner_signature = self.signatures.get(data_record_key)?;
+        Some(owner_signature.timestamp.saturating_add(ttl.saturating_mul(1000)))
+    }
+
+    /// Merges another copy of the user's index into this one. Every key keeps its latest signed put or delete, so
+    /// copies written concurrently through different nodes converge without losing entries.
+    pub(crate) fn merge(&mut self, other: User) {
+        for data_record_key in &other.data_record_keys {
+            match other.signatures.get(data_record_key) {
+                Some(owner_signature) if self.is_older_than(data_record_key, owner_signature) => {
+                    let value_hash = other.value_hashes.get(data_record_key).cloned().unwrap_or_default();
+                    self.add_data_record(data_record_key.clone(), value_hash, owner_signature.clone(), other.visibility(data_record_key), other.ttls.get(data_record_key).copied(), other.conditions.get(data_record_key).copied());
+                }
+                Some(_) => {}
+                // Unsigned entries predate signed index entries, so any signed put or delete of the key is newer.
+                None if !self.data_record_keys.contains(data_record_key) && !self.deleted_keys.contains_key(data_record_key) => {
+                    self.data_record_keys.push(data_record_key.clone());
+                    if let Some(value_hash) = other.value_hashes.get(data_record_key) {
+                        self.value_hashes.insert(data_record_key.clone(), value_hash.clone());
+                    }
+                }
+                None => {}
+            }
+        }
+        for (data_record_key, owner_signature) in other.deleted_keys {
+            if self.is_older_than(&data_record_key, &owner_signature) {
+                self.remove_data_record_key(&data_record_key, owner_signature);
+            }
+        }
+    }
+
+    /// Whether `owner_signature` is for a later put or delete of the key than the one this index holds. Requests
+    /// signed in the same millisecond are ordered by nonce so every node picks the same one.
+    fn is_older_than(&self, data_record_key: &str, owner_signature: &OwnerSignature) -> bool {
+        match self.signatures.get(data_record_key).or_else(|| self.deleted_keys.get(data_record_key)) {
+            Some(existing_signature) => { (existing_signature.timestamp, existing_signature.nonce) < (owner_signature.timestamp, owner_signature.nonce) }
+            None => { true }
+        }
+    }
+
+    pub(crate) fn visibility(&self, data_record_key: &str) -> Visibility {
+        if self.public_keys.contains(data_record_key) { Visibility::Public } else { Visibility::Private }
     }
 
     pub(crate) fn contains_data_record_key(&self, key: String) -> bool {
         self.data_record_keys.contains(&key)
     }
-}
 
-impl From<Record> for User {
-    // fn from(value: (Vec<u8>, Record)) -> Self {
-    //     Self {
-    //         key: String::from_utf8(value[1].record.key.to_vec()).expect("Invalid record key"),
-    //         data_record_keys: split_raw_data_record_keys(value[0], value[1].record.value),
-    //     }
-    // }
-    fn from(record: Record) -> Self {
-        Self{
-            key: String::from_utf8(record.key.to_vec()).expect("Invalid user key"),
-            data_record_keys: split_raw_data_record_keys(record.value)
+    /// Merkle tree over the user's `(data record key, value hash)` pairs.
+    pub(crate) fn merkle_tree(&self) -> MerkleTree {
+        let leaves: BTreeMap<String, String> = self.data_record_keys.iter()
+            .map(|key| (key.clone(), self.value_hashes.get(key).cloned().unwrap_or_default()))
+            .collect();
+        MerkleTree::new(&leaves)
+    }
 
-        }
+    /// Page of the user's data record keys, sorted, matching `prefix` and sorting after `cursor`.
+    pub(crate) fn list_data_record_keys(&self, prefix: &Option<String>, cursor: &Option<String>, limit: usize) -> ListResult {
+        let mut matching_keys: Vec<&String> = self.data_record_keys.iter()
+            .filter(|key| prefix.as_ref().map_or(true, |prefix| key.starts_with(prefix.as_str())))
+            .filter(|key| cursor.as_ref().map_or(true, |cursor| *key > cursor))
+            .collect();
+        matching_keys.sort();
+        matching_keys.dedup();
+
+        let has_more_keys = matching_keys.len() > limit;
+        let keys: Vec<String> = matching_keys.into_iter().take(limit).cloned().collect();
+        let next_cursor = if has_more_keys { keys.last().cloned() } else { None };
+        ListResult { keys, next_cursor }
     }
 }
 
@@ -73,18 +327,284 @@ impl TryInto<Record> for User {
     type Error = DisruptedDataError;
 
     fn try_into(self) -> Result<Record, Self::Error> {
-        let raw_data_record_keys = join_data_record_keys(self.data_record_keys);
+        let encoded_user = encode_user(&self)?;
 
         let record_key = RecordKey::from(self.key.into_bytes());
-        Ok(Record::new(record_key, raw_data_record_keys))
+        Ok(Record::new(record_key, encoded_user))
+    }
+}
+
+fn encode_user(user: &User) -> Result<Vec<u8>, DisruptedDataError> {
+    let mut entries: Vec<IndexEntry> = user.data_record_keys.iter().map(|data_record_key| {
+        match (user.value_hashes.get(data_record_key), user.signatures.get(data_record_key)) {
+            (Some(value_hash), Some(owner_signature)) if user.conditions.contains_key(data_record_key) => {
+                IndexEntry::ConditionalPut {
+                    data_record_key: data_record_key.clone(),
+                    value_hash: value_hash.clone(),
+                    visibility: user.visibility(data_record_key),
+                    ttl: user.ttls.get(data_record_key).copied(),
+                    condition: user.conditions[data_record_key],
+                    signature: EntrySignature::from(owner_signature),
+                }
+            }
+            (Some(value_hash), Some(owner_signature)) if user.ttls.contains_key(data_record_key) => {
+                IndexEntry::ExpiringPut {
+                    data_record_key: data_record_key.clone(),
+                    value_hash: value_hash.clone(),
+                    visibility: user.visibility(data_record_key),
+                    ttl: user.ttls[data_record_key],
+                    signature: EntrySignature::from(owner_signature),
+                }
+            }
+            (Some(value_hash), Some(owner_signature)) => {
+                IndexEntry::Put {
+                    data_record_key: data_record_key.clone(),
+                    value_hash: value_hash.clone(),
+                    visibility: user.visibility(data_record_key),
+                    signature: EntrySignature::from(owner_signature),
+                }
+            }
+            (value_hash, _) => { IndexEntry::Unsigned { data_record_key: data_record_key.clone(), value_hash: value_hash.cloned() } }
+        }
+    }).collect();
+    entries.extend(user.deleted_keys.iter().map(|(data_record_key, owner_signature)| {
+        IndexEntry::Deleted { data_record_key: data_record_key.clone(), signature: EntrySignature::from(owner_signature) }
+    }));
+
+    let encoded_user = EncodedUser { merkle_root: user.merkle_tree().root(), entries };
+    let mut bytes = USER_RECORD_MAGIC.to_vec();
+    bytes.push(USER_RECORD_FORMAT);
+    bytes.append(&mut bincode::serialize(&encoded_user).map_err(|error| DisruptedDataError::new(ErrorKind::Internal, format!("Could not encode user record: {}", error)))?);
+    Ok(bytes)
+}
+
+fn decode_entries(user: &mut User, entries: Vec<IndexEntry>) {
+    for entry in entries {
+        match entry {
+            IndexEntry::Put { data_record_key, value_hash, visibility, signature } => {
+                if visibility == Visibility::Public {
+                    user.public_keys.insert(data_record_key.clone());
+                }
+                user.value_hashes.insert(data_record_key.clone(), value_hash);
+                user.signatures.insert(data_record_key.clone(), signature.to_owner_signature(&user.key));
+                user.data_record_keys.push(data_record_key);
+            }
+            IndexEntry::ExpiringPut { data_record_key, value_hash, visibility, ttl, signature } => {
+                if visibility == Visibility::Public {
+                    user.public_keys.insert(data_record_key.clone());
+                }
+                user.ttls.insert(data_record_key.clone(), ttl);
+                user.value_hashes.insert(data_record_key.clone(), value_hash);
+                user.signatures.insert(data_record_key.clone(), signature.to_owner_signature(&user.key));
+                user.data_record_keys.push(data_record_key);
+            }
+            IndexEntry::ConditionalPut { data_record_key, value_hash, visibility, ttl, condition, signature } => {
+                if visibility == Visibility::Public {
+                    user.public_keys.insert(data_record_key.clone());
+                }
+                if let Some(ttl) = ttl {
+                    user.ttls.insert(data_record_key.clone(), ttl);
+                }
+                user.conditions.insert(data_record_key.clone(), condition);
+                user.value_hashes.insert(data_record_key.clone(), value_hash);
+                user.signatures.insert(data_record_key.clone(), signature.to_owner_signature(&user.key));
+                user.data_record_keys.push(data_record_key);
+            }
+            IndexEntry::Deleted { data_record_key, signature } => {
+                user.deleted_keys.insert(data_record_key, signature.to_owner_signature(&user.key));
+            }
+            IndexEntry::Unsigned { data_record_key, value_hash } => {
+                if let Some(value_hash) = value_hash {
+                    user.value_hashes.insert(data_record_key.clone(), value_hash);
+                }
+                user.data_record_keys.push(data_record_key);
+            }
+        }
     }
 }
 
-fn join_data_record_keys(data_record_keys: Vec<String>) -> Vec<u8> {
-    data_record_keys.join("|").into_bytes()
+/// Reads an index record written before [`USER_RECORD_MAGIC`]. Its entries are separated by `|`. The first entry
+/// carries the Merkle root. Keys with an owner signature are written as `key:value hash:timestamp:nonce:signature`,
+/// deleted keys with `-` as value hash and public keys with `/public` after the value hash.
+fn decode_legacy_entries(user: &mut User, raw_data_record_keys: &str) {
+    for entry in raw_data_record_keys.split('|') {
+        // Records written before the Merkle tree was added have no root entry and no value hashes. An empty index
+        // was written as an empty string.
+        if entry.is_empty() || entry.starts_with(MERKLE_ROOT_PREFIX) {
+            continue;
+        }
+        if let Some((data_record_key, value_hash, owner_signature)) = parse_signed_entry(&user.key, entry) {
+            if value_hash == DELETED_ENTRY_MARKER {
+                user.deleted_keys.insert(data_record_key, owner_signature);
+            } else {
+                if let Some(value_hash) = value_hash.strip_suffix(PUBLIC_ENTRY_SUFFIX) {
+                    user.public_keys.insert(data_record_key.clone());
+                    user.value_hashes.insert(data_record_key.clone(), value_hash.to_string());
+                } else {
+                    user.value_hashes.insert(data_record_key.clone(), value_hash);
+                }
+                user.data_record_keys.push(data_record_key.clone());
+                user.signatures.insert(data_record_key, owner_signature);
+            }
+            continue;
+        }
+        match entry.rsplit_once(':') {
+            Some((data_record_key, value_hash)) if is_value_hash(value_hash) => {
+                user.data_record_keys.push(data_record_key.to_string());
+                user.value_hashes.insert(data_record_key.to_string(), value_hash.to_string());
+            }
+            _ => { user.data_record_keys.push(entry.to_string()) }
+        }
+    }
 }
 
-fn split_raw_data_record_keys( raw_data_record_keys: Vec<u8>) -> Vec<String> {
-    let comma_delimited_data_record_keys = String::from_utf8(raw_data_record_keys).expect("Invalid data record keys");
-    comma_delimited_data_record_keys.split('|').map(|data_record_key| { data_record_key.to_string() }).collect()
-}
\ No newline at end of file
+fn parse_signed_entry(user_key: &str, entry: &str) -> Option<(String, String, OwnerSignature)> {
+    let mut fields = entry.rsplitn(5, ':');
+    let signature = decode(fields.next()?).ok()?;
+    let nonce = fields.next()?.parse().ok()?;
+    let timestamp = fields.next()?.parse().ok()?;
+    let value_hash = fields.next()?;
+    let data_record_key = fields.next()?;
+    if value_hash != DELETED_ENTRY_MARKER && !is_value_hash(value_hash.strip_suffix(PUBLIC_ENTRY_SUFFIX).unwrap_or(value_hash)) {
+        return None;
+    }
+
+    let owner_signature = OwnerSignature { user_public_key: user_key.as_bytes().to_vec(), timestamp, nonce, signature };
+    Some((data_record_key.to_string(), value_hash.to_string(), owner_signature))
+}
+
+fn is_value_hash(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|character| character.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::encode;
+
+    const USER_KEY: &str = "02bbddbec3adf4d700f80550a620eef4f1c7f502ed3259ff4170ba8eef23b6f40b";
+
+    fn owner_signature(timestamp: u64, nonce: u64) -> OwnerSignature {
+        OwnerSignature { user_public_key: USER_KEY.as_bytes().to_vec(), timestamp, nonce, signature: vec![timestamp as u8, nonce as u8] }
+    }
+
+    fn user_record(value: Vec<u8>) -> Record {
+        Record::new(RecordKey::new(&USER_KEY), value)
+    }
+
+    fn new_user() -> User {
+        User::new(&RecordKey::new(&USER_KEY))
+    }
+
+    #[test]
+    fn legacy_records_decode_and_are_written_back_in_the_current_encoding() {
+        let (value_hash, timestamp) = ("a".repeat(64), current_timestamp_millis());
+        let legacy_value = format!(
+            "{}root|hello:{}:{}:1:{}|public:{}/public:{}:2:{}|gone:-:{}:3:{}|hashed:{}|ancient",
+            MERKLE_ROOT_PREFIX,
+            value_hash, timestamp, encode(owner_signature(timestamp, 1).signature),
+            value_hash, timestamp, encode(owner_signature(timestamp, 2).signature),
+            timestamp, encode(owner_signature(timestamp, 3).signature),
+            value_hash
+        );
+        let user = User::decode(&user_record(legacy_value.into_bytes())).unwrap();
+
+        assert_eq!(user.data_record_keys, vec!["hello", "public", "hashed", "ancient"]);
+        assert_eq!(user.signatures["hello"], owner_signature(timestamp, 1));
+        assert_eq!(user.visibility("public"), Visibility::Public);
+        assert_eq!(user.deleted_keys["gone"], owner_signature(timestamp, 3));
+        assert_eq!(user.value_hashes.get("hashed"), Some(&value_hash));
+        assert!(!user.signatures.contains_key("ancient") && !user.value_hashes.contains_key("ancient"));
+
+        let record: Record = user.clone().try_into().unwrap();
+        assert!(record.value.starts_with(b"DDU3"));
+        assert_eq!(User::decode(&record).unwrap(), user);
+    }
+
+    #[test]
+    fn records_of_an_earlier_format_are_read() {
+        let mut user = new_user();
+        user.add_data_record("key".to_string(), "1".repeat(64), owner_signature(current_timestamp_millis(), 1), Visibility::Private, None, None);
+        let mut record: Record = user.clone().try_into().unwrap();
+        record.value[USER_RECORD_MAGIC.len()] = b'1';
+
+        assert_eq!(User::decode(&record).unwrap(), user);
+
+        record.value[USER_RECORD_MAGIC.len()] = b'9';
+        assert_ne!(User::decode(&record).ok(), Some(user));
+    }
+
+    #[test]
+    fn merge_keeps_the_latest_put_or_delete_of_every_key() {
+        let mut user = new_user();
+        user.add_data_record("deleted".to_string(), "1".repeat(64), owner_signature(10, 1), Visibility::Private, None, None);
+        user.add_data_record("kept".to_string(), "2".repeat(64), owner_signature(30, 1), Visibility::Private, None, None);
+        let mut other = new_user();
+        other.remove_data_record_key(&"deleted".to_string(), owner_signature(20, 1));
+        other.remove_data_record_key(&"kept".to_string(), owner_signature(20, 1));
+        other.add_data_record("added".to_string(), "3".repeat(64), owner_signature(20, 1), Visibility::Public, Some(60), None);
+
+        user.merge(other);
+
+        assert_eq!(user.data_record_keys, vec!["kept", "added"]);
+        assert_eq!(user.deleted_keys.keys().collect::<Vec<_>>(), vec!["deleted"]);
+        assert_eq!(user.visibility("added"), Visibility::Public);
+        assert_eq!(user.ttls.get("added"), Some(&60));
+    }
+
+    #[test]
+    fn writes_in_the_same_millisecond_are_ordered_by_nonce() {
+        let mut first = new_user();
+        first.add_data_record("key".to_string(), "1".repeat(64), owner_signature(10, 7), Visibility::Private, None, None);
+        let mut second = new_user();
+        second.add_data_record("key".to_string(), "2".repeat(64), owner_signature(10, 3), Visibility::Private, None, None);
+
+        let mut merged_into_first = first.clone();
+        merged_into_first.merge(second.clone());
+        let mut merged_into_second = second;
+        merged_into_second.merge(first);
+
+        assert_eq!(merged_into_first, merged_into_second);
+        assert_eq!(merged_into_first.value_hashes["key"], "1".repeat(64));
+    }
+
+    #[test]
+    fn deletes_are_dropped_after_the_retention_window() {
+        let now = current_timestamp_millis();
+        let mut user = new_user();
+        user.remove_data_record_key(&"expired".to_string(), owner_signature(now - DELETED_KEY_RETENTION_MILLIS - 1000, 1));
+        user.remove_data_record_key(&"recent".to_string(), owner_signature(now - 1000, 1));
+
+        let record: Record = user.try_into().unwrap();
+        let user = User::decode(&record).unwrap();
+
+        assert_eq!(user.deleted_keys.keys().collect::<Vec<_>>(), vec!["recent"]);
+    }
+
+    #[test]
+    fn expired_keys_are_left_out_of_the_index() {
+        let signed_at = current_timestamp_millis() - 10_000;
+        let mut user = new_user();
+        user.add_data_record("expired".to_string(), "1".repeat(64), owner_signature(signed_at, 1), Visibility::Private, Some(5), None);
+        user.add_data_record("expiring".to_string(), "2".repeat(64), owner_signature(signed_at, 2), Visibility::Private, Some(60), None);
+        user.add_data_record("kept".to_string(), "3".repeat(64), owner_signature(signed_at, 3), Visibility::Private, None, None);
+
+        let record: Record = user.try_into().unwrap();
+        let user = User::decode(&record).unwrap();
+
+        assert_eq!(user.data_record_keys, vec!["expiring", "kept"]);
+        assert_eq!(user.ttls.keys().collect::<Vec<_>>(), vec!["expiring"]);
+        assert!(!user.deleted_keys.contains_key("expired"));
+    }
+
+    #[test]
+    fn garbage_records_are_rejected() {
+        let mut truncated_record = USER_RECORD_MAGIC.to_vec();
+        truncated_record.push(USER_RECORD_FORMAT);
+        truncated_record.extend_from_slice(&[0xff; 7]);
+
+        assert_eq!(User::decode(&user_record(truncated_record)).unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert_eq!(User::decode(&user_record(vec![0xff, 0xfe, 0x00])).unwrap_err().kind, ErrorKind::InvalidRequest);
+        assert!(User::decode(&Record::new(RecordKey::new(&vec![0xff]), vec![])).is_err());
+    }
+}
@@ -6,36 +6,48 @@ use libp2p::PeerId;
 use tokio::sync::oneshot;
 use uuid::Uuid;
 
-use disrupted_data_sdk_rs::Identity;
+use disrupted_data_sdk_rs::{DisruptedDataError, Identity};
 
-use crate::types::config::NodeConfig;
+use crate::types::config::{NodeConfig, RecordStoreKind, RecordStoreLimits};
 
 #[derive(Clone)]
 pub struct Node {
     pub ip_address: String,
     pub port: String,
+    pub quic_port: Option<String>,
+    pub websocket_port: Option<String>,
+    pub mdns: bool,
     pub log_file: String,
     pub key: Keypair,
     pub peer_id: PeerId,
+    pub record_store: RecordStoreKind,
+    pub record_store_limits: RecordStoreLimits,
+    pub history_retention: usize,
     clients: Arc<Mutex<HashMap<Uuid, oneshot::Sender<Vec<u8>>>>>,
     get_requests: Arc<Mutex<HashMap<String, Uuid>>>,
 
 }
 
 impl Node {
-    pub fn new(config: NodeConfig) -> Self {
+    pub fn new(config: NodeConfig) -> Result<Self, DisruptedDataError> {
         let identity = Identity::new(config.clone().node_key_location());
 
-        Self {
+        Ok(Self {
             ip_address: config.ip_address(),
             port: config.port(),
+            quic_port: config.quic_port(),
+            websocket_port: config.websocket_port(),
+            mdns: config.mdns(),
             log_file: config.log_file(),
             key: identity.keypair.clone(),
             peer_id: identity.get_peer_id(),
+            record_store: config.record_store()?,
+            record_store_limits: config.record_store_limits(),
+            history_retention: config.history_retention(),
             clients: Arc::new(Mutex::new(HashMap::<Uuid, oneshot::Sender<Vec<u8>>>::new())),
             get_requests: Arc::new(Mutex::new(HashMap::new())),
 
-        }
+        })
     }
 }
 
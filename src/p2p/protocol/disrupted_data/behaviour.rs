@@ -3,19 +3,30 @@ use std::time::Duration;
 
 use crate::p2p::protocol::disrupted_data::request::FindResult;
 use crate::p2p::protocol::disrupted_data::request::Request;
+use crate::p2p::protocol::disrupted_data::request::Request::DELETE;
 use crate::p2p::protocol::disrupted_data::request::Request::GET;
+use crate::p2p::protocol::disrupted_data::request::Request::GRANT;
+use crate::p2p::protocol::disrupted_data::request::Request::HISTORY;
+use crate::p2p::protocol::disrupted_data::request::Request::INDEX;
+use crate::p2p::protocol::disrupted_data::request::Request::PUBLIC;
 use crate::p2p::protocol::disrupted_data::request::Request::PUT;
-use crate::p2p::protocol::disrupted_data::types::state::{GetRequestState, PutRequestState};
-use disrupted_data_sdk_rs::{ActionResult, Actions};
+use crate::p2p::protocol::disrupted_data::types::replay::ReplayGuard;
+use crate::p2p::protocol::disrupted_data::types::state::{DeleteRequestState, GetRequestState, PutRequestState};
+use crate::p2p::store::DisruptedDataStore;
+use crate::p2p::validation::validate_record;
+use disrupted_data_sdk_rs::{ActionResult, Actions, ErrorKind};
 use libp2p::identity::Keypair;
-use libp2p::kad::store::MemoryStore;
 use libp2p::kad::Mode::Server;
-use libp2p::kad::{Record, RecordKey};
+use libp2p::kad::store::RecordStore;
+use libp2p::kad::{ProviderRecord, Record, RecordKey, StoreInserts};
 use libp2p::request_response::{json, InboundRequestId, ProtocolSupport, ResponseChannel};
 use libp2p::swarm::{ConnectionHandler, NetworkBehaviour};
-use libp2p::{identify, kad, ping, request_response, PeerId, StreamProtocol};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::{autonat, dcutr, identify, kad, mdns, ping, relay, request_response, PeerId, StreamProtocol};
 use sha2::digest::Mac;
 
+const MAX_KAD_PACKET_SIZE: usize = 128 * 1024;
+
 pub(crate) type RequestResponseBehaviour = json::Behaviour<Actions, ActionResult>;
 pub(crate) type RequestResponseEvent = request_response::Event<Actions, ActionResult>;
 
@@ -23,14 +34,26 @@ pub(crate) type RequestResponseEvent = request_response::Event<Actions, ActionRe
 #[behaviour(to_swarm = "Event")]
 pub struct Behaviour {
 	pub(crate) request_response: json::Behaviour<Actions, ActionResult>,
-	pub(crate) kad: kad::Behaviour<MemoryStore>,
+	pub(crate) kad: kad::Behaviour<DisruptedDataStore>,
 	pub(crate) identify: identify::Behaviour,
+	pub(crate) autonat: autonat::Behaviour,
+	pub(crate) relay: relay::Behaviour,
+	pub(crate) relay_client: relay::client::Behaviour,
+	pub(crate) dcutr: dcutr::Behaviour,
+	pub(crate) mdns: Toggle<mdns::tokio::Behaviour>,
 }
 
 impl Behaviour {
-	pub fn new(peer_id: &PeerId, key_pair: &Keypair) -> Self {
-		let store = MemoryStore::new(*peer_id);
-		let mut kad = kad::Behaviour::new(*peer_id, store);
+	/// `relay_client` comes from `relay::client::new`, whose transport half must be part of the swarm transport.
+	pub fn new(peer_id: &PeerId, key_pair: &Keypair, store: DisruptedDataStore, relay_client: relay::client::Behaviour, enable_mdns: bool) -> Self {
+		let mut kad_config = kad::Config::new(kad::PROTOCOL_NAME);
+		// The default 16 KiB packet limit is smaller than the chunks the SDK stores for large values.
+		kad_config.set_max_packet_size(MAX_KAD_PACKET_SIZE);
+		// Records pushed by other peers are validated before they are stored, see `store_inbound_record`.
+		kad_config.set_record_filtering(StoreInserts::FilterBoth);
+		// Records only expire when their owner set a TTL, see `DataRecord::expires`, instead of after 48 hours.
+		kad_config.set_record_ttl(None);
+		let mut kad = kad::Behaviour::with_config(*peer_id, store, kad_config);
 		kad.set_mode(Option::from(Server));
 
 		let protocol = StreamProtocol::new("/disrupted-data/browser/1");
@@ -43,23 +66,41 @@ impl Behaviour {
 		let identify_config = identify::Config::new(
 			"/ipfs/id/1.0.0".to_string(),
 			key_pair.public(),
-		).with_interval(Duration::from_secs(20));
+		).with_interval(Duration::from_secs(20))
+			// Peers learn about new listeners, e.g. the WebSocket address browsers dial, without waiting for the next interval.
+			.with_push_listen_addr_updates(true);
 
 		let identify = identify::Behaviour::new(identify_config);
 
+		// AutoNAT asks peers to dial back the addresses identify observed. Reachable nodes also relay for and
+		// hole punch with nodes behind NAT, see `DisruptedDataSwarm::nat_status_changed`.
+		let autonat = autonat::Behaviour::new(*peer_id, autonat::Config::default());
+		let relay = relay::Behaviour::new(*peer_id, relay::Config::default());
+		let dcutr = dcutr::Behaviour::new(*peer_id);
+		// Hosts without multicast cannot run mDNS. The node still reaches its bootstrap nodes, so it runs without it.
+		let mdns = enable_mdns
+			.then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), *peer_id))
+			.and_then(|mdns| mdns.map_err(|error| println!("Could not start mDNS, running without it: {}", error)).ok())
+			.into();
+
 		Behaviour {
 			request_response,
 			kad,
 			identify,
+			autonat,
+			relay,
+			relay_client,
+			dcutr,
+			mdns,
 			// ping,
 		}
 	}
 
 
-	pub(crate) fn put(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions) -> Option<Request> {
+	pub(crate) fn put(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions, replay_guard: &mut ReplayGuard) -> Option<Request> {
 		let inbound_request: Request = (request_id, request).try_into().unwrap();
 		// println!("Handling disrupted_data message in verify state in behaviour: {:?}", inbound_request);
-		let verification_result = inbound_request.verify_request();
+		let verification_result = inbound_request.verify_request(replay_guard);
 		let user_key = RecordKey::new(&inbound_request.clone().get_user_public_key());
 		match &inbound_request {
 			PUT(put_request_state, _) => {
@@ -68,6 +109,7 @@ impl Behaviour {
 				Some(PUT(next_state.clone(), inbound_request.get_data()))
 			}
 			GET(get_request_state, _) => { None }
+			DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
 		}
 	}
 
@@ -75,14 +117,25 @@ impl Behaviour {
 		// println!("Handling get_query_progress in behaviour: {:?} for inbound request id: {:?} find result: {:?}", request, inbound_request_id, find_result);
 		match &request {
 			PUT(put_request_state, data) => {
-				let updated_state_with_find_result = put_request_state.find_user_result(find_result);
-				// println!("updated state with find result in get_query_progress behaviour: {:?}", updated_state_with_find_result);
-				let next_state = updated_state_with_find_result.create_data_record(&mut self.kad, data.clone());
+				let next_state = match put_request_state {
+					PutRequestState::FindUser(_) => { put_request_state.find_user_result(&mut self.kad, find_result, data.clone()) }
+					_ => {
+						let mut updated_state_with_find_result = put_request_state.check_condition(find_result, data);
+						// println!("updated state with find result in get_query_progress behaviour: {:?}", updated_state_with_find_result);
+						if let PutRequestState::ArchiveCurrentVersion(_, _, _) = updated_state_with_find_result {
+							updated_state_with_find_result = updated_state_with_find_result.archive_current_version(&mut self.kad, data.clone());
+						}
+						match updated_state_with_find_result {
+							PutRequestState::CreateDataRecord(_, _) => { updated_state_with_find_result.create_data_record(&mut self.kad, data.clone()) }
+							_ => { updated_state_with_find_result }
+						}
+					}
+				};
 
 				// println!("Next state obtained in get_query_progress behaviour: {:?}", next_state);
 				Some(PUT(next_state.clone(), data.clone()))
 			}
-			GET(_, _) => { None }
+			GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
 		}
 	}
 
@@ -90,25 +143,27 @@ impl Behaviour {
 		match &request {
 			PUT(request_state, data) => {
 				// println!("WaitingDataCreate in put_query_progress with state:{:?} and data: {:?}", request_state, data);
-				if let PutRequestState::WaitingDataCreate(_, _) = &request_state {
+				if let PutRequestState::WaitingArchive(_, _, _) = &request_state {
+					Some(PUT(request_state.create_data_record(&mut self.kad, data.clone()), data.clone()))
+				} else if let PutRequestState::WaitingDataCreate(_, _, _) = &request_state {
 					let possible_waiting_user_create_state = request_state.create_or_update_user(&mut self.kad, data.clone());
 
 					Some(PUT(possible_waiting_user_create_state.clone(), data.clone()))
 				} else { None }
 			}
-			GET(_, _) => { None }
+			GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
 		}
 	}
 	pub(crate) fn put_user_query_progress(&mut self, request: Request, channel: ResponseChannel<ActionResult>) {
 		match &request {
 			PUT(request_state, data) => {
 				// println!("WaitingUserCreate in put_query_progress with state:{:?} and data: {:?}", request_state, data);
-				if let PutRequestState::WaitingUserCreate(query_id) = &request_state {
-					let send_result = self.request_response.send_response(channel, ActionResult::Success("Data added".to_string()));
+				if let PutRequestState::WaitingUserCreate(query_id, version) = &request_state {
+					let send_result = self.request_response.send_response(channel, ActionResult::Stored { version: *version });
 					// println!("SendResult in WaitingUserCreate put_query_progress behaviour: {:?}", send_result);
 				}
 			}
-			GET(_, _) => {}
+			GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => {}
 		}
 	}
 	pub(crate) fn send_response(&mut self, action_result: ActionResult, channel: ResponseChannel<ActionResult>) {
@@ -116,18 +171,29 @@ impl Behaviour {
 		// println!("Sending response result in behaviour: {:?}", send_result);
 	}
 
-	pub(crate) fn get(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions) -> Option<Request> {
+	pub(crate) fn get(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions, replay_guard: &mut ReplayGuard) -> Option<Request> {
 		let inbound_request: Request = (request_id, request).try_into().unwrap();
 		// println!("Handling disrupted_data message in GET verify state in behaviour: {:?}", inbound_request);
-		let verification_result = inbound_request.verify_request();
-		let user_key = RecordKey::new(&inbound_request.clone().get_user_public_key());
+		let verification_result = inbound_request.verify_request(replay_guard);
+		let data = inbound_request.get_data();
+		let user_key = RecordKey::new(data.get_owner_public_key());
 		match &inbound_request {
 			GET(get_request_state, _) => {
-				let next_state = get_request_state.verify(&mut self.kad, verification_result, user_key);
+				let next_state = get_request_state.verify(&mut self.kad, verification_result, user_key, data.get_shared_grant_record_key());
 				// println!("Next state obtained in behaviour after verify: {:?}", next_state);
 				Some(GET(next_state.clone(), inbound_request.get_data()))
 			}
-			PUT(_, _) => { None }
+			PUT(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn find_grant_query_progress(&mut self, find_result: FindResult, request: Request) -> Option<Request> {
+		match &request {
+			GET(get_request_state, data) => {
+				let next_state = get_request_state.find_grant_result(&mut self.kad, find_result, data.clone());
+				Some(GET(next_state, data.clone()))
+			}
+			PUT(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
 		}
 	}
 
@@ -137,18 +203,18 @@ impl Behaviour {
 				let updated_state_with_find_result = get_request_state.find_user_result(find_result);
 				// println!("updated state with find result in find_user_query_progress behaviour: {:?}", updated_state_with_find_result);
 				if let GetRequestState::FindData(user) = &updated_state_with_find_result {
-					if user.contains_data_record_key(data.clone().record_key) {
+					// Deleted keys still have their earlier versions, which can be read back by version. Chunks are not indexed.
+					if data.is_chunk() || user.contains_data_record_key(data.clone().record_key) || (data.version.is_some() && user.deleted_keys.contains_key(&data.record_key)) {
 						updated_state_with_find_result.clone()
 					} else {
-						// self.send_response(ActionResult::Failure("Data not associated with user".to_string()), channel);
-						GetRequestState::SendResponse(ActionResult::Failure("Data not associated with user".to_string()))
+						// self.send_response(ActionResult::failure(ErrorKind::NotFound, "Data not associated with user"), channel);
+						GetRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, "Data not associated with user"))
 					}
-				} else if let GetRequestState::UserNotFound = &updated_state_with_find_result {
-					// self.send_response(ActionResult::Failure("User not found".to_string()), channel);
-					GetRequestState::SendResponse(ActionResult::Failure("User not found".to_string()))
+				} else if let GetRequestState::SendResponse(_) = &updated_state_with_find_result {
+					updated_state_with_find_result
 				} else { GetRequestState::Invalid }
 			}
-			PUT(_, _) => { GetRequestState::Invalid }
+			PUT(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { GetRequestState::Invalid }
 		}
 	}
 
@@ -165,18 +231,178 @@ impl Behaviour {
 		} else { None }
 	}
 
-	pub(crate) fn find_data_query_progress(&mut self, find_result: FindResult, message: Option<String>, request: Request, channel: ResponseChannel<ActionResult>) {
+	pub(crate) fn find_data_query_progress(&mut self, find_result: FindResult, message: Option<String>, request: Request) -> Option<Request> {
 		match &request {
 			GET(get_request_state, data) => {
-				let updated_state_with_find_result = get_request_state.find_data_result(find_result);
+				let updated_state_with_find_result = get_request_state.find_data_result(&mut self.kad, find_result, data);
 				// println!("updated state with find result in find_data_query_progress behaviour: {:?}", updated_state_with_find_result);
-				if let GetRequestState::SendResponse(action_result) = &updated_state_with_find_result {
-					self.send_response(action_result.clone(), channel);
-				} else if let GetRequestState::CouldNotGetData = &updated_state_with_find_result {
-					self.send_response(ActionResult::Failure(message.clone().unwrap()), channel);
+				if let GetRequestState::CouldNotGetData = &updated_state_with_find_result {
+					Some(GET(GetRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, message.unwrap_or_default())), data.clone()))
+				} else {
+					Some(GET(updated_state_with_find_result, data.clone()))
+				}
+			}
+			PUT(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn delete(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions, replay_guard: &mut ReplayGuard) -> Option<Request> {
+		let inbound_request: Request = (request_id, request).try_into().unwrap();
+		let verification_result = inbound_request.verify_request(replay_guard);
+		let user_key = RecordKey::new(&inbound_request.clone().get_user_public_key());
+		match &inbound_request {
+			DELETE(delete_request_state, data) => {
+				let next_state = delete_request_state.verify(&mut self.kad, verification_result, user_key);
+				Some(DELETE(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn delete_find_progress(&mut self, find_result: FindResult, request: Request) -> Option<Request> {
+		match &request {
+			DELETE(delete_request_state, data) => {
+				let next_state = match delete_request_state {
+					DeleteRequestState::FindUser(_) => { delete_request_state.find_user_result(&mut self.kad, find_result, data.clone()) }
+					_ => { delete_request_state.create_tombstone(&mut self.kad, find_result, data.clone()) }
+				};
+				Some(DELETE(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn delete_put_progress(&mut self, request: Request) -> Option<Request> {
+		match &request {
+			DELETE(delete_request_state, data) => {
+				let next_state = match delete_request_state {
+					DeleteRequestState::WaitingArchive(_, _, _) => {
+						delete_request_state.current_version_archived(&mut self.kad, data.clone())
+					}
+					DeleteRequestState::WaitingTombstoneCreate(_, _) => {
+						delete_request_state.remove_key_from_user(&mut self.kad, data.clone())
+					}
+					_ => { delete_request_state.user_updated() }
+				};
+				Some(DELETE(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn grant(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions, replay_guard: &mut ReplayGuard) -> Option<Request> {
+		let inbound_request: Request = (request_id, request).try_into().unwrap();
+		let verification_result = inbound_request.verify_request(replay_guard);
+		match &inbound_request {
+			GRANT(grant_request_state, data) => {
+				let next_state = grant_request_state.verify(&mut self.kad, verification_result, data.clone());
+				Some(GRANT(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn grant_find_progress(&mut self, find_result: FindResult, request: Request) -> Option<Request> {
+		match &request {
+			GRANT(grant_request_state, data) => {
+				let next_state = grant_request_state.store_grant(&mut self.kad, find_result, data.clone());
+				Some(GRANT(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn grant_put_progress(&mut self, request: Request) -> Option<Request> {
+		match &request {
+			GRANT(grant_request_state, data) => {
+				Some(GRANT(grant_request_state.grant_stored(data.clone()), data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn get_public(&mut self, request_id: InboundRequestId, request: Actions) -> Option<Request> {
+		let inbound_request: Request = (request_id, request).try_into().unwrap();
+		match &inbound_request {
+			PUBLIC(public_get_request_state, data) => {
+				let next_state = public_get_request_state.find_data_record(&mut self.kad, data.clone());
+				Some(PUBLIC(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn public_find_data_progress(&mut self, find_result: FindResult, request: Request) -> Option<Request> {
+		match &request {
+			PUBLIC(public_get_request_state, data) => {
+				Some(PUBLIC(public_get_request_state.find_data_result(find_result), data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn history(&mut self, request_id: InboundRequestId, request: Actions, replay_guard: &mut ReplayGuard) -> Option<Request> {
+		let inbound_request: Request = (request_id, request).try_into().unwrap();
+		let verification_result = inbound_request.verify_request(replay_guard);
+		match &inbound_request {
+			HISTORY(history_request_state, data) => {
+				let next_state = history_request_state.verify(&mut self.kad, verification_result, data.clone());
+				Some(HISTORY(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn history_find_progress(&mut self, find_result: FindResult, request: Request) -> Option<Request> {
+		match &request {
+			HISTORY(history_request_state, data) => {
+				Some(HISTORY(history_request_state.find_version_result(&mut self.kad, find_result, data.clone()), data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | INDEX(_, _) | GRANT(_, _) | PUBLIC(_, _) => { None }
+		}
+	}
+
+	/// Stores a record another peer sent with a Kademlia PUT_VALUE, if it passes validation.
+	pub(crate) fn store_inbound_record(&mut self, source: PeerId, record: Record) {
+		let existing_record = self.kad.store_mut().get(&record.key).map(|existing_record| existing_record.into_owned());
+		match validate_record(&record, existing_record.as_ref()) {
+			Ok(()) => {
+				if let Err(error) = self.kad.store_mut().put(record) {
+					println!("Could not store record from {}: {:?}", source, error);
 				}
 			}
-			PUT(_, _) => {}
+			Err(error) => {
+				println!("Rejected record {:?} from {}: {}", record.key, source, error.message);
+			}
+		}
+	}
+
+	pub(crate) fn store_inbound_provider(&mut self, provider_record: ProviderRecord) {
+		if let Err(error) = self.kad.store_mut().add_provider(provider_record) {
+			println!("Could not store provider record: {:?}", error);
+		}
+	}
+
+	pub(crate) fn index(&mut self, peer: PeerId, request_id: InboundRequestId, request: Actions, replay_guard: &mut ReplayGuard) -> Option<Request> {
+		let inbound_request: Request = (request_id, request).try_into().unwrap();
+		let verification_result = inbound_request.verify_request(replay_guard);
+		let user_key = RecordKey::new(&inbound_request.clone().get_user_public_key());
+		match &inbound_request {
+			INDEX(index_request_state, data) => {
+				let next_state = index_request_state.verify(&mut self.kad, verification_result, user_key);
+				Some(INDEX(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
+		}
+	}
+
+	pub(crate) fn index_find_user_progress(&mut self, find_result: FindResult, request: Request) -> Option<Request> {
+		match &request {
+			INDEX(index_request_state, data) => {
+				let next_state = index_request_state.find_user_result(find_result, data.clone());
+				Some(INDEX(next_state, data.clone()))
+			}
+			PUT(_, _) | GET(_, _) | DELETE(_, _) | GRANT(_, _) | PUBLIC(_, _) | HISTORY(_, _) => { None }
 		}
 	}
 }
@@ -185,6 +411,11 @@ impl Behaviour {
 pub enum Event {
 	Kademlia(kad::Event),
 	Identify(identify::Event),
+	Autonat(autonat::Event),
+	Relay(relay::Event),
+	RelayClient(relay::client::Event),
+	Dcutr(dcutr::Event),
+	Mdns(mdns::Event),
 	RequestResponse(RequestResponseEvent),
 	VerificationResult(),
 }
@@ -201,8 +432,38 @@ impl From<identify::Event> for Event {
 	}
 }
 
+impl From<autonat::Event> for Event {
+	fn from(autonat_event: autonat::Event) -> Self {
+		Event::Autonat(autonat_event)
+	}
+}
+
+impl From<relay::Event> for Event {
+	fn from(relay_event: relay::Event) -> Self {
+		Event::Relay(relay_event)
+	}
+}
+
+impl From<relay::client::Event> for Event {
+	fn from(relay_client_event: relay::client::Event) -> Self {
+		Event::RelayClient(relay_client_event)
+	}
+}
+
+impl From<dcutr::Event> for Event {
+	fn from(dcutr_event: dcutr::Event) -> Self {
+		Event::Dcutr(dcutr_event)
+	}
+}
+
+impl From<mdns::Event> for Event {
+	fn from(mdns_event: mdns::Event) -> Self {
+		Event::Mdns(mdns_event)
+	}
+}
+
 impl From<RequestResponseEvent> for Event {
 	fn from(request_response_event: RequestResponseEvent) -> Self {
 		Event::RequestResponse(request_response_event)
 	}
-}
\ No newline at end of file
+}
@@ -1,7 +1,8 @@
 use secp256k1::{Message, PublicKey, Secp256k1};
 use secp256k1::schnorr::Signature;
-use disrupted_data_sdk_rs::DisruptedDataError;
+use disrupted_data_sdk_rs::{DisruptedDataError, ErrorKind, SignedPayload};
 
+pub(crate) mod replay;
 pub(crate) mod request;
 pub(crate) mod state;
 
@@ -10,35 +11,30 @@ pub(crate) fn is_identity_verified(signature_vec: Vec<u8>, public_key: PublicKey
 
 	let message = Message::from_digest(<[u8; 32]>::try_from(disrupted_data_sdk_rs::hash_message(&message)).unwrap());
 	let (x_only_public_key, parity) = public_key.x_only_public_key();
-	let signature = Signature::from_slice(signature_vec.as_slice()).unwrap();
-	secp256k1.verify_schnorr(&signature, &message, &x_only_public_key).is_ok()
+	match Signature::from_slice(signature_vec.as_slice()) {
+		Ok(signature) => { secp256k1.verify_schnorr(&signature, &message, &x_only_public_key).is_ok() }
+		Err(_) => { false }
+	}
 }
 
 fn get_secp256k1_public_key(public_key: Vec<u8>) -> Result<PublicKey, DisruptedDataError> {
-	PublicKey::from_slice(public_key.as_slice()).map_err(|e| { DisruptedDataError { message: "Invalid Key".to_string() } })
-}
-
-fn get_message(parts: Vec<String>) -> String {
-	parts.into_iter()
-		.map(|s| s.trim().to_string())
-		.collect()
+	PublicKey::from_slice(public_key.as_slice()).map_err(|e| { DisruptedDataError::new(ErrorKind::InvalidRequest, "Invalid Key".to_string()) })
 }
 
-
-
-fn verify_signature(user_public_key: Vec<u8>, signature: Vec<u8>, parts: Vec<String>) -> Result<(), DisruptedDataError> {
+/// Checks the owner's signature over the canonical encoding of the request payload.
+pub(crate) fn verify_signature(user_public_key: Vec<u8>, signature: Vec<u8>, payload: &SignedPayload) -> Result<(), DisruptedDataError> {
 	let secp256k1_public_key_result = get_secp256k1_public_key(user_public_key);
 
 	match secp256k1_public_key_result {
 		Ok(public_key) => {
-			if is_identity_verified(signature, public_key, get_message(parts)) {
+			if is_identity_verified(signature, public_key, payload.to_message()) {
 				Ok(())
 			} else {
-				Err(DisruptedDataError { message: "Could not get public key".to_string() })
+				Err(DisruptedDataError::new(ErrorKind::InvalidSignature, "Could not get public key".to_string()))
 			}
 		}
 		Err(error) => {
-			Err(DisruptedDataError { message: "Could not get public key".to_string() })
+			Err(DisruptedDataError::new(ErrorKind::InvalidSignature, "Could not get public key".to_string()))
 		}
 	}
 }
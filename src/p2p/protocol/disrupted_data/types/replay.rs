@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+
+use disrupted_data_sdk_rs::payload::MAX_REQUEST_AGE_MILLIS;
+use disrupted_data_sdk_rs::{current_timestamp_millis, DisruptedDataError, ErrorKind, SignedPayload};
+
+/// Remembers the `(user, nonce)` pairs of recently served requests so a captured request cannot be sent again.
+///
+/// Requests older than [`MAX_REQUEST_AGE_MILLIS`] are rejected outright, so nonces only need to be kept for
+/// that long. Every node keeps its own guard, so a write captured on one node could still be sent through another.
+/// Puts and deletes are therefore also rejected unless they were signed after the write that produced the stored
+/// record, see `check_newer_than` in the request states.
+#[derive(Debug, Default)]
+pub(crate) struct ReplayGuard {
+	seen_nonces: HashMap<(Vec<u8>, u64), u64>,
+}
+
+impl ReplayGuard {
+	/// Records the payload's nonce, failing if the request is stale or was already seen.
+	pub(crate) fn check(&mut self, payload: &SignedPayload) -> Result<(), DisruptedDataError> {
+		let now = current_timestamp_millis();
+		if payload.timestamp.abs_diff(now) > MAX_REQUEST_AGE_MILLIS {
+			return Err(DisruptedDataError::new(ErrorKind::Replayed, "Request timestamp is too far from the node's clock".to_string()));
+		}
+
+		self.seen_nonces.retain(|_, timestamp| timestamp.abs_diff(now) <= MAX_REQUEST_AGE_MILLIS);
+		let nonce_key = (payload.user_public_key.clone(), payload.nonce);
+		if self.seen_nonces.insert(nonce_key, payload.timestamp).is_some() {
+			return Err(DisruptedDataError::new(ErrorKind::Replayed, "Request was already processed".to_string()));
+		}
+		Ok(())
+	}
+}
@@ -1,8 +1,11 @@
-use crate::p2p::protocol::disrupted_data::request::Request::{GET, PUT};
-use crate::p2p::protocol::disrupted_data::types::state::{GetRequestState, PutRequestState};
+use crate::p2p::protocol::disrupted_data::request::Request::{DELETE, GET, GRANT, HISTORY, INDEX, PUBLIC, PUT};
+use crate::p2p::protocol::disrupted_data::types::state::{DeleteRequestState, GetRequestState, GrantRequestState, HistoryRequestState, IndexRequestState, PublicGetRequestState, PutRequestState};
+use crate::p2p::protocol::disrupted_data::types::replay::ReplayGuard;
 use crate::p2p::protocol::disrupted_data::types::verify_signature;
-use disrupted_data_sdk_rs::{Actions, DisruptedDataError};
-use hex::{decode, encode};
+use crate::p2p::record::{data_record_key, grant_record_key, DataRecord, OwnerSignature};
+use disrupted_data_sdk_rs::merkle::value_hash;
+use disrupted_data_sdk_rs::{chunk_hash, Actions, DisruptedDataError, ErrorKind, GrantRequest, PutCondition, SignedPayload, VersionSelector, Visibility};
+use hex::decode;
 use libp2p::kad::{QueryId, Record, RecordKey};
 use libp2p::request_response::InboundRequestId;
 
@@ -10,6 +13,15 @@ use libp2p::request_response::InboundRequestId;
 pub(crate) enum Request {
 	PUT(PutRequestState, RequestData),
 	GET(GetRequestState, RequestData),
+	DELETE(DeleteRequestState, RequestData),
+	/// Read-only queries answered from the user's index record: listing keys and Merkle tree lookups.
+	INDEX(IndexRequestState, RequestData),
+	/// Grants or revokes another user's read access to the owner's keys.
+	GRANT(GrantRequestState, RequestData),
+	/// Unsigned read of a record its owner published.
+	PUBLIC(PublicGetRequestState, RequestData),
+	/// Lists the retained versions of a record.
+	HISTORY(HistoryRequestState, RequestData),
 }
 
 impl TryFrom<(InboundRequestId, Actions)> for Request {
@@ -19,14 +31,23 @@ impl TryFrom<(InboundRequestId, Actions)> for Request {
 	fn try_from(value: (InboundRequestId, Actions)) -> Result<Self, Self::Error> {
 		match value.1 {
 			Actions::Put(put_request) => {
+				let signed_payload = put_request.signed_payload();
 				let request_data = RequestData {
 					// peer_id: value.0,
 					inbound_request_id: value.0,
 					user_public_key: put_request.user_public_key,
 					record_key: put_request.record_key,
 					record_value: Some(put_request.record_value),
+					signed_payload,
 					signature: put_request.signature,
-					user: None,
+					index_query: None,
+					owner_public_key: None,
+					grant_request: None,
+					visibility: put_request.visibility,
+					condition: put_request.condition,
+					version: None,
+					ttl: put_request.ttl,
+					value_key: None,
 				};
 
 				Ok(
@@ -34,43 +55,218 @@ impl TryFrom<(InboundRequestId, Actions)> for Request {
 				)
 			}
 			Actions::Get(get_request) => {
+				let signed_payload = get_request.signed_payload();
 				let request_data = RequestData {
 					// peer_id: value.0,
 					inbound_request_id: value.0,
 					user_public_key: get_request.user_public_key,
 					record_key: get_request.record_key,
 					record_value: None,
+					signed_payload,
 					signature: get_request.signature,
-					user: None,
+					index_query: None,
+					owner_public_key: get_request.owner_public_key,
+					grant_request: None,
+					visibility: Visibility::Private,
+					condition: None,
+					version: get_request.version,
+					ttl: None,
+					value_key: get_request.value_key,
 				};
 				Ok(
 					GET(GetRequestState::Verify, request_data)
 				)
 			}
-			Actions::Unknown => { Err(DisruptedDataError { message: format!("Unknown action: {:?}", value.1) }) }
+			Actions::Delete(delete_request) => {
+				let signed_payload = delete_request.signed_payload();
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: delete_request.user_public_key,
+					record_key: delete_request.record_key,
+					record_value: None,
+					signed_payload,
+					signature: delete_request.signature,
+					index_query: None,
+					owner_public_key: None,
+					grant_request: None,
+					visibility: Visibility::Private,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					DELETE(DeleteRequestState::Verify, request_data)
+				)
+			}
+			Actions::List(list_request) => {
+				let signed_payload = list_request.signed_payload();
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: list_request.user_public_key,
+					record_key: String::new(),
+					record_value: None,
+					signed_payload,
+					signature: list_request.signature,
+					index_query: Some(IndexQuery::List {
+						prefix: list_request.prefix,
+						cursor: list_request.cursor,
+						limit: list_request.limit,
+					}),
+					owner_public_key: None,
+					grant_request: None,
+					visibility: Visibility::Private,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					INDEX(IndexRequestState::Verify, request_data)
+				)
+			}
+			Actions::Proof(proof_request) => {
+				let signed_payload = proof_request.signed_payload();
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: proof_request.user_public_key,
+					record_key: proof_request.record_key.clone(),
+					record_value: None,
+					signed_payload,
+					signature: proof_request.signature,
+					index_query: Some(IndexQuery::Proof { record_key: proof_request.record_key }),
+					owner_public_key: None,
+					grant_request: None,
+					visibility: Visibility::Private,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					INDEX(IndexRequestState::Verify, request_data)
+				)
+			}
+			Actions::Subtree(subtree_request) => {
+				let signed_payload = subtree_request.signed_payload();
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: subtree_request.user_public_key,
+					record_key: String::new(),
+					record_value: None,
+					signed_payload,
+					signature: subtree_request.signature,
+					index_query: Some(IndexQuery::Subtree { depth: subtree_request.depth, index: subtree_request.index }),
+					owner_public_key: None,
+					grant_request: None,
+					visibility: Visibility::Private,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					INDEX(IndexRequestState::Verify, request_data)
+				)
+			}
+			Actions::Grant(grant_request) => {
+				let signed_payload = grant_request.signed_payload();
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: grant_request.user_public_key.clone(),
+					record_key: String::new(),
+					record_value: None,
+					signed_payload,
+					signature: grant_request.signature.clone(),
+					index_query: None,
+					owner_public_key: None,
+					grant_request: Some(grant_request),
+					visibility: Visibility::Private,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					GRANT(GrantRequestState::Verify, request_data)
+				)
+			}
+			Actions::GetPublic(get_public_request) => {
+				// Public reads are not signed. The payload only names the record, nobody signed it.
+				let signed_payload = SignedPayload::new("get-public", &get_public_request.owner_public_key, &get_public_request.record_key, 0, 0);
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: get_public_request.owner_public_key,
+					record_key: get_public_request.record_key,
+					record_value: None,
+					signed_payload,
+					signature: vec![],
+					index_query: None,
+					owner_public_key: None,
+					grant_request: None,
+					visibility: Visibility::Public,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					PUBLIC(PublicGetRequestState::Find, request_data)
+				)
+			}
+			Actions::History(history_request) => {
+				let signed_payload = history_request.signed_payload();
+				let request_data = RequestData {
+					inbound_request_id: value.0,
+					user_public_key: history_request.user_public_key,
+					record_key: history_request.record_key,
+					record_value: None,
+					signed_payload,
+					signature: history_request.signature,
+					index_query: None,
+					owner_public_key: None,
+					grant_request: None,
+					visibility: Visibility::Private,
+					condition: None,
+					version: None,
+					ttl: None,
+					value_key: None,
+				};
+				Ok(
+					HISTORY(HistoryRequestState::Verify, request_data)
+				)
+			}
+			Actions::Unknown => { Err(DisruptedDataError::new(ErrorKind::InvalidRequest, format!("Unknown action: {:?}", value.1))) }
 		}
 	}
 }
 
 impl Request {
-	pub(crate) fn verify_request(&self) -> VerifyRequestResult {
-		match self {
-			PUT(_, data) => {
-				let public_key_bytes = decode(data.clone().user_public_key).expect("Could not decode public key");
-				if let Ok(()) = verify_signature(public_key_bytes, data.clone().signature, vec![data.clone().record_value.unwrap()])
-				{ VerifyRequestResult::Success } else { VerifyRequestResult::Failed(DisruptedDataError { message: "Unexpected identity signature".to_string() }) }
-			}
-			GET(_, data) => {
-				let public_key_bytes = decode(data.clone().user_public_key).expect("Could not decode public key");
-				if let Ok(()) = verify_signature(public_key_bytes, data.clone().signature, vec![data.record_key.clone()])
-				{ VerifyRequestResult::Success } else { VerifyRequestResult::Failed(DisruptedDataError { message: "Unexpected identity signature".to_string() }) }
+	/// Checks the owner's signature over the request and rejects stale or replayed requests, and chunks whose
+	/// value is not the content their key names.
+	pub(crate) fn verify_request(&self, replay_guard: &mut ReplayGuard) -> VerifyRequestResult {
+		let data = self.get_data();
+		if let (Some(chunk_hash), Some(record_value)) = (chunk_hash(&data.record_key), &data.record_value) {
+			if value_hash(record_value) != chunk_hash {
+				return VerifyRequestResult::Failed(DisruptedDataError::new(ErrorKind::Integrity, "Chunk does not match its key".to_string()));
 			}
 		}
+		let public_key_bytes = match decode(&data.user_public_key) {
+			Ok(public_key_bytes) => { public_key_bytes }
+			Err(_) => { return VerifyRequestResult::Failed(DisruptedDataError::new(ErrorKind::InvalidRequest, "Could not decode public key".to_string())) }
+		};
+		if verify_signature(public_key_bytes, data.signature.clone(), &data.signed_payload).is_err() {
+			return VerifyRequestResult::Failed(DisruptedDataError::new(ErrorKind::InvalidSignature, "Unexpected identity signature".to_string()));
+		}
+		match replay_guard.check(&data.signed_payload) {
+			Ok(()) => { VerifyRequestResult::Success }
+			Err(error) => { VerifyRequestResult::Failed(error) }
+		}
 	}
 
 	pub(crate) fn get_data(&self) -> RequestData {
 		match self {
-			PUT(_, data) | GET(_, data) => {
+			PUT(_, data) | GET(_, data) | DELETE(_, data) | INDEX(_, data) | GRANT(_, data) | PUBLIC(_, data) | HISTORY(_, data) => {
 				data.clone()
 			}
 		}
@@ -84,6 +280,21 @@ impl Request {
 			GET(_, data) => {
 				data.user_public_key
 			}
+			DELETE(_, data) => {
+				data.user_public_key
+			}
+			INDEX(_, data) => {
+				data.user_public_key
+			}
+			GRANT(_, data) => {
+				data.user_public_key
+			}
+			PUBLIC(_, data) => {
+				data.user_public_key
+			}
+			HISTORY(_, data) => {
+				data.user_public_key
+			}
 		}
 	}
 }
@@ -125,18 +336,76 @@ pub(crate) struct RequestData {
 	inbound_request_id: InboundRequestId,
 	user_public_key: Vec<u8>,
 	pub(crate) record_key: String,
-	pub(crate) record_value: Option<String>,
+	pub(crate) record_value: Option<Vec<u8>>,
+	/// What the owner signed. Built from the request fields, so the signature only verifies if none of them were altered.
+	pub(crate) signed_payload: SignedPayload,
 	signature: Vec<u8>,
-	user: Option<Record>,
+	pub(crate) index_query: Option<IndexQuery>,
+	/// Owner of the record when a user reads a record another user shared with them.
+	owner_public_key: Option<Vec<u8>>,
+	pub(crate) grant_request: Option<GrantRequest>,
+	pub(crate) visibility: Visibility,
+	/// Condition of a put, signed by the owner and checked against the stored data record before the value is
+	/// written.
+	pub(crate) condition: Option<PutCondition>,
+	/// Version of the record a GET reads, instead of the current one.
+	pub(crate) version: Option<VersionSelector>,
+	/// Seconds until the value of a put expires, signed by the owner.
+	pub(crate) ttl: Option<u64>,
+	/// Key of the value a GET reads a chunk of. Grants for shared records are checked against it.
+	pub(crate) value_key: Option<String>,
 }
 
-impl RequestData {
-	pub(crate) fn get_record(&self) -> Record {
-		let mut record_key_bytes: Vec<u8> = self.user_public_key.clone();
-		record_key_bytes.append(&mut self.record_key.clone().into_bytes());
+#[derive(Debug, Clone)]
+pub(crate) enum IndexQuery {
+	List {
+		prefix: Option<String>,
+		cursor: Option<String>,
+		limit: Option<u32>,
+	},
+	Proof {
+		record_key: String,
+	},
+	Subtree {
+		depth: u8,
+		index: u32,
+	},
+}
 
+impl Default for IndexQuery {
+	fn default() -> Self {
+		IndexQuery::List { prefix: None, cursor: None, limit: None }
+	}
+}
+
+impl RequestData {
+	pub(crate) fn get_record(&self, version: u64) -> Record {
 		//Record can only be created for Put record which should have the record_value
-		Record::new(RecordKey::new(&encode(record_key_bytes)), self.record_value.clone().unwrap().as_bytes().to_vec())
+		let (record_key, value, ttl, condition, owner) = (self.record_key.clone(), self.record_value.clone().unwrap(), self.ttl, self.condition, self.owner_signature());
+		let data_record = match self.visibility {
+			Visibility::Private => { DataRecord::Value { record_key, value, version, ttl, condition, owner } }
+			Visibility::Public => { DataRecord::Published { record_key, value, version, ttl, condition, owner } }
+		};
+		data_record.to_record(data_record_key(&self.user_public_key, &self.record_key))
+	}
+
+	pub(crate) fn get_tombstone_record(&self, version: u64) -> Record {
+		let tombstone = DataRecord::Tombstone {
+			record_key: self.record_key.clone(),
+			version,
+			owner: self.owner_signature(),
+		};
+		tombstone.to_record(data_record_key(&self.user_public_key, &self.record_key))
+	}
+
+	/// The owner's signature over this request, stored with the records the request writes.
+	pub(crate) fn owner_signature(&self) -> OwnerSignature {
+		OwnerSignature {
+			user_public_key: self.user_public_key.clone(),
+			timestamp: self.signed_payload.timestamp,
+			nonce: self.signed_payload.nonce,
+			signature: self.signature.clone(),
+		}
 	}
 
 	pub(crate) fn get_user_record_key(&self) -> RecordKey {
@@ -147,67 +416,22 @@ impl RequestData {
 	}
 
 	pub(crate) fn get_data_record_key(self) -> RecordKey {
-		let mut record_key_bytes: Vec<u8> = self.user_public_key.clone();
-		record_key_bytes.append(&mut self.record_key.into_bytes());
-		RecordKey::new(&encode(record_key_bytes))
+		data_record_key(self.get_owner_public_key(), &self.record_key)
 	}
 
-	pub(crate) fn update_request_with_user(mut self, user: Option<Record>) -> Self {
-		match user {
-			None => {
-				let mut user_data_keys: Vec<u8> = Vec::new();
-				let new_key = self.record_key.as_bytes().to_vec();
-				let length = new_key.len() as u32;
-				user_data_keys.extend_from_slice(&length.to_le_bytes());
-				user_data_keys.extend_from_slice(&new_key);
-				let user_record_key = RecordKey::new(&encode(self.user_public_key.clone()));
-				let new_user_record = Record::new(user_record_key, user_data_keys);
-
-				Self {
-					// peer_id: self.peer_id,
-					inbound_request_id: self.inbound_request_id,
-					user_public_key: self.user_public_key,
-					record_key: self.record_key,
-					record_value: self.record_value,
-					signature: self.signature,
-					user: Some(new_user_record),
-				}
-			}
-			Some(user_record) => {
-				let mut user_data_keys = user_record.clone().value;
-				let new_key = self.record_key.as_bytes().to_vec();
-				let length = new_key.len() as u32;
-				user_data_keys.extend_from_slice(&length.to_le_bytes());
-				user_data_keys.extend_from_slice(&new_key);
-				let updated_user_record = Record::new(user_record.key, user_data_keys);
-				Self {
-					// peer_id: self.peer_id,
-					inbound_request_id: self.inbound_request_id,
-					user_public_key: self.user_public_key,
-					record_key: self.record_key,
-					record_value: self.record_value,
-					signature: self.signature,
-					user: Some(updated_user_record),
-				}
-			}
-		}
+	/// Chunks of large values are kept out of the user's index and have no history. Readers learn their keys from
+	/// the manifest of the value they belong to.
+	pub(crate) fn is_chunk(&self) -> bool {
+		chunk_hash(&self.record_key).is_some()
 	}
 
-	pub(crate) fn add_data_key_to_user(mut self) -> Self {
-		let mut user_data_keys = self.user.clone().unwrap().value;
-		let new_key = self.record_key.as_bytes().to_vec();
-		let length = new_key.len() as u32;
-		user_data_keys.extend_from_slice(&length.to_le_bytes());
-		user_data_keys.extend_from_slice(&new_key);
-		let updated_user_record = Record::new(self.user.unwrap().key, user_data_keys);
-		Self {
-			// peer_id: self.peer_id,
-			inbound_request_id: self.inbound_request_id,
-			user_public_key: self.user_public_key,
-			record_key: self.record_key,
-			record_value: self.record_value,
-			signature: self.signature,
-			user: Some(updated_user_record),
-		}
+	/// Hex encoded public key of the user whose records the request reads.
+	pub(crate) fn get_owner_public_key(&self) -> &Vec<u8> {
+		self.owner_public_key.as_ref().unwrap_or(&self.user_public_key)
+	}
+
+	/// Key of the grants the owner gave this user, when the request reads another user's record.
+	pub(crate) fn get_shared_grant_record_key(&self) -> Option<RecordKey> {
+		self.owner_public_key.as_ref().map(|owner_public_key| grant_record_key(owner_public_key, &self.user_public_key))
 	}
 }
@@ -1,30 +1,70 @@
-use crate::p2p::protocol::disrupted_data::request::{FindResult, RequestData, VerifyRequestResult};
+use crate::p2p::protocol::disrupted_data::request::{FindResult, IndexQuery, RequestData, VerifyRequestResult};
 use crate::p2p::protocol::disrupted_data::types::state::GetRequestState::{CouldNotGetData, DataNotAssociatedWithUser, FindData, WaitingData};
-use crate::p2p::protocol::disrupted_data::types::state::PutRequestState::{CreateDataRecord, FindUser, SendResponse, WaitingDataCreate, WaitingPut, WaitingUserCreate};
+use crate::p2p::protocol::disrupted_data::types::state::PutRequestState::{ArchiveCurrentVersion, CreateDataRecord, FindCurrentVersion, FindUser, SendResponse, WaitingArchive, WaitingDataCreate, WaitingUserCreate};
+use crate::p2p::record::{check_newer_than, grant_record_key, history_record_key, DataRecord, Grant, GrantRecord, MAX_RECORD_VERSION};
+use crate::p2p::store::DisruptedDataStore;
+use crate::p2p::validation::validate_record;
 use crate::p2p::User;
-use disrupted_data_sdk_rs::ActionResult;
+use disrupted_data_sdk_rs::merkle::value_hash;
+use disrupted_data_sdk_rs::{ActionResult, DisruptedDataError, ErrorKind, ListResult, MerkleTree, PublicRecord, PutCondition, RecordVersion, VersionSelector};
+use std::collections::BTreeMap;
 use libp2p::kad;
-use libp2p::kad::store::MemoryStore;
-use libp2p::kad::{QueryId, Quorum, RecordKey};
-use std::collections::HashSet;
+use hex::decode;
+use libp2p::kad::{QueryId, Quorum, Record, RecordKey};
+use secp256k1::PublicKey;
 
-pub(crate) trait RequestState {}
+const DEFAULT_LIST_LIMIT: usize = 100;
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// Version a write stores a record at when it currently is at `current_version`.
+fn next_version(current_version: u64) -> Result<u64, DisruptedDataError> {
+	current_version.checked_add(1)
+		.filter(|next_version| *next_version <= MAX_RECORD_VERSION)
+		.ok_or(DisruptedDataError::new(ErrorKind::Conflict, "Record is at the highest version".to_string()))
+}
+
+/// Decodes the data record a request found, failing the request when another peer served a record that cannot be
+/// decoded.
+fn decode_data_record(record: &Record) -> Result<DataRecord, DisruptedDataError> {
+	DataRecord::from_bytes(&record.value).map_err(|error| DisruptedDataError::new(ErrorKind::Integrity, format!("Could not read the data record: {}", error.message)))
+}
+
+/// Decodes the user's index record a request found, failing the request instead of the node when another peer
+/// served a record that cannot be decoded.
+fn decode_user(record: &Record) -> Result<User, DisruptedDataError> {
+	User::decode(record).map_err(|error| DisruptedDataError::new(ErrorKind::Integrity, format!("Could not read the user index: {}", error.message)))
+}
+
+/// Stores `current_record` under its history key before a newer version replaces it. Returns `None` when the node
+/// keeps no history or the record predates versions, as there is nothing to archive then. History starts at
+/// version 1.
+fn archive_record(kad: &mut kad::Behaviour<DisruptedDataStore>, data_record_key: RecordKey, current_record: &DataRecord) -> Option<Result<QueryId, kad::store::Error>> {
+	if kad.store_mut().history_retention() <= 1 || current_record.version() == 0 {
+		return None;
+	}
+	let history_key = history_record_key(&data_record_key, current_record.version());
+	Some(kad.put_record(current_record.to_record(history_key), Quorum::One))
+}
 
 #[derive(Debug, Clone)]
 pub(crate) enum PutRequestState {
 	Verify,
 	FindUser(QueryId),
-	CreateUserRecord(FindResult),
-	WaitingUserCreate(QueryId),
-	CreateDataRecord(FindResult),
-	WaitingDataCreate(QueryId, FindResult),
-	WaitingPut(HashSet<QueryId>),
+	/// Reading the stored data record, whose version the condition of the put is checked against.
+	FindCurrentVersion(QueryId, FindResult),
+	/// The stored record is kept under its history key before the value replaces it.
+	ArchiveCurrentVersion(FindResult, DataRecord, u64),
+	WaitingArchive(QueryId, FindResult, u64),
+	/// The version the value was stored at is sent back once the user's index is updated.
+	WaitingUserCreate(QueryId, u64),
+	CreateDataRecord(FindResult, u64),
+	WaitingDataCreate(QueryId, FindResult, u64),
 	SendResponse(ActionResult),
 
 }
 
 impl PutRequestState {
-	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<MemoryStore>, verification_result: VerifyRequestResult, user_key: RecordKey) -> Self {
+	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, verification_result: VerifyRequestResult, user_key: RecordKey) -> Self {
 		if let PutRequestState::Verify = self {
 			println!("Verification result: {:?}", verification_result);
 			match verification_result {
@@ -34,85 +74,129 @@ impl PutRequestState {
 					println!("get_user_query_id: {:?}", get_user_query_id);
 					FindUser(get_user_query_id)
 				}
-				VerifyRequestResult::Failed(_) => {
-					SendResponse(ActionResult::Failure("Invalid request".to_string()))
+				VerifyRequestResult::Failed(error) => {
+					SendResponse(ActionResult::failure(error.kind, format!("Invalid request: {}", error.message)))
 				}
 			}
 		} else {
-			SendResponse(ActionResult::Failure("Invalid state".to_string()))
+			SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
 		}
 	}
 
-	pub(crate) fn find_user_result(&self, find_user_result: FindResult) -> Self {
-		CreateDataRecord(find_user_result)
+	pub(crate) fn find_user_result(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_user_result: FindResult, data: RequestData) -> Self {
+		FindCurrentVersion(kad.get_record(data.get_data_record_key()), find_user_result)
+	}
+
+	/// Checks the condition of the put against the stored data record, before anything is written. The value is
+	/// stored at the version after the current one. Chunks are not archived, see [`RequestData::is_chunk`].
+	pub(crate) fn check_condition(&self, find_data_result: FindResult, data: &RequestData) -> Self {
+		if let PutRequestState::FindCurrentVersion(_, find_user_result) = self {
+			let current_record = match find_data_result {
+				FindResult::Found(_, record) => {
+					match decode_data_record(&record) {
+						Ok(current_record) => { Some(current_record) }
+						Err(error) => { return SendResponse(ActionResult::Failure(error)) }
+					}
+				}
+				FindResult::NotFound => { None }
+			};
+			if let Err(error) = check_newer_than(current_record.as_ref(), &data.owner_signature()) {
+				return SendResponse(ActionResult::Failure(error));
+			}
+			let current_version = current_record.as_ref().map_or(0, DataRecord::version);
+			let next_version = match next_version(current_version) {
+				Ok(next_version) => { next_version }
+				Err(error) => { return SendResponse(ActionResult::Failure(error)) }
+			};
+			let error_message = match data.condition {
+				Some(PutCondition::IfVersion(version)) if version != current_version => {
+					Some(format!("Expected version {} but the record is at version {}", version, current_version))
+				}
+				Some(PutCondition::IfAbsent) if current_record.as_ref().is_some_and(|current_record| !current_record.is_tombstone()) => {
+					Some(format!("Record already holds a value at version {}", current_version))
+				}
+				_ => { None }
+			};
+			match (error_message, current_record) {
+				(Some(error_message), _) => { SendResponse(ActionResult::failure(ErrorKind::VersionMismatch { current_version }, error_message)) }
+				(None, Some(current_record)) if !data.is_chunk() => { ArchiveCurrentVersion(find_user_result.clone(), current_record, next_version) }
+				(None, _) => { CreateDataRecord(find_user_result.clone(), next_version) }
+			}
+		} else {
+			SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
 	}
 
-	pub(crate) fn create_data_record(&self, kad: &mut kad::Behaviour<MemoryStore>, data: RequestData) -> Self {
-		if let PutRequestState::CreateDataRecord(find_user_result) = self {
-			let data_put_result = kad.put_record(data.get_record(), Quorum::One);
+	pub(crate) fn archive_current_version(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
+		if let ArchiveCurrentVersion(find_user_result, current_record, version) = self {
+			match archive_record(kad, data.get_data_record_key(), current_record) {
+				Some(Ok(archive_put_query_id)) => { WaitingArchive(archive_put_query_id, find_user_result.clone(), *version) }
+				Some(Err(error)) => {
+					SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, format!("Error while archiving the current version: {:?}", error)))
+				}
+				None => { CreateDataRecord(find_user_result.clone(), *version) }
+			}
+		} else {
+			SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	/// Writes the value, once the version it replaces is archived.
+	pub(crate) fn create_data_record(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
+		if let CreateDataRecord(find_user_result, version) | WaitingArchive(_, find_user_result, version) = self {
+			let data_put_result = kad.put_record(data.get_record(*version), Quorum::One);
 			match data_put_result {
 				Ok(data_put_query_id) => {
 					println!("data_put_query_id in create_data_record put request state: {:?}", data_put_query_id);
-					WaitingDataCreate(data_put_query_id, find_user_result.clone())
+					WaitingDataCreate(data_put_query_id, find_user_result.clone(), *version)
 				}
 				Err(error) => {
-					SendResponse(ActionResult::Failure(format!("Error while putting data: {:?}", error)))
+					SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, format!("Error while putting data: {:?}", error)))
 				}
 			}
 		} else {
-			SendResponse(ActionResult::Failure("Invalid state".to_string()))
+			SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
 		}
 	}
-	pub(crate) fn create_or_update_user(&self, kad: &mut kad::Behaviour<MemoryStore>, data: RequestData) -> Self {
-		if let PutRequestState::WaitingDataCreate(data_create_query_id, find_user_result) = self {
-			let user_record = match find_user_result {
-				FindResult::Found(query_id, record) => {
-					let mut existing_user: User = record.clone().try_into().unwrap();
-					existing_user.add_data_record_keys(data.record_key.clone());
-					println!("existing_user with new data record key: {:?}", existing_user);
-					existing_user.try_into().unwrap()
-					// println!("updated_user_record: {:?}", updated_user_record);
-				}
-				FindResult::NotFound => {
-					let mut new_user = User::new(&data.get_user_record_key());
-					new_user.add_data_record_keys(data.record_key.clone());
-					println!("new user with new data record key: {:?}", new_user);
-					new_user.try_into().unwrap()
-					// println!("updated_user_record (New user): {:?}", updated_user_record);
-				}
+	/// Adds the key to the user's index once the value is stored. Chunks are not indexed, so their put is done.
+	pub(crate) fn create_or_update_user(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
+		if let PutRequestState::WaitingDataCreate(data_create_query_id, find_user_result, version) = self {
+			if data.is_chunk() {
+				return SendResponse(ActionResult::Stored { version: *version });
+			}
+			let user = match find_user_result {
+				FindResult::Found(_query_id, record) => { decode_user(record) }
+				FindResult::NotFound => { Ok(User::new(&data.get_user_record_key())) }
+			};
+			let user_record = user.and_then(|mut user| {
+				user.add_data_record(data.record_key.clone(), value_hash(&data.record_value.clone().unwrap_or_default()), data.owner_signature(), data.visibility, data.ttl, data.condition);
+				println!("user with new data record key: {:?}", user);
+				user.try_into()
+			});
+			let user_record = match user_record {
+				Ok(user_record) => { user_record }
+				Err(error) => { return SendResponse(ActionResult::Failure(error)) }
 			};
 			let user_put_result = kad.put_record(user_record, Quorum::One);
 			match user_put_result {
 				Ok(user_put_query_id) => {
-					WaitingUserCreate(user_put_query_id)
+					WaitingUserCreate(user_put_query_id, *version)
 				}
 				Err(error) => {
 					println!("Error while putting user: {:?}", error);
-					SendResponse(ActionResult::Failure("Error while putting user".to_string()))
+					SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, "Error while putting user"))
 				}
 			}
 		} else {
-			SendResponse(ActionResult::Failure("Invalid state".to_string()))
+			SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
 		}
 	}
 
-	pub(crate) fn update_waiting_put_request_list(&self, completed_query_id: QueryId) -> Self {
-		if let WaitingPut(query_id_list) = self {
-			let mut new_query_id_set = query_id_list.clone();
-			let removal_status = new_query_id_set.remove(&completed_query_id);
-			if removal_status {
-				println!("Value removed from set");
-			} else {
-				println!("Value not removed from set");
-			}
-			if new_query_id_set.is_empty() {
-				SendResponse(ActionResult::Success("Completed".to_string()))
-			} else {
-				println!("query_id_list count: {:?}", query_id_list.len());
-				WaitingPut(new_query_id_set)
-			}
-		} else {
-			SendResponse(ActionResult::Failure("Invalid state".to_string()))
+	/// The Kademlia query the request is waiting on, if any.
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			FindUser(query_id) | FindCurrentVersion(query_id, _) | WaitingArchive(query_id, _, _) | WaitingDataCreate(query_id, _, _) | WaitingUserCreate(query_id, _) => { Some(*query_id) }
+			PutRequestState::Verify | ArchiveCurrentVersion(_, _, _) | CreateDataRecord(_, _) | SendResponse(_) => { None }
 		}
 	}
 }
@@ -120,11 +204,14 @@ impl PutRequestState {
 #[derive(Debug, Clone)]
 pub(crate) enum GetRequestState {
 	Verify,
+	/// Looking up the grants of the record owner before reading a shared record.
+	FindGrant(QueryId),
 	FindUser(QueryId),
-	UserNotFound,
 	DataNotAssociatedWithUser,
 	FindData(User),
 	WaitingData(QueryId),
+	/// Reading an archived version of the record, see [`VersionSelector`].
+	FindVersion(QueryId),
 	CouldNotGetData,
 	SendResponse(ActionResult),
 	Invalid,
@@ -132,44 +219,549 @@ pub(crate) enum GetRequestState {
 
 
 impl GetRequestState {
-	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<MemoryStore>, verification_result: VerifyRequestResult, user_key: RecordKey) -> Self {
+	/// `grant_key` is set when the user reads a record of another user, who must have granted them access.
+	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, verification_result: VerifyRequestResult, user_key: RecordKey, grant_key: Option<RecordKey>) -> Self {
 		if let GetRequestState::Verify = self {
 			println!("Verification result: {:?}", verification_result);
 			match verification_result {
+				VerifyRequestResult::Success if grant_key.is_some() => {
+					GetRequestState::FindGrant(kad.get_record(grant_key.unwrap()))
+				}
 				VerifyRequestResult::Success => {
 					println!("user public key: {:?}", user_key);
 					let get_user_query_id = kad.get_record(user_key);
 					println!("get_user_query_id: {:?}", get_user_query_id);
 					GetRequestState::FindUser(get_user_query_id)
 				}
-				VerifyRequestResult::Failed(_) => {
-					GetRequestState::SendResponse(ActionResult::Failure("Invalid request".to_string()))
+				VerifyRequestResult::Failed(error) => {
+					GetRequestState::SendResponse(ActionResult::failure(error.kind, format!("Invalid request: {}", error.message)))
+				}
+			}
+		} else {
+			GetRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	/// Continues with the owner's index once a grant covering the record key is found. Grant records returned by
+	/// other peers have not been through this node's validation yet, so their signatures are checked here.
+	pub(crate) fn find_grant_result(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_grant_result: FindResult, data: RequestData) -> Self {
+		if let GetRequestState::FindGrant(_) = self {
+			let is_granted = match find_grant_result {
+				FindResult::Found(_, record) => {
+					validate_record(&record, None).is_ok() && GrantRecord::from_bytes(&record.value).is_some_and(|grant_record| grant_record.allows(&data.record_key, data.value_key.as_deref()))
 				}
+				FindResult::NotFound => { false }
+			};
+			if is_granted {
+				GetRequestState::FindUser(kad.get_record(RecordKey::new(data.get_owner_public_key())))
+			} else {
+				GetRequestState::SendResponse(ActionResult::failure(ErrorKind::Unauthorized, "Access not granted"))
 			}
 		} else {
-			GetRequestState::SendResponse(ActionResult::Failure("Invalid state".to_string()))
+			GetRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
 		}
 	}
 
 	pub(crate) fn find_user_result(&self, find_user_result: FindResult) -> Self {
 		match find_user_result {
-			FindResult::Found(_, record) => { FindData(record.try_into().unwrap()) }
+			FindResult::Found(_, record) => {
+				match decode_user(&record) {
+					Ok(user) => { FindData(user) }
+					Err(error) => { GetRequestState::SendResponse(ActionResult::Failure(error)) }
+				}
+			}
 			FindResult::NotFound => { DataNotAssociatedWithUser }
 		}
 	}
-	pub(crate) fn find_data_record(&self, kad: &mut kad::Behaviour<MemoryStore>, data: RequestData) -> Self {
+	pub(crate) fn find_data_record(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
 		let get_data_query_id = kad.get_record(data.get_data_record_key());
 		// println!("get_data_query_id in GetRequestState find_data_record : {:?}", get_data_query_id);
 		WaitingData(get_data_query_id)
 	}
 
-	pub(crate) fn find_data_result(&self, find_user_result: FindResult) -> Self {
-		match find_user_result {
+	/// Answers with the record, or with the version the request selects. Older versions are read from their history
+	/// key, walking back one version at a time for reads at a point in time.
+	///
+	/// Peers whose clock is behind may still serve a value that expired, which is read as missing.
+	pub(crate) fn find_data_result(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_data_result: FindResult, data: &RequestData) -> Self {
+		let data_record = match find_data_result {
 			FindResult::Found(_, record) => {
 				println!("Found: {:?}", record);
-				GetRequestState::SendResponse(ActionResult::Success(String::from_utf8(record.value).unwrap()))
+				match decode_data_record(&record) {
+					Ok(data_record) if data_record.is_expired() => { return CouldNotGetData }
+					Ok(data_record) => { data_record }
+					Err(error) => { return GetRequestState::SendResponse(ActionResult::Failure(error)) }
+				}
+			}
+			FindResult::NotFound if matches!(self, GetRequestState::FindVersion(_)) => {
+				return GetRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, "Version is no longer retained"));
+			}
+			FindResult::NotFound => { return CouldNotGetData }
+		};
+
+		let (is_selected, older_version) = match data.version {
+			None => { (true, None) }
+			Some(VersionSelector::Version(version)) => {
+				(data_record.version() == version, Some(version).filter(|version| *version < data_record.version()))
+			}
+			Some(VersionSelector::At(timestamp)) => {
+				(data_record.timestamp() <= timestamp, data_record.version().checked_sub(1))
+			}
+		};
+		// History starts at version 1, see `archive_record`.
+		let older_version = older_version.filter(|older_version| *older_version >= 1);
+		if is_selected {
+			let action_result = match data_record {
+				DataRecord::Value { value, .. } | DataRecord::Published { value, .. } | DataRecord::Unsigned(value) => { ActionResult::Value(value) }
+				DataRecord::Tombstone { .. } => { ActionResult::failure(ErrorKind::Deleted, "Record deleted") }
+			};
+			return GetRequestState::SendResponse(action_result);
+		}
+		match older_version {
+			Some(older_version) => { GetRequestState::FindVersion(kad.get_record(history_record_key(&data.clone().get_data_record_key(), older_version))) }
+			None => { GetRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, "Version not found")) }
+		}
+	}
+
+	/// The Kademlia query the request is waiting on, if any.
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			GetRequestState::FindGrant(query_id) | GetRequestState::FindUser(query_id) | WaitingData(query_id) | GetRequestState::FindVersion(query_id) => { Some(*query_id) }
+			GetRequestState::Verify | DataNotAssociatedWithUser | FindData(_) | CouldNotGetData | GetRequestState::SendResponse(_) | GetRequestState::Invalid => { None }
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum DeleteRequestState {
+	Verify,
+	FindUser(QueryId),
+	/// Reading the stored data record, so that the tombstone is stored at the version after it.
+	FindCurrentVersion(QueryId, User),
+	/// The stored record is kept under its history key before the tombstone replaces it.
+	WaitingArchive(QueryId, User, u64),
+	WaitingTombstoneCreate(QueryId, User),
+	WaitingUserUpdate(QueryId),
+	SendResponse(ActionResult),
+}
+
+impl DeleteRequestState {
+	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, verification_result: VerifyRequestResult, user_key: RecordKey) -> Self {
+		if let DeleteRequestState::Verify = self {
+			match verification_result {
+				VerifyRequestResult::Success => {
+					DeleteRequestState::FindUser(kad.get_record(user_key))
+				}
+				VerifyRequestResult::Failed(error) => {
+					DeleteRequestState::SendResponse(ActionResult::failure(error.kind, format!("Invalid request: {}", error.message)))
+				}
+			}
+		} else {
+			DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	/// Looks up the data record once the user is known to own the key. Chunks are not in the index, and only the
+	/// owner can sign a delete of the chunks under their keys.
+	pub(crate) fn find_user_result(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_user_result: FindResult, data: RequestData) -> Self {
+		if let DeleteRequestState::FindUser(_) = self {
+			match find_user_result {
+				FindResult::Found(_, record) => {
+					let user = match decode_user(&record) {
+						Ok(user) => { user }
+						Err(error) => { return DeleteRequestState::SendResponse(ActionResult::Failure(error)) }
+					};
+					if !data.is_chunk() && !user.contains_data_record_key(data.record_key.clone()) {
+						return DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, "Data not associated with user"));
+					}
+					DeleteRequestState::FindCurrentVersion(kad.get_record(data.get_data_record_key()), user)
+				}
+				FindResult::NotFound => {
+					DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, "User not found"))
+				}
+			}
+		} else {
+			DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	/// Archives the data record, then replaces it with a tombstone at the next version.
+	pub(crate) fn create_tombstone(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_data_result: FindResult, data: RequestData) -> Self {
+		if let DeleteRequestState::FindCurrentVersion(_, user) = self {
+			let current_record = match find_data_result {
+				FindResult::Found(_, record) => {
+					match decode_data_record(&record) {
+						Ok(current_record) => { Some(current_record) }
+						Err(error) => { return DeleteRequestState::SendResponse(ActionResult::Failure(error)) }
+					}
+				}
+				FindResult::NotFound => { None }
+			};
+			if let Err(error) = check_newer_than(current_record.as_ref(), &data.owner_signature()) {
+				return DeleteRequestState::SendResponse(ActionResult::Failure(error));
+			}
+			let version = match next_version(current_record.as_ref().map_or(0, DataRecord::version)) {
+				Ok(version) => { version }
+				Err(error) => { return DeleteRequestState::SendResponse(ActionResult::Failure(error)) }
+			};
+			match current_record.filter(|_| !data.is_chunk()).and_then(|current_record| archive_record(kad, data.clone().get_data_record_key(), &current_record)) {
+				Some(Ok(archive_put_query_id)) => { DeleteRequestState::WaitingArchive(archive_put_query_id, user.clone(), version) }
+				Some(Err(error)) => {
+					DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, format!("Error while archiving the current version: {:?}", error)))
+				}
+				None => { Self::put_tombstone(kad, user, version, data) }
+			}
+		} else {
+			DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn current_version_archived(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
+		if let DeleteRequestState::WaitingArchive(_, user, version) = self {
+			Self::put_tombstone(kad, user, *version, data)
+		} else {
+			DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	fn put_tombstone(kad: &mut kad::Behaviour<DisruptedDataStore>, user: &User, version: u64, data: RequestData) -> Self {
+		match kad.put_record(data.get_tombstone_record(version), Quorum::One) {
+			Ok(tombstone_put_query_id) => {
+				DeleteRequestState::WaitingTombstoneCreate(tombstone_put_query_id, user.clone())
+			}
+			Err(error) => {
+				DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, format!("Error while deleting data: {:?}", error)))
+			}
+		}
+	}
+
+	pub(crate) fn remove_key_from_user(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
+		if let DeleteRequestState::WaitingTombstoneCreate(_, user) = self {
+			if data.is_chunk() {
+				return DeleteRequestState::SendResponse(ActionResult::Deleted);
+			}
+			let mut updated_user = user.clone();
+			updated_user.remove_data_record_key(&data.record_key, data.owner_signature());
+			let user_record = match updated_user.try_into() {
+				Ok(user_record) => { user_record }
+				Err(error) => { return DeleteRequestState::SendResponse(ActionResult::Failure(error)) }
+			};
+			match kad.put_record(user_record, Quorum::One) {
+				Ok(user_put_query_id) => {
+					DeleteRequestState::WaitingUserUpdate(user_put_query_id)
+				}
+				Err(error) => {
+					println!("Error while putting user: {:?}", error);
+					DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, "Error while putting user"))
+				}
+			}
+		} else {
+			DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn user_updated(&self) -> Self {
+		if let DeleteRequestState::WaitingUserUpdate(_) = self {
+			DeleteRequestState::SendResponse(ActionResult::Deleted)
+		} else {
+			DeleteRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	/// The Kademlia query the request is waiting on, if any.
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			DeleteRequestState::FindUser(query_id) | DeleteRequestState::FindCurrentVersion(query_id, _) | DeleteRequestState::WaitingArchive(query_id, _, _) | DeleteRequestState::WaitingTombstoneCreate(query_id, _) | DeleteRequestState::WaitingUserUpdate(query_id) => {
+				Some(*query_id)
+			}
+			DeleteRequestState::Verify | DeleteRequestState::SendResponse(_) => { None }
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum IndexRequestState {
+	Verify,
+	FindUser(QueryId),
+	SendResponse(ActionResult),
+}
+
+impl IndexRequestState {
+	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, verification_result: VerifyRequestResult, user_key: RecordKey) -> Self {
+		if let IndexRequestState::Verify = self {
+			match verification_result {
+				VerifyRequestResult::Success => {
+					IndexRequestState::FindUser(kad.get_record(user_key))
+				}
+				VerifyRequestResult::Failed(error) => {
+					IndexRequestState::SendResponse(ActionResult::failure(error.kind, format!("Invalid request: {}", error.message)))
+				}
+			}
+		} else {
+			IndexRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn find_user_result(&self, find_user_result: FindResult, data: RequestData) -> Self {
+		if let IndexRequestState::FindUser(_) = self {
+			// A user without an index record has not stored anything yet.
+			let user = match find_user_result {
+				FindResult::Found(_, record) => {
+					match decode_user(&record) {
+						Ok(user) => { Some(user) }
+						Err(error) => { return IndexRequestState::SendResponse(ActionResult::Failure(error)) }
+					}
+				}
+				FindResult::NotFound => { None }
+			};
+			let action_result = match data.index_query.unwrap_or_default() {
+				IndexQuery::List { prefix, cursor, limit } => {
+					let limit = limit.map_or(DEFAULT_LIST_LIMIT, |limit| (limit as usize).clamp(1, MAX_LIST_LIMIT));
+					let list_result = match user {
+						Some(user) => { user.list_data_record_keys(&prefix, &cursor, limit) }
+						None => { ListResult { keys: vec![], next_cursor: None } }
+					};
+					ActionResult::List(list_result)
+				}
+				IndexQuery::Proof { record_key } => {
+					match user.and_then(|user| user.merkle_tree().proof(&record_key)) {
+						Some(merkle_proof) => { ActionResult::Proof(merkle_proof) }
+						None => { ActionResult::failure(ErrorKind::NotFound, "Data not associated with user") }
+					}
+				}
+				IndexQuery::Subtree { depth, index } => {
+					let merkle_tree = user.map_or_else(|| MerkleTree::new(&BTreeMap::new()), |user| user.merkle_tree());
+					match merkle_tree.subtree(depth, index) {
+						Some(merkle_subtree) => { ActionResult::Subtree(merkle_subtree) }
+						None => { ActionResult::failure(ErrorKind::InvalidRequest, "Invalid subtree position") }
+					}
+				}
+			};
+			IndexRequestState::SendResponse(action_result)
+		} else {
+			IndexRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			IndexRequestState::FindUser(query_id) => { Some(*query_id) }
+			IndexRequestState::Verify | IndexRequestState::SendResponse(_) => { None }
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum GrantRequestState {
+	Verify,
+	FindGrants(QueryId),
+	WaitingPut(QueryId),
+	SendResponse(ActionResult),
+}
+
+impl GrantRequestState {
+	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, verification_result: VerifyRequestResult, data: RequestData) -> Self {
+		if let GrantRequestState::Verify = self {
+			let grant_request = match data.grant_request {
+				Some(grant_request) => { grant_request }
+				None => { return GrantRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state")) }
+			};
+			// Readers must be able to unwrap the content keys of encrypted values, so only secp256k1 keys are accepted.
+			if decode(&grant_request.reader_public_key).ok().and_then(|reader_public_key| PublicKey::from_slice(&reader_public_key).ok()).is_none() {
+				return GrantRequestState::SendResponse(ActionResult::failure(ErrorKind::InvalidRequest, "Invalid reader public key"));
 			}
-			FindResult::NotFound => { CouldNotGetData }
+			match verification_result {
+				VerifyRequestResult::Success => {
+					GrantRequestState::FindGrants(kad.get_record(grant_record_key(&grant_request.user_public_key, &grant_request.reader_public_key)))
+				}
+				VerifyRequestResult::Failed(error) => {
+					GrantRequestState::SendResponse(ActionResult::failure(error.kind, format!("Invalid request: {}", error.message)))
+				}
+			}
+		} else {
+			GrantRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
 		}
 	}
-}
\ No newline at end of file
+
+	/// Adds the grant, or the revoke, to the grants the owner already gave the reader.
+	pub(crate) fn store_grant(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_grants_result: FindResult, data: RequestData) -> Self {
+		if let (GrantRequestState::FindGrants(_), Some(grant_request)) = (self, &data.grant_request) {
+			let mut grant_record = match find_grants_result {
+				FindResult::Found(_, record) => { GrantRecord::from_bytes(&record.value) }
+				FindResult::NotFound => { None }
+			}.unwrap_or_else(|| GrantRecord::new(&grant_request.user_public_key, &grant_request.reader_public_key));
+			grant_record.apply(Grant {
+				scope: grant_request.scope.clone(),
+				revoked: grant_request.revoke,
+				owner: data.owner_signature(),
+			});
+			match kad.put_record(grant_record.to_record(), Quorum::One) {
+				Ok(grant_put_query_id) => {
+					GrantRequestState::WaitingPut(grant_put_query_id)
+				}
+				Err(error) => {
+					GrantRequestState::SendResponse(ActionResult::failure(ErrorKind::QuotaExceeded, format!("Error while putting grant: {:?}", error)))
+				}
+			}
+		} else {
+			GrantRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn grant_stored(&self, data: RequestData) -> Self {
+		match (self, data.grant_request) {
+			(GrantRequestState::WaitingPut(_), Some(grant_request)) if grant_request.revoke => {
+				GrantRequestState::SendResponse(ActionResult::Revoked)
+			}
+			(GrantRequestState::WaitingPut(_), Some(_)) => {
+				GrantRequestState::SendResponse(ActionResult::Granted)
+			}
+			_ => { GrantRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state")) }
+		}
+	}
+
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			GrantRequestState::FindGrants(query_id) | GrantRequestState::WaitingPut(query_id) => { Some(*query_id) }
+			GrantRequestState::Verify | GrantRequestState::SendResponse(_) => { None }
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum PublicGetRequestState {
+	Find,
+	WaitingData(QueryId),
+	SendResponse(ActionResult),
+}
+
+impl PublicGetRequestState {
+	/// Public records are read straight from their data record. The owner's index is not needed, because the
+	/// record itself carries the signature the reader checks.
+	pub(crate) fn find_data_record(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, data: RequestData) -> Self {
+		if let PublicGetRequestState::Find = self {
+			PublicGetRequestState::WaitingData(kad.get_record(data.get_data_record_key()))
+		} else {
+			PublicGetRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn find_data_result(&self, find_data_result: FindResult) -> Self {
+		if let PublicGetRequestState::WaitingData(_) = self {
+			let action_result = match find_data_result {
+				FindResult::Found(_, record) => {
+					match decode_data_record(&record) {
+						Ok(DataRecord::Published { record_key, value, ttl, condition, owner, .. }) => {
+							ActionResult::PublicValue(PublicRecord {
+								owner_public_key: owner.user_public_key,
+								record_key,
+								value,
+								timestamp: owner.timestamp,
+								nonce: owner.nonce,
+								signature: owner.signature,
+								ttl,
+								condition,
+							})
+						}
+						Ok(DataRecord::Tombstone { .. }) => { ActionResult::failure(ErrorKind::Deleted, "Record deleted") }
+						Ok(DataRecord::Value { .. } | DataRecord::Unsigned(_)) => { ActionResult::failure(ErrorKind::Unauthorized, "Record is not public") }
+						Err(error) => { ActionResult::Failure(error) }
+					}
+				}
+				FindResult::NotFound => { ActionResult::failure(ErrorKind::NotFound, "Record Not found") }
+			};
+			PublicGetRequestState::SendResponse(action_result)
+		} else {
+			PublicGetRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			PublicGetRequestState::WaitingData(query_id) => { Some(*query_id) }
+			PublicGetRequestState::Find | PublicGetRequestState::SendResponse(_) => { None }
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum HistoryRequestState {
+	Verify,
+	FindCurrent(QueryId),
+	/// Reading the archived version before the oldest one listed so far.
+	FindVersion(QueryId, Vec<RecordVersion>),
+	SendResponse(ActionResult),
+}
+
+impl HistoryRequestState {
+	pub(crate) fn verify(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, verification_result: VerifyRequestResult, data: RequestData) -> Self {
+		if let HistoryRequestState::Verify = self {
+			match verification_result {
+				VerifyRequestResult::Success => {
+					HistoryRequestState::FindCurrent(kad.get_record(data.get_data_record_key()))
+				}
+				VerifyRequestResult::Failed(error) => {
+					HistoryRequestState::SendResponse(ActionResult::failure(error.kind, format!("Invalid request: {}", error.message)))
+				}
+			}
+		} else {
+			HistoryRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state"))
+		}
+	}
+
+	/// Lists the version found and walks back to the one before it, until the node's retention is reached or an
+	/// older version is no longer retained.
+	pub(crate) fn find_version_result(&self, kad: &mut kad::Behaviour<DisruptedDataStore>, find_result: FindResult, data: RequestData) -> Self {
+		let record_versions = match (self, find_result) {
+			(HistoryRequestState::FindCurrent(_), FindResult::NotFound) => {
+				return HistoryRequestState::SendResponse(ActionResult::failure(ErrorKind::NotFound, "Record Not found"));
+			}
+			(HistoryRequestState::FindVersion(_, record_versions), FindResult::NotFound) => {
+				return HistoryRequestState::SendResponse(ActionResult::History(record_versions.clone()));
+			}
+			(HistoryRequestState::FindCurrent(_), FindResult::Found(_, record)) => {
+				match decode_data_record(&record) {
+					Ok(data_record) => { vec![data_record.record_version()] }
+					Err(error) => { return HistoryRequestState::SendResponse(ActionResult::Failure(error)) }
+				}
+			}
+			(HistoryRequestState::FindVersion(_, record_versions), FindResult::Found(_, record)) => {
+				let mut record_versions = record_versions.clone();
+				match decode_data_record(&record) {
+					Ok(data_record) => { record_versions.push(data_record.record_version()) }
+					Err(error) => { return HistoryRequestState::SendResponse(ActionResult::Failure(error)) }
+				}
+				record_versions
+			}
+			_ => { return HistoryRequestState::SendResponse(ActionResult::failure(ErrorKind::Internal, "Invalid state")) }
+		};
+
+		// History starts at version 1, see `archive_record`.
+		let oldest_version = record_versions.last().map_or(0, |record_version| record_version.version);
+		if oldest_version <= 1 || record_versions.len() >= kad.store_mut().history_retention() {
+			return HistoryRequestState::SendResponse(ActionResult::History(record_versions));
+		}
+		let history_key = history_record_key(&data.get_data_record_key(), oldest_version - 1);
+		HistoryRequestState::FindVersion(kad.get_record(history_key), record_versions)
+	}
+
+	pub(crate) fn query_id(&self) -> Option<QueryId> {
+		match self {
+			HistoryRequestState::FindCurrent(query_id) | HistoryRequestState::FindVersion(query_id, _) => { Some(*query_id) }
+			HistoryRequestState::Verify | HistoryRequestState::SendResponse(_) => { None }
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn versions_stop_at_the_highest_version() {
+		assert_eq!(next_version(0).unwrap(), 1);
+		assert_eq!(next_version(MAX_RECORD_VERSION - 1).unwrap(), MAX_RECORD_VERSION);
+		assert_eq!(next_version(MAX_RECORD_VERSION).unwrap_err().kind, ErrorKind::Conflict);
+		assert_eq!(next_version(u64::MAX).unwrap_err().kind, ErrorKind::Conflict);
+	}
+}
@@ -0,0 +1,554 @@
+use disrupted_data_sdk_rs::merkle::value_hash;
+use disrupted_data_sdk_rs::{current_timestamp_millis, is_chunk_of, DisruptedDataError, ErrorKind, GrantRequest, GrantScope, PutCondition, RecordVersion, SignedPayload, Visibility, CHUNK_KEY_PREFIX};
+use hex::encode;
+use libp2p::kad::{Record, RecordKey};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::time::{Instant, SystemTime};
+
+use crate::p2p::store::to_instant;
+
+/// Prefix written in front of every encoded [`DataRecord`], followed by the format it is encoded in. Values
+/// stored before data records had a format do not carry it and are read back as [`DataRecord::Unsigned`].
+const DATA_RECORD_MAGIC: &[u8; 3] = b"DDR";
+
+/// Format data records are written in. Records carry a version since format `3`, a TTL since format `4` and the
+/// condition of the put since format `5`, see [`DataRecord::from_bytes`].
+const DATA_RECORD_FORMAT: u8 = b'5';
+
+/// Highest version a data record may be stored at. Records from other peers beyond it are rejected, so the next
+/// version of a stored record can always be counted.
+pub const MAX_RECORD_VERSION: u64 = u32::MAX as u64;
+
+/// Prefix written in front of every encoded [`GrantRecord`].
+const GRANT_RECORD_MAGIC: &[u8; 4] = b"DDG1";
+
+/// Owner signature of the request that produced a record. It travels with the record so that any peer can
+/// rebuild the signed payload and check that the owner really wrote it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OwnerSignature {
+	/// Hex encoded public key of the owner, as sent in requests.
+	pub user_public_key: Vec<u8>,
+	pub timestamp: u64,
+	pub nonce: u64,
+	pub signature: Vec<u8>,
+}
+
+/// Value stored in the DHT under a user's data record key.
+///
+/// Every put and delete of a key stores the record at the next version, counting from 1 for the first write.
+/// The node that serves the request assigns the version, so it is not covered by the owner signature and replicas
+/// are ordered by the signature instead, see [`DataRecord::can_replace`].
+/// The TTL of a value is signed by the owner, in seconds from the timestamp of the owner signature. The condition of
+/// the put that stored the value is kept only because the owner signed it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum DataRecord {
+	Value {
+		record_key: String,
+		value: Vec<u8>,
+		version: u64,
+		ttl: Option<u64>,
+		condition: Option<PutCondition>,
+		owner: OwnerSignature,
+	},
+	/// Left behind by a delete so that older replicas of the value held by other peers cannot overwrite it.
+	/// The owner signature over the delete lets any peer check that the tombstone is genuine.
+	Tombstone {
+		record_key: String,
+		version: u64,
+		owner: OwnerSignature,
+	},
+	/// Value written before records carried their owner's signature.
+	Unsigned(Vec<u8>),
+	/// Value the owner published. Anyone may read it, see `Actions::GetPublic`.
+	Published {
+		record_key: String,
+		value: Vec<u8>,
+		version: u64,
+		ttl: Option<u64>,
+		condition: Option<PutCondition>,
+		owner: OwnerSignature,
+	},
+}
+
+/// Layout of a data record in any of its formats. `V` is the version, `T` the TTL and `C` the condition, or
+/// [`Absent`] in formats written before records carried them.
+#[derive(Deserialize)]
+enum EncodedDataRecord<V, T, C> {
+	Value {
+		record_key: String,
+		value: Vec<u8>,
+		version: V,
+		ttl: T,
+		condition: C,
+		owner: OwnerSignature,
+	},
+	Tombstone {
+		record_key: String,
+		version: V,
+		owner: OwnerSignature,
+	},
+	Unsigned(Vec<u8>),
+	Published {
+		record_key: String,
+		value: Vec<u8>,
+		version: V,
+		ttl: T,
+		condition: C,
+		owner: OwnerSignature,
+	},
+}
+
+/// Field a data record format does not have. It takes no bytes, and reads back as version 0, or as no TTL or
+/// condition.
+#[derive(Deserialize)]
+struct Absent;
+
+impl From<Absent> for u64 {
+	fn from(_: Absent) -> Self {
+		0
+	}
+}
+
+impl From<Absent> for Option<u64> {
+	fn from(_: Absent) -> Self {
+		None
+	}
+}
+
+impl From<Absent> for Option<PutCondition> {
+	fn from(_: Absent) -> Self {
+		None
+	}
+}
+
+impl<V, T, C> EncodedDataRecord<V, T, C>
+where
+	V: DeserializeOwned + Into<u64>,
+	T: DeserializeOwned + Into<Option<u64>>,
+	C: DeserializeOwned + Into<Option<PutCondition>>,
+{
+	fn decode(encoded_record: &[u8]) -> Result<DataRecord, DisruptedDataError> {
+		let encoded_record: Self = bincode::deserialize(encoded_record)
+			.map_err(|_| DisruptedDataError::new(ErrorKind::InvalidRequest, "Invalid data record"))?;
+		Ok(match encoded_record {
+			EncodedDataRecord::Value { record_key, value, version, ttl, condition, owner } => {
+				DataRecord::Value { record_key, value, version: version.into(), ttl: ttl.into(), condition: condition.into(), owner }
+			}
+			EncodedDataRecord::Tombstone { record_key, version, owner } => { DataRecord::Tombstone { record_key, version: version.into(), owner } }
+			EncodedDataRecord::Unsigned(value) => { DataRecord::Unsigned(value) }
+			EncodedDataRecord::Published { record_key, value, version, ttl, condition, owner } => {
+				DataRecord::Published { record_key, value, version: version.into(), ttl: ttl.into(), condition: condition.into(), owner }
+			}
+		})
+	}
+}
+
+impl DataRecord {
+	/// Decodes a data record in any of its formats. Values without the magic prefix predate data records and are
+	/// read as [`DataRecord::Unsigned`], while a record in a known format that cannot be decoded is an error.
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DisruptedDataError> {
+		match bytes.strip_prefix(DATA_RECORD_MAGIC).and_then(<[u8]>::split_first) {
+			Some((b'5', encoded_record)) => { EncodedDataRecord::<u64, Option<u64>, Option<PutCondition>>::decode(encoded_record) }
+			Some((b'4', encoded_record)) => { EncodedDataRecord::<u64, Option<u64>, Absent>::decode(encoded_record) }
+			Some((b'3', encoded_record)) => { EncodedDataRecord::<u64, Absent, Absent>::decode(encoded_record) }
+			Some((b'2', encoded_record)) => { EncodedDataRecord::<Absent, Absent, Absent>::decode(encoded_record) }
+			_ => { Ok(DataRecord::Unsigned(bytes.to_vec())) }
+		}
+	}
+
+	/// Whether `bytes` hold an encoded data record rather than a user's key index.
+	pub fn is_data_record(bytes: &[u8]) -> bool {
+		bytes.strip_prefix(DATA_RECORD_MAGIC).and_then(<[u8]>::first).is_some_and(|format| matches!(format, b'2'..=DATA_RECORD_FORMAT))
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = DATA_RECORD_MAGIC.to_vec();
+		bytes.push(DATA_RECORD_FORMAT);
+		bytes.append(&mut bincode::serialize(self).expect("Data records are always serializable"));
+		bytes
+	}
+
+	/// The record expires with the value it holds, on every peer that stores it.
+	pub fn to_record(&self, key: RecordKey) -> Record {
+		let mut record = Record::new(key, self.to_bytes());
+		record.expires = self.expires();
+		record
+	}
+
+	/// Signature of the owner's request that wrote the record, `None` for unsigned records.
+	pub fn owner(&self) -> Option<&OwnerSignature> {
+		match self {
+			DataRecord::Value { owner, .. } | DataRecord::Published { owner, .. } | DataRecord::Tombstone { owner, .. } => { Some(owner) }
+			DataRecord::Unsigned(_) => { None }
+		}
+	}
+
+	pub fn timestamp(&self) -> u64 {
+		match self {
+			DataRecord::Value { owner, .. } | DataRecord::Published { owner, .. } | DataRecord::Tombstone { owner, .. } => { owner.timestamp }
+			DataRecord::Unsigned(_) => { 0 }
+		}
+	}
+
+	/// Version of the record, 0 for records written before records carried a version.
+	pub fn version(&self) -> u64 {
+		match self {
+			DataRecord::Value { version, .. } | DataRecord::Published { version, .. } | DataRecord::Tombstone { version, .. } => { *version }
+			DataRecord::Unsigned(_) => { 0 }
+		}
+	}
+
+	/// Unix time in milliseconds at which the value expires, `None` for records that never expire.
+	pub fn expires_at(&self) -> Option<u64> {
+		match self {
+			DataRecord::Value { ttl: Some(ttl), owner, .. } | DataRecord::Published { ttl: Some(ttl), owner, .. } => {
+				Some(owner.timestamp.saturating_add(ttl.saturating_mul(1000)))
+			}
+			_ => { None }
+		}
+	}
+
+	/// [`DataRecord::expires_at`] as an instant of this node's clock, as kept in `Record::expires`.
+	pub fn expires(&self) -> Option<Instant> {
+		Some(to_instant(self.expires_at()?, SystemTime::now()).unwrap_or_else(Instant::now))
+	}
+
+	/// Whether the value expired, so the key no longer holds it.
+	pub fn is_expired(&self) -> bool {
+		self.expires_at().is_some_and(|expires_at| expires_at <= current_timestamp_millis())
+	}
+
+	/// Whether the key holds no value, because it was deleted.
+	pub fn is_tombstone(&self) -> bool {
+		matches!(self, DataRecord::Tombstone { .. })
+	}
+
+	/// Timestamp and nonce of the owner signature, which order the writes of a key. Unsigned records come first.
+	pub fn signed_order(&self) -> (u64, u64) {
+		self.owner().map_or((0, 0), |owner| (owner.timestamp, owner.nonce))
+	}
+
+	/// Whether this record may replace `existing` in the store. Records are ordered by [`DataRecord::signed_order`]
+	/// rather than by version, which the owner does not sign, so stale replicas cannot resurrect deleted data or
+	/// roll back newer values by claiming a higher version. A record signed for the same write only replaces an
+	/// identical copy.
+	pub fn can_replace(&self, existing: &DataRecord) -> bool {
+		match self.signed_order().cmp(&existing.signed_order()) {
+			Ordering::Greater => { true }
+			Ordering::Equal => { self == existing }
+			Ordering::Less => { false }
+		}
+	}
+
+	/// Summary of the record as listed by `Actions::History`.
+	pub fn record_version(&self) -> RecordVersion {
+		let value_hash = match self {
+			DataRecord::Value { value, .. } | DataRecord::Published { value, .. } | DataRecord::Unsigned(value) => { Some(value_hash(value)) }
+			DataRecord::Tombstone { .. } => { None }
+		};
+		let (ttl, visibility) = match self {
+			DataRecord::Value { ttl, .. } => { (*ttl, Visibility::Private) }
+			DataRecord::Published { ttl, .. } => { (*ttl, Visibility::Public) }
+			DataRecord::Tombstone { .. } | DataRecord::Unsigned(_) => { (None, Visibility::Private) }
+		};
+		RecordVersion { version: self.version(), timestamp: self.timestamp(), value_hash, ttl, visibility }
+	}
+
+	/// The payload the owner signed for this record, or `None` for unsigned records.
+	pub fn signed_payload(&self) -> Option<SignedPayload> {
+		match self {
+			DataRecord::Value { record_key, value, ttl, condition, owner, .. } => {
+				Some(SignedPayload::new("put", &owner.user_public_key, record_key, owner.timestamp, owner.nonce)
+					.with_value(value)
+					.with_ttl(*ttl)
+					.with_condition(*condition))
+			}
+			DataRecord::Published { record_key, value, ttl, condition, owner, .. } => {
+				Some(SignedPayload::new("put", &owner.user_public_key, record_key, owner.timestamp, owner.nonce)
+					.with_value(value)
+					.with_visibility(Visibility::Public)
+					.with_ttl(*ttl)
+					.with_condition(*condition))
+			}
+			DataRecord::Tombstone { record_key, owner, .. } => {
+				Some(SignedPayload::new("delete", &owner.user_public_key, record_key, owner.timestamp, owner.nonce))
+			}
+			DataRecord::Unsigned(_) => { None }
+		}
+	}
+}
+
+/// A put or delete must be signed after the write that produced `current_record`, as ordered by timestamp and then
+/// nonce. Nodes only remember the nonces of the requests they served, so this keeps a write captured on one node
+/// from being replayed through another, or pushed as a record over Kademlia, to roll the record back.
+pub(crate) fn check_newer_than(current_record: Option<&DataRecord>, request_owner: &OwnerSignature) -> Result<(), DisruptedDataError> {
+	match current_record.and_then(DataRecord::owner) {
+		Some(current_owner) if (request_owner.timestamp, request_owner.nonce) <= (current_owner.timestamp, current_owner.nonce) => {
+			Err(DisruptedDataError::new(ErrorKind::Replayed, "Request is not newer than the stored record".to_string()))
+		}
+		_ => { Ok(()) }
+	}
+}
+
+/// DHT key of a user's data record: the hex encoding of the user's hex public key followed by the record key.
+pub fn data_record_key(user_public_key: &[u8], record_key: &str) -> RecordKey {
+	let mut record_key_bytes: Vec<u8> = user_public_key.to_vec();
+	record_key_bytes.extend_from_slice(record_key.as_bytes());
+	RecordKey::new(&encode(record_key_bytes))
+}
+
+/// DHT key under which `version` of the data record stored under `data_record_key` is kept once a newer
+/// version replaces it. Data record keys are hex encoded, so the `/` keeps the key and the version apart.
+pub fn history_record_key(data_record_key: &RecordKey, version: u64) -> RecordKey {
+	let mut record_key_bytes = b"history/".to_vec();
+	record_key_bytes.extend_from_slice(data_record_key.as_ref());
+	record_key_bytes.push(b'/');
+	record_key_bytes.extend_from_slice(version.to_string().as_bytes());
+	RecordKey::new(&record_key_bytes)
+}
+
+/// Splits a [`history_record_key`] into the data record key and the version, or `None` for any other key.
+pub fn parse_history_record_key(key: &RecordKey) -> Option<(RecordKey, u64)> {
+	let history_key = key.as_ref().strip_prefix(b"history/")?;
+	let separator = history_key.iter().rposition(|byte| *byte == b'/')?;
+	let version = std::str::from_utf8(&history_key[separator + 1..]).ok()?.parse().ok()?;
+	Some((RecordKey::from(history_key[..separator].to_vec()), version))
+}
+
+/// Access an owner gave one reader, stored in the DHT under [`grant_record_key`]. Every grant carries the
+/// owner's signature so that any peer can check it before serving the owner's records to the reader.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GrantRecord {
+	/// Hex encoded public keys, as sent in requests.
+	pub owner_public_key: Vec<u8>,
+	pub reader_public_key: Vec<u8>,
+	/// The latest grant or revoke for every scope.
+	pub grants: Vec<Grant>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+	pub scope: GrantScope,
+	pub revoked: bool,
+	pub owner: OwnerSignature,
+}
+
+impl Grant {
+	/// The payload the owner signed for this grant.
+	pub fn signed_payload(&self, reader_public_key: &[u8]) -> SignedPayload {
+		GrantRequest::payload(&self.owner.user_public_key, reader_public_key, &self.scope, self.revoked, self.owner.timestamp, self.owner.nonce)
+	}
+}
+
+impl GrantRecord {
+	pub fn new(owner_public_key: &[u8], reader_public_key: &[u8]) -> Self {
+		Self {
+			owner_public_key: owner_public_key.to_vec(),
+			reader_public_key: reader_public_key.to_vec(),
+			grants: vec![],
+		}
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+		bincode::deserialize(bytes.strip_prefix(GRANT_RECORD_MAGIC)?).ok()
+	}
+
+	pub fn is_grant_record(bytes: &[u8]) -> bool {
+		bytes.starts_with(GRANT_RECORD_MAGIC)
+	}
+
+	pub fn to_record(&self) -> Record {
+		let mut bytes = GRANT_RECORD_MAGIC.to_vec();
+		bytes.append(&mut bincode::serialize(self).expect("Grant records are always serializable"));
+		Record::new(grant_record_key(&self.owner_public_key, &self.reader_public_key), bytes)
+	}
+
+	/// Replaces the grant for the same scope, unless the stored one is more recent.
+	pub fn apply(&mut self, grant: Grant) {
+		match self.grants.iter_mut().find(|existing_grant| existing_grant.scope == grant.scope) {
+			Some(existing_grant) if existing_grant.owner.timestamp <= grant.owner.timestamp => { *existing_grant = grant }
+			Some(_) => {}
+			None => { self.grants.push(grant) }
+		}
+	}
+
+	/// Whether the reader may read `record_key`. Chunks of large values are read as chunks of `value_key`, and
+	/// are only readable by readers of that value.
+	pub fn allows(&self, record_key: &str, value_key: Option<&str>) -> bool {
+		let granted_key = if record_key.starts_with(CHUNK_KEY_PREFIX) {
+			match value_key.filter(|value_key| is_chunk_of(record_key, value_key)) {
+				Some(value_key) => { value_key }
+				None => { return false }
+			}
+		} else {
+			record_key
+		};
+		self.grants.iter()
+			.filter(|grant| !grant.revoked)
+			.any(|grant| grant.scope.covers(granted_key))
+	}
+}
+
+/// DHT key of the grants an owner gave a reader. Data record keys are hex encoded, so the `/` keeps the two
+/// apart.
+pub fn grant_record_key(owner_public_key: &[u8], reader_public_key: &[u8]) -> RecordKey {
+	let mut record_key_bytes = b"grants/".to_vec();
+	record_key_bytes.extend_from_slice(owner_public_key);
+	record_key_bytes.push(b'/');
+	record_key_bytes.extend_from_slice(reader_public_key);
+	RecordKey::new(&record_key_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Layout of the value variant in the formats written before records carried the condition, before that a TTL,
+	/// and before that a version.
+	#[derive(Serialize)]
+	enum UnconditionalDataRecord {
+		Value { record_key: String, value: Vec<u8>, version: u64, ttl: Option<u64>, owner: OwnerSignature },
+	}
+
+	#[derive(Serialize)]
+	enum OlderDataRecord {
+		Value { record_key: String, value: Vec<u8>, version: u64, owner: OwnerSignature },
+	}
+
+	#[derive(Serialize)]
+	enum OldestDataRecord {
+		Value { record_key: String, value: Vec<u8>, owner: OwnerSignature },
+	}
+
+	fn owner() -> OwnerSignature {
+		OwnerSignature { user_public_key: b"02ab".to_vec(), timestamp: 1_700_000_000_000, nonce: 7, signature: vec![1, 2, 3] }
+	}
+
+	fn signed_at(timestamp: u64, nonce: u64) -> OwnerSignature {
+		OwnerSignature { timestamp, nonce, ..owner() }
+	}
+
+	fn encode(format: &[u8], encoded_record: Vec<u8>) -> Vec<u8> {
+		[format, &encoded_record].concat()
+	}
+
+	#[test]
+	fn decodes_the_current_format() {
+		let condition = Some(PutCondition::IfVersion(2));
+		let data_record = DataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 3, ttl: Some(60), condition, owner: owner() };
+
+		assert_eq!(DataRecord::from_bytes(&data_record.to_bytes()).unwrap(), data_record);
+	}
+
+	#[test]
+	fn decodes_records_written_before_records_carried_the_condition() {
+		let unconditional_data_record = UnconditionalDataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 3, ttl: Some(60), owner: owner() };
+		let bytes = encode(b"DDR4", bincode::serialize(&unconditional_data_record).unwrap());
+
+		let data_record = DataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 3, ttl: Some(60), condition: None, owner: owner() };
+		assert_eq!(DataRecord::from_bytes(&bytes).unwrap(), data_record);
+	}
+
+	#[test]
+	fn decodes_records_written_before_records_could_expire() {
+		let older_data_record = OlderDataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 3, owner: owner() };
+		let bytes = encode(b"DDR3", bincode::serialize(&older_data_record).unwrap());
+
+		let data_record = DataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 3, ttl: None, condition: None, owner: owner() };
+		assert_eq!(DataRecord::from_bytes(&bytes).unwrap(), data_record);
+	}
+
+	#[test]
+	fn decodes_records_written_before_records_carried_a_version() {
+		let oldest_data_record = OldestDataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), owner: owner() };
+		let bytes = encode(b"DDR2", bincode::serialize(&oldest_data_record).unwrap());
+
+		let data_record = DataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 0, ttl: None, condition: None, owner: owner() };
+		assert_eq!(DataRecord::from_bytes(&bytes).unwrap(), data_record);
+	}
+
+	#[test]
+	fn rejects_a_known_format_that_cannot_be_decoded() {
+		let error = DataRecord::from_bytes(b"DDR5garbage").unwrap_err();
+
+		assert_eq!(error.kind, ErrorKind::InvalidRequest);
+		assert!(DataRecord::is_data_record(b"DDR5garbage"));
+	}
+
+	#[test]
+	fn reads_values_without_a_format_as_unsigned() {
+		assert_eq!(DataRecord::from_bytes(b"plain value").unwrap(), DataRecord::Unsigned(b"plain value".to_vec()));
+		assert_eq!(DataRecord::from_bytes(b"DDR9value").unwrap(), DataRecord::Unsigned(b"DDR9value".to_vec()));
+		assert!(!DataRecord::is_data_record(b"DDR9value"));
+	}
+
+	#[test]
+	fn expires_ttl_seconds_after_the_owner_signed() {
+		let owner = OwnerSignature { timestamp: current_timestamp_millis() - 10_000, ..owner() };
+		let expiring_record = |ttl| DataRecord::Published { record_key: "key".to_string(), value: b"value".to_vec(), version: 1, ttl, condition: None, owner: owner.clone() };
+
+		assert_eq!(expiring_record(Some(60)).expires_at(), Some(owner.timestamp + 60_000));
+		assert!(!expiring_record(Some(60)).is_expired());
+		assert!(expiring_record(Some(5)).is_expired());
+		assert!(!expiring_record(None).is_expired() && expiring_record(None).expires_at().is_none());
+		assert_eq!(expiring_record(Some(60)).record_version().ttl, Some(60));
+		assert_eq!(expiring_record(Some(60)).record_version().visibility, Visibility::Public);
+	}
+
+	#[test]
+	fn orders_replacements_by_the_owner_signature() {
+		let data_record = DataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 3, ttl: None, condition: None, owner: owner() };
+		let newer_owner = OwnerSignature { nonce: owner().nonce + 1, ..owner() };
+		let newer_record = DataRecord::Tombstone { record_key: "key".to_string(), version: 2, owner: newer_owner };
+		let mut raised_version = data_record.clone();
+		if let DataRecord::Value { version, .. } = &mut raised_version {
+			*version = 100;
+		}
+
+		assert!(data_record.can_replace(&data_record));
+		assert!(newer_record.can_replace(&data_record));
+		assert!(!data_record.can_replace(&newer_record));
+		assert!(!raised_version.can_replace(&newer_record));
+		assert!(!raised_version.can_replace(&data_record));
+		assert!(data_record.can_replace(&DataRecord::Unsigned(b"value".to_vec())));
+	}
+
+	#[test]
+	fn writes_must_be_signed_after_the_stored_record() {
+		let current_record = DataRecord::Tombstone { record_key: "key".to_string(), version: 3, owner: signed_at(1_000, 5) };
+
+		assert!(check_newer_than(None, &signed_at(1, 1)).is_ok());
+		assert!(check_newer_than(Some(&DataRecord::Unsigned(vec![])), &signed_at(1, 1)).is_ok());
+		assert!(check_newer_than(Some(&current_record), &signed_at(1_001, 0)).is_ok());
+		assert!(check_newer_than(Some(&current_record), &signed_at(1_000, 6)).is_ok());
+		assert_eq!(check_newer_than(Some(&current_record), &signed_at(1_000, 5)).unwrap_err().kind, ErrorKind::Replayed);
+		assert_eq!(check_newer_than(Some(&current_record), &signed_at(999, 9)).unwrap_err().kind, ErrorKind::Replayed);
+	}
+
+	#[test]
+	fn grants_cover_the_chunks_of_the_values_they_cover() {
+		let mut grant_record = GrantRecord::new(b"02ab", b"03cd");
+		grant_record.apply(Grant { scope: GrantScope::Prefix("shared/".to_string()), revoked: false, owner: owner() });
+		grant_record.apply(Grant { scope: GrantScope::Key("revoked".to_string()), revoked: true, owner: owner() });
+		let chunk_key = |record_key: &str| format!("{}{}/00", CHUNK_KEY_PREFIX, value_hash(record_key.as_bytes()));
+		let (shared_chunk_key, private_chunk_key, revoked_chunk_key) = (chunk_key("shared/video"), chunk_key("private/video"), chunk_key("revoked"));
+
+		assert!(grant_record.allows("shared/video", None));
+		assert!(!grant_record.allows("private/video", None));
+		assert!(grant_record.allows(&shared_chunk_key, Some("shared/video")));
+		assert!(!grant_record.allows(&shared_chunk_key, None));
+		assert!(!grant_record.allows(&private_chunk_key, Some("private/video")));
+		assert!(!grant_record.allows(&private_chunk_key, Some("shared/video")));
+		assert!(!grant_record.allows(&revoked_chunk_key, Some("revoked")));
+	}
+
+	#[test]
+	fn parses_the_history_record_key_it_builds() {
+		let key = data_record_key(b"02ab", "folder/key");
+
+		assert_eq!(parse_history_record_key(&history_record_key(&key, 12)), Some((key.clone(), 12)));
+		assert_eq!(parse_history_record_key(&key), None);
+	}
+}
@@ -0,0 +1,615 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use libp2p::kad::store::{Error, MemoryStore, MemoryStoreConfig, RecordStore, Result};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::p2p::record::{history_record_key, parse_history_record_key, DataRecord};
+use crate::p2p::User;
+use crate::types::config::{JournalSync, RecordStoreKind, RecordStoreLimits};
+
+/// Entries a journal gains beyond twice the entries it was last compacted to before it is compacted again. A node
+/// that keeps overwriting the same keys thereby keeps its journal, and its startup replay, proportional to the
+/// records it holds.
+const MIN_COMPACTION_ENTRIES: usize = 1024;
+
+/// Record store backing the node's Kademlia behaviour.
+///
+/// Records and provider entries are always served from an in-memory [`MemoryStore`], limited by the node's
+/// [`RecordStoreLimits`]. When the node is configured with [`RecordStoreKind::File`], every mutation is also
+/// appended to a journal file which is replayed (and compacted) on startup, so the stored records survive node
+/// restarts. The journal is also compacted while the node runs, once it grew well beyond the records it holds. A record or provider entry that cannot be written to the journal is not stored.
+///
+/// Only the latest `history_retention` versions of a record are kept, the current one included. Older
+/// versions are dropped as newer ones are archived under their [`history_record_key`].
+pub struct DisruptedDataStore {
+	memory: MemoryStore,
+	provider_keys: HashSet<RecordKey>,
+	journal: Option<Journal>,
+	history_retention: usize,
+}
+
+impl DisruptedDataStore {
+	pub fn new(peer_id: PeerId, kind: RecordStoreKind, limits: RecordStoreLimits, history_retention: usize) -> io::Result<Self> {
+		let memory_config = MemoryStoreConfig {
+			max_records: limits.max_records,
+			max_value_bytes: limits.max_record_size,
+			..MemoryStoreConfig::default()
+		};
+		let mut store = Self {
+			memory: MemoryStore::with_config(peer_id, memory_config),
+			provider_keys: HashSet::new(),
+			journal: None,
+			history_retention: history_retention.max(1),
+		};
+
+		if let RecordStoreKind::File(location, sync) = kind {
+			let entries = Journal::read_entries(&location)?;
+			let replayed_entries = entries.len();
+			for entry in entries {
+				store.apply(entry);
+			}
+
+			// Rewrite the journal with only the live records so it does not grow forever across restarts.
+			let journal = Journal::compact(location, sync, store.snapshot())?;
+			println!("Loaded {} records from {} journal entries in {:?}", store.memory.records().count(), replayed_entries, journal.location);
+			store.journal = Some(journal);
+		}
+
+		Ok(store)
+	}
+
+	pub fn history_retention(&self) -> usize {
+		self.history_retention
+	}
+
+	/// Whether `version` of the record under `data_record_key` falls outside the retained history. The version
+	/// being archived is always older than the one about to replace it, even before this node sees the new one.
+	fn is_expired_version(&self, data_record_key: &RecordKey, version: u64) -> bool {
+		let current_version = self.memory.get(data_record_key)
+			.and_then(|record| DataRecord::from_bytes(&record.value).ok())
+			.map(|record| record.version())
+			.unwrap_or_default()
+			.max(version.saturating_add(1));
+		version.saturating_add(self.history_retention as u64) <= current_version
+	}
+
+	/// Removes the archived versions that fell out of the retained history once `version` was archived.
+	fn prune_history(&mut self, data_record_key: &RecordKey, version: u64) {
+		let mut expired_version = version.saturating_add(1).saturating_sub(self.history_retention as u64);
+		while expired_version > 0 {
+			let expired_key = history_record_key(data_record_key, expired_version);
+			if self.memory.get(&expired_key).is_none() {
+				break;
+			}
+			self.remove(&expired_key);
+			expired_version -= 1;
+		}
+	}
+
+	fn apply(&mut self, entry: JournalEntry) {
+		let now = SystemTime::now();
+		let result = match entry {
+			JournalEntry::PutRecord(stored_record) => {
+				match stored_record.into_record(now) {
+					Some(record) => { self.memory.put(record) }
+					None => { Ok(()) }
+				}
+			}
+			JournalEntry::RemoveRecord(key) => {
+				self.memory.remove(&RecordKey::from(key));
+				Ok(())
+			}
+			JournalEntry::AddProvider(stored_provider) => {
+				match stored_provider.into_provider_record(now) {
+					Some(provider_record) => { self.add_provider_to_memory(provider_record) }
+					None => { Ok(()) }
+				}
+			}
+			JournalEntry::RemoveProvider(key, provider) => {
+				if let Ok(provider) = PeerId::from_bytes(&provider) {
+					self.remove_provider_from_memory(&RecordKey::from(key), &provider);
+				}
+				Ok(())
+			}
+		};
+
+		if let Err(error) = result {
+			println!("Could not restore journal entry: {:?}", error);
+		}
+	}
+
+	fn snapshot(&self) -> Vec<JournalEntry> {
+		let records = self.memory.records().map(|record| JournalEntry::PutRecord(StoredRecord::from(record.as_ref())));
+		let providers = self.provider_keys.iter()
+			.flat_map(|key| self.memory.providers(key))
+			.map(|provider_record| JournalEntry::AddProvider(StoredProvider::from(&provider_record)));
+
+		records.chain(providers).collect()
+	}
+
+	fn add_provider_to_memory(&mut self, record: ProviderRecord) -> Result<()> {
+		let key = record.key.clone();
+		self.memory.add_provider(record)?;
+		self.provider_keys.insert(key);
+		Ok(())
+	}
+
+	fn remove_provider_from_memory(&mut self, key: &RecordKey, provider: &PeerId) {
+		self.memory.remove_provider(key, provider);
+		if self.memory.providers(key).is_empty() {
+			self.provider_keys.remove(key);
+		}
+	}
+
+	/// Kademlia's store errors have no variant for I/O failures, so a failed append is reported as a full store.
+	fn append(&mut self, entry: JournalEntry) -> Result<()> {
+		if let Some(journal) = &mut self.journal {
+			if let Err(error) = journal.append(&entry) {
+				eprintln!("Could not write to record store journal {:?}: {}", journal.location, error);
+				return Err(Error::MaxRecords);
+			}
+			if journal.needs_compaction() {
+				self.compact_journal();
+			}
+		}
+		Ok(())
+	}
+
+	/// Rewrites the journal with only the live records. The entry that triggered it is already journaled, so a
+	/// failed compaction only leaves the journal as long as it was.
+	fn compact_journal(&mut self) {
+		let Some(journal) = &self.journal else { return };
+		let (location, sync) = (journal.location.clone(), journal.sync);
+		match Journal::compact(location, sync, self.snapshot()) {
+			Ok(journal) => { self.journal = Some(journal) }
+			Err(error) => { eprintln!("Could not compact record store journal: {}", error) }
+		}
+	}
+}
+
+impl RecordStore for DisruptedDataStore {
+	type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+	type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+	/// Expired records are left for Kademlia to remove, but are no longer served.
+	fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+		self.memory.get(k).filter(|record| !record.is_expired(Instant::now()))
+	}
+
+	/// Index records are merged with the stored copy instead of replacing it, see [`User::merge`]. Archived
+	/// versions outside the retained history are ignored.
+	///
+	/// Data records expire when the TTL their owner signed runs out, whatever expiry the peer that sent them
+	/// set. Expired data records are ignored.
+	fn put(&mut self, mut r: Record) -> Result<()> {
+		let archived_version = parse_history_record_key(&r.key);
+		if let Some((data_record_key, version)) = &archived_version {
+			if self.is_expired_version(data_record_key, *version) {
+				println!("Ignoring version {} of {:?} outside the retained history", version, data_record_key);
+				return Ok(());
+			}
+		}
+
+		if DataRecord::is_data_record(&r.value) {
+			let data_record = match DataRecord::from_bytes(&r.value) {
+				Ok(data_record) => { data_record }
+				Err(error) => {
+					println!("Ignoring undecodable record for key {:?}: {}", r.key, error.message);
+					return Ok(());
+				}
+			};
+			if data_record.is_expired() {
+				println!("Ignoring expired record for key {:?}", r.key);
+				return Ok(());
+			}
+			r.expires = data_record.expires();
+		}
+
+		if let Some(existing_record) = self.get(&r.key) {
+			if User::is_user_record(&r.value) && User::is_user_record(&existing_record.value) {
+				if let (Ok(mut user), Ok(new_user)) = (User::decode(&existing_record), User::decode(&r)) {
+					user.merge(new_user);
+					if let Ok(merged_record) = TryInto::<Record>::try_into(user) {
+						r.value = merged_record.value;
+					}
+				}
+			} else {
+				let stale = match (DataRecord::from_bytes(&r.value), DataRecord::from_bytes(&existing_record.value)) {
+					(Ok(data_record), Ok(existing_data_record)) => { !data_record.can_replace(&existing_data_record) }
+					_ => { false }
+				};
+				if stale {
+					println!("Ignoring stale record for key {:?}", r.key);
+					return Ok(());
+				}
+			}
+		}
+
+		let stored_record = StoredRecord::from(&r);
+		let replaced_record = self.memory.get(&r.key).map(Cow::into_owned);
+		let key = r.key.clone();
+		self.memory.put(r)?;
+		if let Err(error) = self.append(JournalEntry::PutRecord(stored_record)) {
+			match replaced_record {
+				Some(replaced_record) => { self.memory.put(replaced_record)? }
+				None => { self.memory.remove(&key) }
+			}
+			return Err(error);
+		}
+		if let Some((data_record_key, version)) = archived_version {
+			self.prune_history(&data_record_key, version);
+		}
+		Ok(())
+	}
+
+	/// A removal that cannot be journaled is still applied. The record comes back on restart, which only delays
+	/// its removal.
+	fn remove(&mut self, k: &RecordKey) {
+		self.memory.remove(k);
+		let _ = self.append(JournalEntry::RemoveRecord(k.to_vec()));
+	}
+
+	fn records(&self) -> Self::RecordsIter<'_> {
+		self.memory.records()
+	}
+
+	fn add_provider(&mut self, record: ProviderRecord) -> Result<()> {
+		let stored_provider = StoredProvider::from(&record);
+		let (key, provider) = (record.key.clone(), record.provider);
+		self.add_provider_to_memory(record)?;
+		if let Err(error) = self.append(JournalEntry::AddProvider(stored_provider)) {
+			self.remove_provider_from_memory(&key, &provider);
+			return Err(error);
+		}
+		Ok(())
+	}
+
+	fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+		self.memory.providers(key)
+	}
+
+	fn provided(&self) -> Self::ProvidedIter<'_> {
+		self.memory.provided()
+	}
+
+	fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+		self.remove_provider_from_memory(k, p);
+		let _ = self.append(JournalEntry::RemoveProvider(k.to_vec(), p.to_bytes()));
+	}
+}
+
+/// Append-only log of store mutations. Each entry is written as a little endian `u32` length followed by
+/// the bincode encoded [`JournalEntry`].
+struct Journal {
+	location: PathBuf,
+	file: File,
+	/// Length of the journal up to its last complete entry.
+	length: u64,
+	sync: JournalSync,
+	/// Entries in the journal, and the entries it held right after it was last compacted.
+	entries: usize,
+	compacted_entries: usize,
+}
+
+impl Journal {
+	fn read_entries(location: &PathBuf) -> io::Result<Vec<JournalEntry>> {
+		let file = match File::open(location) {
+			Ok(file) => { file }
+			Err(error) if error.kind() == ErrorKind::NotFound => { return Ok(vec![]) }
+			Err(error) => { return Err(error) }
+		};
+
+		let mut reader = BufReader::new(file);
+		let mut entries = Vec::new();
+		loop {
+			let mut length_bytes = [0u8; 4];
+			match reader.read_exact(&mut length_bytes) {
+				Ok(()) => {}
+				Err(error) if error.kind() == ErrorKind::UnexpectedEof => { break }
+				Err(error) => { return Err(error) }
+			}
+
+			let mut entry_bytes = vec![0u8; u32::from_le_bytes(length_bytes) as usize];
+			if let Err(error) = reader.read_exact(&mut entry_bytes) {
+				// A partially written trailing entry is left behind when the node is killed mid-write.
+				println!("Ignoring truncated record store journal entry: {}", error);
+				break;
+			}
+
+			match bincode::deserialize::<JournalEntry>(&entry_bytes) {
+				Ok(entry) => { entries.push(entry) }
+				Err(error) => {
+					println!("Ignoring corrupt record store journal entry: {}", error);
+					break;
+				}
+			}
+		}
+
+		Ok(entries)
+	}
+
+	fn compact(location: PathBuf, sync: JournalSync, entries: Vec<JournalEntry>) -> io::Result<Self> {
+		if let Some(parent) = location.parent() {
+			fs::create_dir_all(parent)?;
+		}
+
+		let compacted_location = location.with_extension("compacting");
+		{
+			let mut writer = BufWriter::new(File::create(&compacted_location)?);
+			for entry in &entries {
+				write_entry(&mut writer, entry)?;
+			}
+			writer.flush()?;
+			writer.get_ref().sync_all()?;
+		}
+		// The compacted journal is opened before it replaces the current one, so a failure leaves the current one in use.
+		let file = OpenOptions::new().append(true).open(&compacted_location)?;
+		let length = file.metadata()?.len();
+		fs::rename(&compacted_location, &location)?;
+
+		Ok(Self { location, file, length, sync, entries: entries.len(), compacted_entries: entries.len() })
+	}
+
+	fn needs_compaction(&self) -> bool {
+		self.entries >= self.compacted_entries * 2 + MIN_COMPACTION_ENTRIES
+	}
+
+	fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+		let mut buffer = Vec::new();
+		write_entry(&mut buffer, entry)?;
+		let result = self.file.write_all(&buffer).and_then(|_| {
+			match self.sync {
+				JournalSync::Always => { self.file.sync_data() }
+				JournalSync::Os => { Ok(()) }
+			}
+		});
+		match result {
+			Ok(()) => {
+				self.length += buffer.len() as u64;
+				self.entries += 1;
+			}
+			// Cut off a partially written entry, so that the entries appended after it are still read back.
+			Err(_) => { let _ = self.file.set_len(self.length); }
+		}
+		result
+	}
+}
+
+fn write_entry<W: Write>(writer: &mut W, entry: &JournalEntry) -> io::Result<()> {
+	let entry_bytes = bincode::serialize(entry).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?;
+	writer.write_all(&(entry_bytes.len() as u32).to_le_bytes())?;
+	writer.write_all(&entry_bytes)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum JournalEntry {
+	PutRecord(StoredRecord),
+	RemoveRecord(Vec<u8>),
+	AddProvider(StoredProvider),
+	RemoveProvider(Vec<u8>, Vec<u8>),
+}
+
+/// [`Record`] with its monotonic expiry converted to wall clock milliseconds so it can be persisted.
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredRecord {
+	key: Vec<u8>,
+	value: Vec<u8>,
+	publisher: Option<Vec<u8>>,
+	expires_at: Option<u64>,
+}
+
+impl From<&Record> for StoredRecord {
+	fn from(record: &Record) -> Self {
+		Self {
+			key: record.key.to_vec(),
+			value: record.value.clone(),
+			publisher: record.publisher.map(|publisher| publisher.to_bytes()),
+			expires_at: record.expires.map(to_unix_millis),
+		}
+	}
+}
+
+impl StoredRecord {
+	fn into_record(self, now: SystemTime) -> Option<Record> {
+		let expires = match self.expires_at {
+			None => { None }
+			Some(expires_at) => { Some(to_instant(expires_at, now)?) }
+		};
+
+		Some(Record {
+			key: RecordKey::from(self.key),
+			value: self.value,
+			publisher: self.publisher.and_then(|publisher| PeerId::from_bytes(&publisher).ok()),
+			expires,
+		})
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredProvider {
+	key: Vec<u8>,
+	provider: Vec<u8>,
+	expires_at: Option<u64>,
+	addresses: Vec<Vec<u8>>,
+}
+
+impl From<&ProviderRecord> for StoredProvider {
+	fn from(provider_record: &ProviderRecord) -> Self {
+		Self {
+			key: provider_record.key.to_vec(),
+			provider: provider_record.provider.to_bytes(),
+			expires_at: provider_record.expires.map(to_unix_millis),
+			addresses: provider_record.addresses.iter().map(|address| address.to_vec()).collect(),
+		}
+	}
+}
+
+impl StoredProvider {
+	fn into_provider_record(self, now: SystemTime) -> Option<ProviderRecord> {
+		let expires = match self.expires_at {
+			None => { None }
+			Some(expires_at) => { Some(to_instant(expires_at, now)?) }
+		};
+
+		Some(ProviderRecord {
+			key: RecordKey::from(self.key),
+			provider: PeerId::from_bytes(&self.provider).ok()?,
+			expires,
+			addresses: self.addresses.into_iter().filter_map(|address| Multiaddr::try_from(address).ok()).collect(),
+		})
+	}
+}
+
+fn to_unix_millis(instant: Instant) -> u64 {
+	let remaining = instant.saturating_duration_since(Instant::now());
+	let expires_at = SystemTime::now() + remaining;
+	expires_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Converts a persisted expiry back into an [`Instant`], returning `None` when it has already passed.
+pub(crate) fn to_instant(unix_millis: u64, now: SystemTime) -> Option<Instant> {
+	let expires_at = UNIX_EPOCH + Duration::from_millis(unix_millis);
+	let remaining = expires_at.duration_since(now).ok()?;
+	Some(Instant::now() + remaining)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use disrupted_data_sdk_rs::current_timestamp_millis;
+
+	use super::*;
+	use crate::p2p::record::OwnerSignature;
+
+	const LIMITS: RecordStoreLimits = RecordStoreLimits { max_records: 16, max_record_size: 1024 };
+
+	fn journal_location(name: &str) -> PathBuf {
+		let location = std::env::temp_dir().join(format!("disrupted-data-store-{}-{}.records", std::process::id(), name));
+		let _ = fs::remove_file(&location);
+		location
+	}
+
+	fn open(location: &Path) -> DisruptedDataStore {
+		DisruptedDataStore::new(PeerId::random(), RecordStoreKind::File(location.to_path_buf(), JournalSync::Always), LIMITS, 1).unwrap()
+	}
+
+	fn record(key: &str, value: &[u8]) -> Record {
+		Record::new(RecordKey::new(&key), value.to_vec())
+	}
+
+	#[test]
+	fn replays_the_journal_on_startup() {
+		let location = journal_location("replay");
+		{
+			let mut store = open(&location);
+			store.put(record("kept", b"kept value")).unwrap();
+			store.put(record("removed", b"removed value")).unwrap();
+			store.remove(&RecordKey::new(&"removed"));
+		}
+
+		let store = open(&location);
+		assert_eq!(store.get(&RecordKey::new(&"kept")).unwrap().value, b"kept value");
+		assert!(store.get(&RecordKey::new(&"removed")).is_none());
+		fs::remove_file(location).unwrap();
+	}
+
+	#[test]
+	fn compacts_the_journal_to_the_live_records() {
+		let location = journal_location("compaction");
+		{
+			let mut store = open(&location);
+			for index in 0..5 {
+				store.put(record(&format!("key-{}", index), b"value")).unwrap();
+			}
+			for index in 0..4 {
+				store.remove(&RecordKey::new(&format!("key-{}", index)));
+			}
+			assert_eq!(Journal::read_entries(&location).unwrap().len(), 9);
+		}
+
+		let store = open(&location);
+		assert_eq!(store.records().count(), 1);
+		let entries = Journal::read_entries(&location).unwrap();
+		assert_eq!(entries.len(), 1);
+		assert!(matches!(&entries[0], JournalEntry::PutRecord(stored_record) if stored_record.key == b"key-4"));
+		fs::remove_file(location).unwrap();
+	}
+
+	#[test]
+	fn compacts_the_journal_while_running() {
+		let location = journal_location("running-compaction");
+		let mut store = DisruptedDataStore::new(PeerId::random(), RecordStoreKind::File(location.clone(), JournalSync::Os), LIMITS, 1).unwrap();
+		for index in 0..MIN_COMPACTION_ENTRIES * 2 {
+			store.put(record(&format!("key-{}", index), b"value")).unwrap();
+			store.remove(&RecordKey::new(&format!("key-{}", index)));
+		}
+		store.put(record("kept", b"kept value")).unwrap();
+
+		assert!(Journal::read_entries(&location).unwrap().len() <= MIN_COMPACTION_ENTRIES);
+		drop(store);
+		let store = open(&location);
+		assert_eq!(store.records().count(), 1);
+		assert_eq!(store.get(&RecordKey::new(&"kept")).unwrap().value, b"kept value");
+		fs::remove_file(location).unwrap();
+	}
+
+	#[test]
+	fn ignores_a_truncated_trailing_entry() {
+		let location = journal_location("truncated");
+		{
+			let mut store = open(&location);
+			store.put(record("complete", b"value")).unwrap();
+		}
+		// A node killed mid-write leaves a length prefix without the full entry behind it.
+		let mut file = OpenOptions::new().append(true).open(&location).unwrap();
+		file.write_all(&100u32.to_le_bytes()).unwrap();
+		file.write_all(b"partial").unwrap();
+		drop(file);
+
+		{
+			let mut store = open(&location);
+			assert_eq!(store.get(&RecordKey::new(&"complete")).unwrap().value, b"value");
+			store.put(record("after restart", b"value")).unwrap();
+		}
+
+		let store = open(&location);
+		assert!(store.get(&RecordKey::new(&"complete")).is_some());
+		assert!(store.get(&RecordKey::new(&"after restart")).is_some());
+		fs::remove_file(location).unwrap();
+	}
+
+	#[test]
+	fn refuses_records_beyond_its_limits() {
+		let limits = RecordStoreLimits { max_records: 1, max_record_size: 8 };
+		let mut store = DisruptedDataStore::new(PeerId::random(), RecordStoreKind::Memory, limits, 1).unwrap();
+
+		assert!(matches!(store.put(record("large", b"more than eight bytes")), Err(Error::ValueTooLarge)));
+		store.put(record("first", b"value")).unwrap();
+		assert!(matches!(store.put(record("second", b"value")), Err(Error::MaxRecords)));
+	}
+
+	#[test]
+	fn expiring_records_expire_with_their_value() {
+		let mut store = DisruptedDataStore::new(PeerId::random(), RecordStoreKind::Memory, LIMITS, 1).unwrap();
+		let signed_at = current_timestamp_millis() - 10_000;
+		let expiring_record = |ttl: u64| {
+			let owner = OwnerSignature { user_public_key: b"02ab".to_vec(), timestamp: signed_at, nonce: 1, signature: vec![] };
+			DataRecord::Value { record_key: "key".to_string(), value: b"value".to_vec(), version: 1, ttl: Some(ttl), condition: None, owner }.to_bytes()
+		};
+
+		store.put(record("expired", &expiring_record(5))).unwrap();
+		assert!(store.get(&RecordKey::new(&"expired")).is_none());
+
+		store.put(record("expiring", &expiring_record(60))).unwrap();
+		let expires = store.get(&RecordKey::new(&"expiring")).unwrap().expires.unwrap();
+		assert!(expires > Instant::now() + Duration::from_secs(45) && expires <= Instant::now() + Duration::from_secs(50));
+	}
+}
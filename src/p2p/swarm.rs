@@ -7,20 +7,25 @@ use std::time::Duration;
 use crate::p2p::node::Node;
 use crate::p2p::protocol::disrupted_data;
 use crate::p2p::protocol::disrupted_data::behaviour;
-use crate::p2p::protocol::disrupted_data::request::Request::{GET, PUT};
+use crate::p2p::protocol::disrupted_data::request::Request::{DELETE, GET, GRANT, HISTORY, INDEX, PUBLIC, PUT};
 use crate::p2p::protocol::disrupted_data::request::{FindResult, Request};
-use crate::p2p::protocol::disrupted_data::types::state::{GetRequestState, PutRequestState};
+use crate::p2p::protocol::disrupted_data::types::replay::ReplayGuard;
+use crate::p2p::protocol::disrupted_data::types::state::{DeleteRequestState, GetRequestState, GrantRequestState, HistoryRequestState, IndexRequestState, PublicGetRequestState, PutRequestState};
+use crate::p2p::store::DisruptedDataStore;
 use crate::p2p::{FromDisruptedDataSwarmEvent, ToDisruptedDataSwarmEvent};
+use crate::types::config::BootstrapNode;
 use crate::types::NodeConfig;
-use disrupted_data_sdk_rs::{ActionResult, Actions};
+use disrupted_data_sdk_rs::{ActionResult, Actions, DisruptedDataError, ErrorKind};
 use libp2p::core::upgrade::Version;
 use libp2p::futures::{FutureExt, StreamExt};
 use libp2p::kad::{QueryId, QueryResult};
 use libp2p::request_response::{InboundRequestId, Message, ResponseChannel};
 use libp2p::swarm::SwarmEvent;
-use libp2p::{identify, kad, noise, request_response, swarm, tcp, yamux, Multiaddr, Swarm, Transport};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::autonat::NatStatus;
+use libp2p::multiaddr::Protocol;
+use libp2p::{autonat, dns, identify, kad, mdns, noise, quic, relay, request_response, swarm, tcp, websocket, yamux, Multiaddr, Swarm, Transport};
 use tokio::sync::mpsc::{Receiver, Sender};
-use toml::Table;
 
 pub(crate) type RequestsHashMap = HashMap<InboundRequestId, (Request, ResponseChannel<ActionResult>)>;
 
@@ -31,46 +36,97 @@ pub struct DisruptedDataSwarm {
 	request_event_receiver: Receiver<ToDisruptedDataSwarmEvent>,
 	requests: RequestsHashMap,
 	kad_request_mapping: HashMap<QueryId, InboundRequestId>,
-
+	replay_guard: ReplayGuard,
+	bootstrap_nodes: Vec<BootstrapNode>,
+	/// Whether the node listens through its bootstrap nodes' relays because AutoNAT found it unreachable.
+	is_listening_on_relays: bool,
 }
 
 impl DisruptedDataSwarm {
-	pub fn new(node_config: NodeConfig, swarm_event_sender: Sender<FromDisruptedDataSwarmEvent>, mut request_event_receiver: Receiver<ToDisruptedDataSwarmEvent>) -> Self {
-		let bootstrap_nodes = node_config.clone().bootstrap_nodes();
-		let node = Node::new(node_config);
+	pub fn new(node_config: NodeConfig, swarm_event_sender: Sender<FromDisruptedDataSwarmEvent>, mut request_event_receiver: Receiver<ToDisruptedDataSwarmEvent>) -> Result<Self, DisruptedDataError> {
+		let bootstrap_nodes = node_config.bootstrap_nodes()?;
+		let node = Node::new(node_config)?;
 
-		Self {
+		Ok(Self {
 			node: node.clone(),
-			swarm: Self::init_swarm(&node, bootstrap_nodes),
+			swarm: Self::init_swarm(&node, bootstrap_nodes.clone())?,
 			swarm_event_sender,
 			request_event_receiver,
 			requests: HashMap::new(),
 			kad_request_mapping: HashMap::new(),
-		}
+			replay_guard: ReplayGuard::default(),
+			bootstrap_nodes,
+			is_listening_on_relays: false,
+		})
 	}
 
 
-	fn init_swarm(node: &Node, bootstrap_nodes: Table) -> Swarm<behaviour::Behaviour> {
+	fn init_swarm(node: &Node, bootstrap_nodes: Vec<BootstrapNode>) -> Result<Swarm<behaviour::Behaviour>, DisruptedDataError> {
 		let peer_id = &node.peer_id;
 		let keypair = &node.key;
-		let behaviour = behaviour::Behaviour::new(peer_id, &keypair);
-
-		let transport = tcp::tokio::Transport::default().upgrade(Version::V1).authenticate(noise::Config::new(&keypair).expect("Signing noise keypair")).multiplex(yamux::Config::default()).boxed();
+		let store = DisruptedDataStore::new(*peer_id, node.record_store.clone(), node.record_store_limits, node.history_retention)
+			.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Could not open record store: {}", error)))?;
+		let (relay_transport, relay_client) = relay::client::new(*peer_id);
+		let behaviour = behaviour::Behaviour::new(peer_id, &keypair, store, relay_client, node.mdns);
+
+		let tcp_transport = tcp::tokio::Transport::default()
+			.upgrade(Version::V1)
+			.authenticate(noise::Config::new(&keypair).expect("Signing noise keypair"))
+			.multiplex(yamux::Config::default())
+			.map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+		// QUIC is always available for dialing. The node only listens on it when a QUIC port is configured.
+		let quic_transport = quic::tokio::Transport::new(quic::Config::new(keypair))
+			.map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+		// WebSocket lets browser clients, which cannot open raw TCP connections, reach the node.
+		let websocket_transport = websocket::WsConfig::new(tcp::tokio::Transport::default())
+			.upgrade(Version::V1)
+			.authenticate(noise::Config::new(&keypair).expect("Signing noise keypair"))
+			.multiplex(yamux::Config::default())
+			.map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+		// Relayed connections are used while the node is unreachable, until hole punching replaces them.
+		let relay_transport = relay_transport
+			.upgrade(Version::V1)
+			.authenticate(noise::Config::new(keypair).expect("Signing noise keypair"))
+			.multiplex(yamux::Config::default())
+			.map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+		let transport = websocket_transport
+			.or_transport(tcp_transport)
+			.map(|either, _| either.into_inner())
+			.or_transport(quic_transport)
+			.map(|either, _| either.into_inner())
+			.or_transport(relay_transport)
+			.map(|either, _| either.into_inner());
+		// DNS resolution lets bootstrap nodes be configured with /dns4 and /dns6 addresses.
+		let transport = dns::tokio::Transport::system(transport)
+			.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Could not read the system DNS configuration: {}", error)))?
+			.boxed();
 		let swarm_config = swarm::Config::with_tokio_executor().with_idle_connection_timeout(Duration::from_secs(60));
 		let mut swarm = Swarm::new(transport, behaviour, *peer_id, swarm_config);
 
 
-		let tcp_address: Multiaddr = format!("/ip4/{}/tcp/{}", node.ip_address, node.port).parse().unwrap();
+		let tcp_address: Multiaddr = format!("/ip4/{}/tcp/{}", node.ip_address, node.port).parse()
+			.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Invalid TCP listen address: {}", error)))?;
 
-		swarm.listen_on(tcp_address).expect("Could not start listener");
-		for (bootstrap_node_peer_id, bootstrap_node_ip) in &bootstrap_nodes {
-			println!("Adding bootstrap node {} with IP {}", bootstrap_node_peer_id, bootstrap_node_ip.to_string());
-			let bootstrap_multi_address = format!("/ip4/{}/tcp/6969", bootstrap_node_ip.as_str().unwrap());
-			swarm.behaviour_mut().kad.add_address(&bootstrap_node_peer_id.parse().expect("Unable to parse peer id"),
-			                                      bootstrap_multi_address.parse().expect("Unable to parse address"));
+		swarm.listen_on(tcp_address)
+			.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Could not start TCP listener: {}", error)))?;
+		if let Some(quic_port) = &node.quic_port {
+			let quic_address: Multiaddr = format!("/ip4/{}/udp/{}/quic-v1", node.ip_address, quic_port).parse()
+				.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Invalid QUIC listen address: {}", error)))?;
+			swarm.listen_on(quic_address)
+				.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Could not start QUIC listener: {}", error)))?;
+		}
+		if let Some(websocket_port) = &node.websocket_port {
+			let websocket_address: Multiaddr = format!("/ip4/{}/tcp/{}/ws", node.ip_address, websocket_port).parse()
+				.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Invalid WebSocket listen address: {}", error)))?;
+			swarm.listen_on(websocket_address)
+				.map_err(|error| DisruptedDataError::new(ErrorKind::Config, format!("Could not start WebSocket listener: {}", error)))?;
+		}
+		for bootstrap_node in bootstrap_nodes {
+			println!("Adding bootstrap node {} at {}", bootstrap_node.peer_id, bootstrap_node.address);
+			swarm.behaviour_mut().kad.add_address(&bootstrap_node.peer_id, bootstrap_node.address);
 		}
 
-		swarm
+		Ok(swarm)
 	}
 	pub async fn start(&mut self) {
 		loop {
@@ -91,40 +147,32 @@ impl DisruptedDataSwarm {
                                 Message::Request{ request_id, request, mut channel } => {
 									match &request {
 										Actions::Put(_) => {
-		                                    let updated_request = self.swarm.behaviour_mut().put(peer, request_id, request);
-		                                    match &updated_request {
-		                                        None => {}
-		                                        Some(request) => {
-		                                            if let PUT(put_request_state, data) = request {
-		                                                // println!("PUT Request after verification in swarm: {:?}", request);
-														if let PutRequestState::FindUser(query_id) = put_request_state {
-															self.kad_request_mapping.insert(query_id.clone(), request_id);
-															self.requests.insert(request_id, (request.clone(), channel));
-														} else if let PutRequestState::SendResponse(action_result) = put_request_state {
-															self.swarm.behaviour_mut().send_response(action_result.clone(), channel)
-														}
-		                                            }
-
-		                                        }
-		                                    }
+		                                    let updated_request = self.swarm.behaviour_mut().put(peer, request_id, request, &mut self.replay_guard);
+											self.progress_put_request(request_id, updated_request, channel);
 										}
 										Actions::Get(_) => {
-		                                    let updated_request = self.swarm.behaviour_mut().get(peer, request_id, request);
-		                                    match &updated_request {
-		                                        None => {}
-		                                        Some(request) => {
-		                                            if let GET(get_request_state, data) = request {
-		                                                // println!("GET Request after verification in swarm: {:?}", request);
-														if let GetRequestState::FindUser(query_id) = get_request_state {
-															self.kad_request_mapping.insert(query_id.clone(), request_id);
-															self.requests.insert(request_id, (request.clone(), channel));
-														} else if let GetRequestState::SendResponse(action_result) = get_request_state {
-															self.swarm.behaviour_mut().send_response(action_result.clone(), channel)
-														}
-		                                            }
-
-		                                        }
-		                                    }
+		                                    let updated_request = self.swarm.behaviour_mut().get(peer, request_id, request, &mut self.replay_guard);
+											self.progress_get_request(request_id, updated_request, channel);
+										}
+										Actions::Delete(_) => {
+											let updated_request = self.swarm.behaviour_mut().delete(peer, request_id, request, &mut self.replay_guard);
+											self.progress_delete_request(request_id, updated_request, channel);
+										}
+										Actions::List(_) | Actions::Proof(_) | Actions::Subtree(_) => {
+											let updated_request = self.swarm.behaviour_mut().index(peer, request_id, request, &mut self.replay_guard);
+											self.progress_index_request(request_id, updated_request, channel);
+										}
+										Actions::Grant(_) => {
+											let updated_request = self.swarm.behaviour_mut().grant(peer, request_id, request, &mut self.replay_guard);
+											self.progress_grant_request(request_id, updated_request, channel);
+										}
+										Actions::GetPublic(_) => {
+											let updated_request = self.swarm.behaviour_mut().get_public(request_id, request);
+											self.progress_public_get_request(request_id, updated_request, channel);
+										}
+										Actions::History(_) => {
+											let updated_request = self.swarm.behaviour_mut().history(request_id, request, &mut self.replay_guard);
+											self.progress_history_request(request_id, updated_request, channel);
 										}
 										Actions::Unknown => {}
 									}
@@ -146,15 +194,8 @@ impl DisruptedDataSwarm {
 										let channel = request_and_channel.1;
 										match &request {
 											PUT(_,_) => {
-												let updated_request_state = self.swarm.behaviour_mut().get_query_progress(FindResult::Found(id, record.clone().try_into().unwrap()), Some(record), request);
-												if let Some(PUT(PutRequestState::WaitingDataCreate(query_id, find_result), data)) = &updated_request_state {
-													self.kad_request_mapping.insert(query_id.clone(), inbound_request_id.unwrap());
-													self.requests.insert(inbound_request_id.unwrap(), (updated_request_state.unwrap().clone(), channel));
-
-												} else if let Some(PUT(PutRequestState::SendResponse(action_result), data)) = &updated_request_state {
-													let send_result = self.swarm.behaviour_mut().request_response.send_response(channel, action_result.clone());
-													// println!("SendResponse result: {:?}", send_result);
-												}
+												let updated_request = self.swarm.behaviour_mut().get_query_progress(FindResult::Found(id, record.clone()), Some(record), request);
+												self.progress_put_request(inbound_request_id.unwrap(), updated_request, channel);
 											}
 											GET(get_request_state,data ) => {
 												if let GetRequestState::FindUser(query_id) = &get_request_state {
@@ -175,14 +216,37 @@ impl DisruptedDataSwarm {
 														let send_result = self.swarm.behaviour_mut().request_response.send_response(channel, action_result.clone());
 														// println!("Error condition after processing find user - SendResponse result: {:?}", send_result);
 													}
-												} else if let GetRequestState::WaitingData(query_id) = &get_request_state {
+												} else if let GetRequestState::FindGrant(_) = &get_request_state {
+													let updated_request = self.swarm.behaviour_mut().find_grant_query_progress(FindResult::Found(id, record), request.clone());
+													self.progress_get_request(inbound_request_id.unwrap(), updated_request, channel);
+												} else if let GetRequestState::WaitingData(_) | GetRequestState::FindVersion(_) = &get_request_state {
 													// println!("GetRequestState::WaitingData in GetRecordOK swarm for query id: {:?}", query_id);
-													let action_result = ActionResult::Success(String::from_utf8(record.value).unwrap() );
-													let send_result = self.swarm.behaviour_mut().request_response.send_response(channel, action_result.clone());
+													let updated_request = self.swarm.behaviour_mut().find_data_query_progress(FindResult::Found(id, record), None, request.clone());
+													self.progress_get_request(inbound_request_id.unwrap(), updated_request, channel);
 													// println!("Data find Success - SendResponse result : {:?}", send_result);
 
 												}
 											}
+											DELETE(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().delete_find_progress(FindResult::Found(id, record), request);
+												self.progress_delete_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											INDEX(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().index_find_user_progress(FindResult::Found(id, record), request);
+												self.progress_index_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											GRANT(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().grant_find_progress(FindResult::Found(id, record), request);
+												self.progress_grant_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											PUBLIC(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().public_find_data_progress(FindResult::Found(id, record), request);
+												self.progress_public_get_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											HISTORY(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().history_find_progress(FindResult::Found(id, record), request);
+												self.progress_history_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
 										}
 
 									}
@@ -198,24 +262,36 @@ impl DisruptedDataSwarm {
 										let channel = request_and_channel.1;
 										match &request {
 											PUT(_,_) => {
-												let updated_request_state = self.swarm.behaviour_mut().get_query_progress(FindResult::NotFound, None, request);
-												// println!("updated request state in GetRecord(NotFound()) swarm{:?}", updated_request_state);
-												match &updated_request_state {
-													None => {}
-													Some(request) => {
-														if let PUT(PutRequestState::WaitingDataCreate(data_put_query_id, find_result), data) = request {
-															// println!("{:?} user_put_query_id WaitingDataCreate GetRecord(Err()) in swarm", data_put_query_id);
-															self.requests.insert(inbound_request_id.unwrap(), (request.clone(), channel));
-															// println!("{:?} current query mappings in WaitingDataCreate GetRecord(Err())swarm", self.kad_request_mapping);
-															self.kad_request_mapping.insert(data_put_query_id.clone(), inbound_request_id.unwrap());
-
-														}
-													}
-												}
-
+												let updated_request = self.swarm.behaviour_mut().get_query_progress(FindResult::NotFound, None, request);
+												self.progress_put_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											GET(GetRequestState::FindGrant(_), _) => {
+												let updated_request = self.swarm.behaviour_mut().find_grant_query_progress(FindResult::NotFound, request);
+												self.progress_get_request(inbound_request_id.unwrap(), updated_request, channel);
 											}
 											GET(_,_) => {
-												self.swarm.behaviour_mut().find_data_query_progress(FindResult::NotFound, Some("Record Not found".to_string()), request.clone(), channel);
+												let updated_request = self.swarm.behaviour_mut().find_data_query_progress(FindResult::NotFound, Some("Record Not found".to_string()), request.clone());
+												self.progress_get_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											DELETE(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().delete_find_progress(FindResult::NotFound, request);
+												self.progress_delete_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											INDEX(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().index_find_user_progress(FindResult::NotFound, request);
+												self.progress_index_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											GRANT(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().grant_find_progress(FindResult::NotFound, request);
+												self.progress_grant_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											PUBLIC(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().public_find_data_progress(FindResult::NotFound, request);
+												self.progress_public_get_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											HISTORY(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().history_find_progress(FindResult::NotFound, request);
+												self.progress_history_request(inbound_request_id.unwrap(), updated_request, channel);
 											}
 										}
 									}
@@ -231,40 +307,87 @@ impl DisruptedDataSwarm {
 										// println!("Found put request in swarm: {:?}", put_request);
 										match &put_request {
 											PUT(put_request_state,data) => {
-												if let PutRequestState::WaitingDataCreate(data_put_query_id, find_result) = put_request_state {
+												if let PutRequestState::WaitingArchive(_, _, _) | PutRequestState::WaitingDataCreate(_, _, _) = put_request_state {
 													let updated_request = self.swarm.behaviour_mut().put_data_query_progress(put_request);
-													match &updated_request {
-														None => {}
-														Some(request) => {
-															if let PUT(PutRequestState::WaitingUserCreate(user_put_query_id), data) = request {
-																// println!("{:?} PutRecord(Ok()) in swarm", user_put_query_id);
-																self.requests.insert(inbound_request_id.unwrap(), (request.clone(), channel));
-																// println!("{:?} current query mappings inPutRecord(Ok()) swarm", self.kad_request_mapping);
-																self.kad_request_mapping.insert(user_put_query_id.clone(), inbound_request_id.unwrap());
-															}
-														}
-													}
-												}else if let PutRequestState::WaitingUserCreate(user_put_query_id) = put_request_state {
-													 self.swarm.behaviour_mut().put_user_query_progress(put_request, channel);
+													self.progress_put_request(inbound_request_id.unwrap(), updated_request, channel);
+												} else if let PutRequestState::WaitingUserCreate(_, _) = put_request_state {
+													self.swarm.behaviour_mut().put_user_query_progress(put_request, channel);
 												}
 											}
 											GET(_,_) => {}
+											DELETE(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().delete_put_progress(put_request);
+												self.progress_delete_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
+											INDEX(_, _) | PUBLIC(_, _) | HISTORY(_, _) => {}
+											GRANT(_, _) => {
+												let updated_request = self.swarm.behaviour_mut().grant_put_progress(put_request);
+												self.progress_grant_request(inbound_request_id.unwrap(), updated_request, channel);
+											}
 										}
 									}
 
 
                                 },
+                                QueryResult::PutRecord(Err(error)) => {
+									let kind = match &error {
+										kad::PutRecordError::QuorumFailed { .. } => { ErrorKind::QuorumFailed }
+										kad::PutRecordError::Timeout { .. } => { ErrorKind::Timeout }
+									};
+									self.fail_query(id, DisruptedDataError::new(kind, format!("Could not store record: {}", error)));
+                                }
+                                QueryResult::GetRecord(Err(error @ (kad::GetRecordError::QuorumFailed { .. } | kad::GetRecordError::Timeout { .. }))) => {
+									let kind = match &error {
+										kad::GetRecordError::QuorumFailed { .. } => { ErrorKind::QuorumFailed }
+										_ => { ErrorKind::Timeout }
+									};
+									self.fail_query(id, DisruptedDataError::new(kind, format!("Could not find record: {}", error)));
+                                }
                                 _ => {println!("outbound query result: {:?}", result)}
                             }
 
                         }
+                        SwarmEvent::Behaviour(behaviour::Event::Kademlia(kad::Event::InboundRequest { request: kad::InboundRequest::PutRecord { source, record: Some(record), .. } })) => {
+                            self.swarm.behaviour_mut().store_inbound_record(source, record);
+                        }
+                        SwarmEvent::Behaviour(behaviour::Event::Kademlia(kad::Event::InboundRequest { request: kad::InboundRequest::AddProvider { record: Some(provider_record) } })) => {
+                            self.swarm.behaviour_mut().store_inbound_provider(provider_record);
+                        }
                         SwarmEvent::Behaviour( behaviour::Event::Identify(identify::Event::Received { peer_id ,info,.. })) => {
-                            let ip_address = &self.node.ip_address;
-
-                            let mut filtered_listening_address: Vec<Multiaddr> = info.listen_addrs.into_iter().filter(|address| !address.to_string().contains(ip_address) ).collect();
-                            let mut listening_address = filtered_listening_address.remove(0);
-
-                            self.swarm.behaviour_mut().kad.add_address(&peer_id, listening_address);
+                            // Peers in client mode do not offer the kad protocol and must not be routed to.
+                            if info.protocols.contains(&kad::PROTOCOL_NAME) {
+                                let ip_address = &self.node.ip_address;
+                                let routable_addresses = info.listen_addrs.into_iter()
+                                    .filter(|address| !address.to_string().contains(ip_address))
+                                    .filter(|address| !address.iter().any(|protocol| protocol == Protocol::P2pCircuit));
+                                for address in routable_addresses {
+                                    self.swarm.behaviour_mut().kad.add_address(&peer_id, address);
+                                }
+                            }
+                        },
+                        SwarmEvent::Behaviour(behaviour::Event::Autonat(autonat::Event::StatusChanged { old, new })) => {
+                            println!("NAT status changed from {:?} to {:?}", old, new);
+                            self.nat_status_changed(new);
+                        },
+                        SwarmEvent::Behaviour(behaviour::Event::Mdns(mdns::Event::Discovered(discovered_nodes))) => {
+                            for (peer_id, address) in discovered_nodes {
+                                println!("Discovered local node {} at {}", peer_id, address);
+                                self.swarm.behaviour_mut().kad.add_address(&peer_id, address);
+                            }
+                        },
+                        SwarmEvent::Behaviour(behaviour::Event::Mdns(mdns::Event::Expired(expired_nodes))) => {
+                            for (peer_id, address) in expired_nodes {
+                                self.swarm.behaviour_mut().kad.remove_address(&peer_id, &address);
+                            }
+                        },
+                        SwarmEvent::Behaviour(behaviour::Event::Relay(relay_event)) => {
+                            println!("Relay event: {:?}", relay_event)
+                        },
+                        SwarmEvent::Behaviour(behaviour::Event::RelayClient(relay_client_event)) => {
+                            println!("Relay client event: {:?}", relay_client_event)
+                        },
+                        SwarmEvent::Behaviour(behaviour::Event::Dcutr(dcutr_event)) => {
+                            println!("Hole punching event: {:?}", dcutr_event)
                         },
                         _ => {
                             println!("Received swarm event: {:?}", event)
@@ -275,6 +398,122 @@ impl DisruptedDataSwarm {
             }
 		}
 	}
+
+	/// Serves the DHT while the node is reachable. Behind NAT the node switches to client mode and listens
+	/// through the relays of its bootstrap nodes, so peers can still reach it and DCUtR can hole punch.
+	fn nat_status_changed(&mut self, nat_status: NatStatus) {
+		match nat_status {
+			NatStatus::Public(_) => {
+				self.swarm.behaviour_mut().kad.set_mode(Some(kad::Mode::Server));
+			}
+			NatStatus::Private => {
+				self.swarm.behaviour_mut().kad.set_mode(Some(kad::Mode::Client));
+				if !self.is_listening_on_relays {
+					for bootstrap_node in &self.bootstrap_nodes {
+						let relay_address = bootstrap_node.address.clone()
+							.with(Protocol::P2p(bootstrap_node.peer_id))
+							.with(Protocol::P2pCircuit);
+						match self.swarm.listen_on(relay_address.clone()) {
+							Ok(_) => { println!("Listening through relay {}", relay_address) }
+							Err(error) => { println!("Could not listen through relay {}: {}", relay_address, error) }
+						}
+					}
+					self.is_listening_on_relays = true;
+				}
+			}
+			// Nodes that cannot be probed, e.g. on a private development network, keep their current mode.
+			NatStatus::Unknown => {}
+		}
+	}
+
+	fn progress_put_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(PUT(put_request_state, _)) = &updated_request {
+			if let Some(query_id) = put_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let PutRequestState::SendResponse(action_result) = put_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
+
+	fn progress_get_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(GET(get_request_state, _)) = &updated_request {
+			if let Some(query_id) = get_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let GetRequestState::SendResponse(action_result) = get_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
+
+	/// Answers the request waiting on a failed Kademlia query and forgets the other queries it was waiting on.
+	fn fail_query(&mut self, query_id: QueryId, error: DisruptedDataError) {
+		if let Some(inbound_request_id) = self.kad_request_mapping.remove(&query_id) {
+			self.kad_request_mapping.retain(|_, request_id| *request_id != inbound_request_id);
+			if let Some((_, channel)) = self.requests.remove(&inbound_request_id) {
+				self.swarm.behaviour_mut().send_response(ActionResult::Failure(error), channel);
+			}
+		}
+	}
+
+	/// Parks a delete request until its next Kademlia query completes, or answers the client once the state
+	/// machine has produced a response.
+	fn progress_delete_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(DELETE(delete_request_state, _)) = &updated_request {
+			if let Some(query_id) = delete_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let DeleteRequestState::SendResponse(action_result) = delete_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
+
+	fn progress_index_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(INDEX(index_request_state, _)) = &updated_request {
+			if let Some(query_id) = index_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let IndexRequestState::SendResponse(action_result) = index_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
+
+	fn progress_grant_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(GRANT(grant_request_state, _)) = &updated_request {
+			if let Some(query_id) = grant_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let GrantRequestState::SendResponse(action_result) = grant_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
+
+	fn progress_public_get_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(PUBLIC(public_get_request_state, _)) = &updated_request {
+			if let Some(query_id) = public_get_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let PublicGetRequestState::SendResponse(action_result) = public_get_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
+
+	fn progress_history_request(&mut self, inbound_request_id: InboundRequestId, updated_request: Option<Request>, channel: ResponseChannel<ActionResult>) {
+		if let Some(HISTORY(history_request_state, _)) = &updated_request {
+			if let Some(query_id) = history_request_state.query_id() {
+				self.kad_request_mapping.insert(query_id, inbound_request_id);
+				self.requests.insert(inbound_request_id, (updated_request.unwrap(), channel));
+			} else if let HistoryRequestState::SendResponse(action_result) = history_request_state {
+				self.swarm.behaviour_mut().send_response(action_result.clone(), channel);
+			}
+		}
+	}
 }
 fn read_file(path: PathBuf) -> Vec<u8> {
 	let mut file = match File::open(&path) {
@@ -289,4 +528,4 @@ fn read_file(path: PathBuf) -> Vec<u8> {
 	};
 
 	bytes
-}
\ No newline at end of file
+}
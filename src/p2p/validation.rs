@@ -0,0 +1,234 @@
+use disrupted_data_sdk_rs::payload::MAX_REQUEST_AGE_MILLIS;
+use disrupted_data_sdk_rs::merkle::value_hash;
+use disrupted_data_sdk_rs::{chunk_hash, current_timestamp_millis, DisruptedDataError, ErrorKind, SignedPayload};
+use hex::decode;
+use libp2p::kad::Record;
+
+use crate::p2p::protocol::disrupted_data::types::verify_signature;
+use crate::p2p::record::{check_newer_than, data_record_key, grant_record_key, history_record_key, DataRecord, GrantRecord, OwnerSignature, MAX_RECORD_VERSION};
+use crate::p2p::User;
+
+/// Checks a record another peer asked this node to store.
+///
+/// Data records must carry a valid owner signature, live under the key derived from their owner and record
+/// key, or under the history key of their version, and be signed after the stored copy unless they are that copy.
+/// Index records must only list keys whose put or delete the owner signed. They are merged with the stored copy,
+/// so an older copy cannot drop or roll back a key. Grant records must not roll back the grant of any scope the
+/// stored copy holds.
+pub(crate) fn validate_record(record: &Record, existing_record: Option<&Record>) -> Result<(), DisruptedDataError> {
+	if DataRecord::is_data_record(&record.value) {
+		validate_data_record(record, existing_record)
+	} else if GrantRecord::is_grant_record(&record.value) {
+		validate_grant_record(record, existing_record)
+	} else {
+		validate_user_record(record, existing_record)
+	}
+}
+
+fn validate_data_record(record: &Record, existing_record: Option<&Record>) -> Result<(), DisruptedDataError> {
+	let data_record = DataRecord::from_bytes(&record.value)?;
+	let (record_key, owner) = match &data_record {
+		DataRecord::Value { record_key, owner, .. } | DataRecord::Published { record_key, owner, .. } | DataRecord::Tombstone { record_key, owner, .. } => { (record_key, owner) }
+		DataRecord::Unsigned(_) => { return Err(DisruptedDataError::new(ErrorKind::InvalidSignature, "Record is not signed by its owner".to_string())) }
+	};
+
+	if data_record.version() > MAX_RECORD_VERSION {
+		return Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record version is too high".to_string()));
+	}
+	let owner_record_key = data_record_key(&owner.user_public_key, record_key);
+	if owner_record_key != record.key && history_record_key(&owner_record_key, data_record.version()) != record.key {
+		return Err(DisruptedDataError::new(ErrorKind::InvalidSignature, "Record key does not belong to the record owner".to_string()));
+	}
+	if let (Some(chunk_hash), DataRecord::Value { value, .. } | DataRecord::Published { value, .. }) = (chunk_hash(record_key), &data_record) {
+		if value_hash(value) != chunk_hash {
+			return Err(DisruptedDataError::new(ErrorKind::Integrity, "Chunk does not match its key".to_string()));
+		}
+	}
+	check_timestamp(owner)?;
+	verify(owner, &data_record.signed_payload().expect("Signed data records always have a payload"))?;
+
+	// A stored copy that cannot be decoded is corrupt, so any valid record may replace it. Peers republish the stored
+	// copy itself, any other record must be newer than it, like a put a client sends.
+	match existing_record.map(|existing_record| DataRecord::from_bytes(&existing_record.value)) {
+		Some(Ok(existing_data_record)) if existing_data_record != data_record => { check_newer_than(Some(&existing_data_record), owner) }
+		_ => { Ok(()) }
+	}
+}
+
+fn validate_user_record(record: &Record, existing_record: Option<&Record>) -> Result<(), DisruptedDataError> {
+	let user = User::decode(record)?;
+	let existing_user = existing_record.and_then(|existing_record| User::decode(existing_record).ok());
+
+	for data_record_key in &user.data_record_keys {
+		// Entries written before index entries were signed are only accepted when the stored copy already has them.
+		let owner = match user.signatures.get(data_record_key) {
+			Some(owner) => { owner }
+			None if existing_user.as_ref().is_some_and(|existing_user| existing_user.contains_data_record_key(data_record_key.clone())) => { continue }
+			None => { return Err(DisruptedDataError::new(ErrorKind::InvalidSignature, format!("Index entry {} is not signed by its owner", data_record_key))) }
+		};
+		check_index_owner(&user, data_record_key, owner)?;
+		let mut payload = SignedPayload::new("put", user.key.as_bytes(), data_record_key, owner.timestamp, owner.nonce)
+			.with_visibility(user.visibility(data_record_key))
+			.with_ttl(user.ttls.get(data_record_key).copied())
+			.with_condition(user.conditions.get(data_record_key).copied());
+		payload.value_hash = user.value_hashes.get(data_record_key).cloned().unwrap_or_default();
+		check_timestamp(owner)?;
+		verify(owner, &payload)?;
+	}
+	for (data_record_key, owner) in &user.deleted_keys {
+		check_index_owner(&user, data_record_key, owner)?;
+		check_timestamp(owner)?;
+		verify(owner, &SignedPayload::new("delete", user.key.as_bytes(), data_record_key, owner.timestamp, owner.nonce))?;
+	}
+	Ok(())
+}
+
+fn validate_grant_record(record: &Record, existing_record: Option<&Record>) -> Result<(), DisruptedDataError> {
+	let grant_record = GrantRecord::from_bytes(&record.value)
+		.ok_or(DisruptedDataError::new(ErrorKind::InvalidRequest, "Could not decode grant record".to_string()))?;
+	if grant_record_key(&grant_record.owner_public_key, &grant_record.reader_public_key) != record.key {
+		return Err(DisruptedDataError::new(ErrorKind::InvalidSignature, "Record key does not belong to the grant owner and reader".to_string()));
+	}
+	for grant in &grant_record.grants {
+		if grant.owner.user_public_key != grant_record.owner_public_key {
+			return Err(DisruptedDataError::new(ErrorKind::InvalidSignature, "Grant is not signed by its owner".to_string()));
+		}
+		check_timestamp(&grant.owner)?;
+		verify(&grant.owner, &grant.signed_payload(&grant_record.reader_public_key))?;
+	}
+
+	if let Some(existing_grant_record) = existing_record.and_then(|existing_record| GrantRecord::from_bytes(&existing_record.value)) {
+		for existing_grant in &existing_grant_record.grants {
+			let is_rolled_back = grant_record.grants.iter()
+				.find(|grant| grant.scope == existing_grant.scope)
+				.map_or(true, |grant| grant.owner.timestamp < existing_grant.owner.timestamp);
+			if is_rolled_back {
+				return Err(DisruptedDataError::new(ErrorKind::Conflict, format!("Grant record is older than the stored copy for scope {:?}", existing_grant.scope)));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Entries of an index record must be signed with the key of the user the record belongs to.
+fn check_index_owner(user: &User, data_record_key: &str, owner: &OwnerSignature) -> Result<(), DisruptedDataError> {
+	if owner.user_public_key != user.key.as_bytes() {
+		return Err(DisruptedDataError::new(ErrorKind::InvalidSignature, format!("Index entry {} is not signed by the index owner", data_record_key)));
+	}
+	Ok(())
+}
+
+/// Records may be republished long after they were written, so only timestamps from the future are rejected.
+fn check_timestamp(owner: &OwnerSignature) -> Result<(), DisruptedDataError> {
+	if owner.timestamp > current_timestamp_millis() + MAX_REQUEST_AGE_MILLIS {
+		return Err(DisruptedDataError::new(ErrorKind::InvalidRequest, "Record timestamp is in the future".to_string()));
+	}
+	Ok(())
+}
+
+fn verify(owner: &OwnerSignature, payload: &SignedPayload) -> Result<(), DisruptedDataError> {
+	let public_key_bytes = decode(&owner.user_public_key).map_err(|_| DisruptedDataError::new(ErrorKind::InvalidRequest, "Could not decode public key".to_string()))?;
+	verify_signature(public_key_bytes, owner.signature.clone(), payload)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use disrupted_data_sdk_rs::merkle::value_hash;
+	use disrupted_data_sdk_rs::PutRequest;
+	use libp2p::identity::Keypair;
+	use libp2p::kad::RecordKey;
+
+	fn signed_put(keypair: &Keypair, record_key: &str) -> (PutRequest, OwnerSignature) {
+		let put_request = PutRequest::new(keypair, record_key.to_string(), b"value".to_vec());
+		let owner = OwnerSignature {
+			user_public_key: put_request.user_public_key.clone(),
+			timestamp: put_request.timestamp,
+			nonce: put_request.nonce,
+			signature: put_request.signature.clone(),
+		};
+		(put_request, owner)
+	}
+
+	fn index_record(user_public_key: &[u8], entries: Vec<(PutRequest, OwnerSignature)>) -> Record {
+		let mut user = User::new(&RecordKey::new(&user_public_key));
+		for (put_request, owner) in entries {
+			user.add_data_record(put_request.record_key, value_hash(&put_request.record_value), owner, put_request.visibility, put_request.ttl, put_request.condition);
+		}
+		user.try_into().unwrap()
+	}
+
+	#[test]
+	fn accepts_index_entries_signed_by_the_owner() {
+		let owner_keypair = Keypair::generate_secp256k1();
+		let (put_request, owner) = signed_put(&owner_keypair, "hello");
+		let record = index_record(&put_request.user_public_key.clone(), vec![(put_request, owner)]);
+
+		assert!(validate_record(&record, None).is_ok());
+	}
+
+	#[test]
+	fn rejects_index_entries_signed_by_another_key() {
+		let (owner_keypair, other_keypair) = (Keypair::generate_secp256k1(), Keypair::generate_secp256k1());
+		let (owner_put_request, _) = signed_put(&owner_keypair, "hello");
+		let (other_put_request, other_owner) = signed_put(&other_keypair, "hello");
+		let record = index_record(&owner_put_request.user_public_key, vec![(other_put_request, other_owner.clone())]);
+
+		assert_eq!(validate_record(&record, None).unwrap_err().kind, ErrorKind::InvalidSignature);
+
+		let mut user = User::decode(&record).unwrap();
+		user.signatures.insert("hello".to_string(), other_owner);
+		assert_eq!(check_index_owner(&user, "hello", &user.signatures["hello"]).unwrap_err().kind, ErrorKind::InvalidSignature);
+	}
+
+	#[test]
+	fn rejects_records_whose_put_condition_was_dropped() {
+		let owner_keypair = Keypair::generate_secp256k1();
+		let (put_request, owner) = signed_put(&owner_keypair, "hello");
+		let put_request = put_request.if_version(&owner_keypair, 4);
+		let owner = OwnerSignature { signature: put_request.signature.clone(), ..owner };
+		let record = index_record(&put_request.user_public_key.clone(), vec![(put_request.clone(), owner.clone())]);
+		assert!(validate_record(&record, None).is_ok());
+
+		let mut user = User::decode(&record).unwrap();
+		user.conditions.clear();
+		assert_eq!(validate_record(&user.try_into().unwrap(), None).unwrap_err().kind, ErrorKind::InvalidSignature);
+
+		let data_record_key = data_record_key(&put_request.user_public_key, "hello");
+		let data_record = DataRecord::Value { record_key: "hello".to_string(), value: put_request.record_value, version: 5, ttl: None, condition: put_request.condition, owner };
+		assert!(validate_record(&data_record.to_record(data_record_key.clone()), None).is_ok());
+
+		let mut unconditional_data_record = data_record.clone();
+		if let DataRecord::Value { condition, .. } = &mut unconditional_data_record {
+			*condition = None;
+		}
+		assert_eq!(validate_record(&unconditional_data_record.to_record(data_record_key), None).unwrap_err().kind, ErrorKind::InvalidSignature);
+	}
+
+	#[test]
+	fn rejects_data_records_beyond_the_highest_version() {
+		let owner_keypair = Keypair::generate_secp256k1();
+		let (put_request, owner) = signed_put(&owner_keypair, "hello");
+		let data_record_key = data_record_key(&put_request.user_public_key, "hello");
+		let data_record = |version| DataRecord::Value { record_key: "hello".to_string(), value: put_request.record_value.clone(), version, ttl: None, condition: None, owner: owner.clone() };
+
+		assert!(validate_record(&data_record(MAX_RECORD_VERSION).to_record(data_record_key.clone()), None).is_ok());
+		assert_eq!(validate_record(&data_record(u64::MAX).to_record(data_record_key), None).unwrap_err().kind, ErrorKind::InvalidRequest);
+	}
+
+	#[test]
+	fn rejects_data_records_not_newer_than_the_stored_copy() {
+		let owner_keypair = Keypair::generate_secp256k1();
+		let mut puts = [signed_put(&owner_keypair, "hello"), signed_put(&owner_keypair, "hello")];
+		puts.sort_by_key(|(_, owner)| (owner.timestamp, owner.nonce));
+		let [(older_put_request, older_owner), (put_request, owner)] = puts;
+		let data_record_key = data_record_key(&put_request.user_public_key, "hello");
+		let stored_record = DataRecord::Value { record_key: "hello".to_string(), value: put_request.record_value, version: 2, ttl: None, condition: None, owner };
+		let stored_record = stored_record.to_record(data_record_key.clone());
+		let replayed_record = DataRecord::Value { record_key: "hello".to_string(), value: older_put_request.record_value, version: 9, ttl: None, condition: None, owner: older_owner };
+
+		assert!(validate_record(&stored_record, Some(&stored_record)).is_ok());
+		assert!(validate_record(&stored_record, Some(&replayed_record.to_record(data_record_key.clone()))).is_ok());
+		assert_eq!(validate_record(&replayed_record.to_record(data_record_key), Some(&stored_record)).unwrap_err().kind, ErrorKind::Replayed);
+	}
+}
@@ -1,17 +1,81 @@
 use std::env;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
+use disrupted_data_sdk_rs::{DisruptedDataError, ErrorKind};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
-use toml::{Table, Value};
-use toml::map::Map;
+use toml::Table;
+
+const DEFAULT_PORT: &str = "6969";
+/// Number of versions of a record kept by default, the current one included.
+const DEFAULT_HISTORY_RETENTION: usize = 10;
+/// Number of records a node stores by default.
+const DEFAULT_MAX_RECORDS: usize = 65_536;
+/// Largest record value a node stores by default. Records must fit in a Kademlia packet, which nodes cap at 128 KiB.
+const DEFAULT_MAX_RECORD_SIZE: usize = 120 * 1024;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NodeConfig {
     pub ip_address: Option<String>,
     pub port: Option<String>,
     pub node_key_location: Option<PathBuf>,
-    pub bootstrap_nodes: Option<Table>,
+    pub bootstrap_nodes: Option<BootstrapNodesConfig>,
     pub log_file: Option<String>,
+    pub record_store: Option<String>,
+    pub record_store_location: Option<PathBuf>,
+    pub quic: Option<bool>,
+    pub quic_port: Option<String>,
+    pub websocket_port: Option<String>,
+    pub mdns: Option<bool>,
+    pub history_retention: Option<usize>,
+    pub max_records: Option<usize>,
+    pub max_record_size: Option<usize>,
+    pub record_store_sync: Option<String>,
+}
+
+/// Value of `bootstrap_nodes` in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum BootstrapNodesConfig {
+    /// Comma separated multiaddrs, as written into the config template from `BOOTSTRAP_NODES`.
+    Addresses(String),
+    List(Vec<String>),
+    /// Legacy `{ <peer id> = '<ip>' }` table. These nodes are dialed over TCP on the default port.
+    PeerIps(Table),
+}
+
+/// A node to bootstrap the Kademlia routing table from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapNode {
+    pub peer_id: PeerId,
+    /// Address of the node without its trailing `/p2p/<peer id>`.
+    pub address: Multiaddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordStoreKind {
+    Memory,
+    File(PathBuf, JournalSync),
+}
+
+/// When appends to the record store journal are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalSync {
+    /// Every append is synced before the write is acknowledged, so acknowledged records survive a power loss.
+    Always,
+    /// Appends are handed to the operating system, which flushes them when it sees fit. Faster, but records
+    /// acknowledged shortly before a power loss may be lost. They still survive the node process crashing.
+    Os,
+}
+
+/// Capacity of a node's record store. Records beyond it are refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordStoreLimits {
+    pub max_records: usize,
+    /// Largest record value in bytes.
+    pub max_record_size: usize,
 }
 
 impl NodeConfig {
@@ -22,6 +86,16 @@ impl NodeConfig {
             node_key_location: None,
             bootstrap_nodes: None,
             log_file: None,
+            record_store: None,
+            record_store_location: None,
+            quic: None,
+            quic_port: None,
+            websocket_port: None,
+            mdns: None,
+            history_retention: None,
+            max_records: None,
+            max_record_size: None,
+            record_store_sync: None,
         }
     }
     pub fn ip_address(&self) -> String {
@@ -29,7 +103,7 @@ impl NodeConfig {
             let ip_address_clone = self.ip_address.clone().unwrap();
             ip_address_clone
         } else {
-            env::var("IP_ADDRESS").unwrap_or_else(|e| { "127.0.0.1".to_string() })
+            env::var("IP_ADDRESS").unwrap_or_else(|_| { "127.0.0.1".to_string() })
         }
     }
     pub fn port(&self) -> String {
@@ -37,7 +111,47 @@ impl NodeConfig {
             let port_clone = self.port.clone().unwrap();
             port_clone
         } else {
-            env::var("PORT").unwrap_or_else(|e| { "6969".to_string() })
+            env::var("PORT").unwrap_or_else(|_| { DEFAULT_PORT.to_string() })
+        }
+    }
+    /// UDP port of the QUIC listener, or `None` when QUIC is disabled. Defaults to the TCP port number.
+    pub fn quic_port(&self) -> Option<String> {
+        if !self.quic.unwrap_or_else(|| env_flag("QUIC")) {
+            return None;
+        }
+
+        let quic_port = self.quic_port.clone()
+            .or_else(|| env::var("QUIC_PORT").ok())
+            .filter(|quic_port| !quic_port.trim().is_empty())
+            .unwrap_or_else(|| self.port());
+        Some(quic_port)
+    }
+    /// TCP port of the WebSocket listener used by browser clients, or `None` when it is disabled. It must differ
+    /// from the plain TCP port.
+    pub fn websocket_port(&self) -> Option<String> {
+        self.websocket_port.clone()
+            .or_else(|| env::var("WEBSOCKET_PORT").ok())
+            .filter(|websocket_port| !websocket_port.trim().is_empty())
+    }
+    /// Whether nodes on the local network are discovered over mDNS and added to the routing table, so development
+    /// clusters do not need a bootstrap table.
+    pub fn mdns(&self) -> bool {
+        self.mdns.unwrap_or_else(|| env_flag("MDNS"))
+    }
+    /// Number of versions of every record the node keeps, the current one included. Older versions are dropped
+    /// as new ones are written. At least the current version is always kept.
+    pub fn history_retention(&self) -> usize {
+        self.history_retention
+            .or_else(|| env_usize("HISTORY_RETENTION"))
+            .unwrap_or(DEFAULT_HISTORY_RETENTION)
+            .max(1)
+    }
+    /// Number of records and largest record value the node stores, from `max_records` (`MAX_RECORDS`) and
+    /// `max_record_size` (`MAX_RECORD_SIZE`).
+    pub fn record_store_limits(&self) -> RecordStoreLimits {
+        RecordStoreLimits {
+            max_records: self.max_records.or_else(|| env_usize("MAX_RECORDS")).unwrap_or(DEFAULT_MAX_RECORDS),
+            max_record_size: self.max_record_size.or_else(|| env_usize("MAX_RECORD_SIZE")).unwrap_or(DEFAULT_MAX_RECORD_SIZE),
         }
     }
     pub fn node_key_location(self) -> PathBuf {
@@ -46,47 +160,129 @@ impl NodeConfig {
             Path::new(&env_node_key_location).to_path_buf()
         })
     }
-    pub fn bootstrap_nodes(&self) -> Table {
-        if self.bootstrap_nodes.is_some() {
-            let bootstrap_nodes_clone = self.bootstrap_nodes.clone();
-            bootstrap_nodes_clone.unwrap()
-        } else {
-            let mut boostrap_nodes_map: Table = Map::new();
-            boostrap_nodes_map.insert("1AXGCQH1hrhZ99MTDDJbMYZD6AYfsfACVDDVZRPxm5KWnj".to_string(), Value::String("189.90.0.2".to_string()));
-            let environment_bootstrap_nodes = env::var("BOOTSTRAP_NODES");
-            match environment_bootstrap_nodes {
-                Ok(env_bootstrap_nodes) => {
-                    let env_bootstrap_nodes = parse_string_to_table(env_bootstrap_nodes);
-                    env_bootstrap_nodes
-                }
-                Err(err) => {
-                    boostrap_nodes_map
-                }
+    /// Bootstrap nodes from the config file, or from the `BOOTSTRAP_NODES` environment variable. Every entry
+    /// is a full multiaddr ending in `/p2p/<peer id>`, e.g. `/ip4/170.64.140.33/tcp/6969/p2p/12D3KooW...`,
+    /// `/ip4/170.64.140.33/udp/6969/quic-v1/p2p/12D3KooW...` or `/dns4/bootstrap.example.com/tcp/6969/p2p/12D3KooW...`.
+    /// All invalid entries are reported together.
+    pub fn bootstrap_nodes(&self) -> Result<Vec<BootstrapNode>, DisruptedDataError> {
+        let entries = match &self.bootstrap_nodes {
+            Some(BootstrapNodesConfig::Addresses(addresses)) => { split_bootstrap_entries(addresses) }
+            Some(BootstrapNodesConfig::List(addresses)) => { addresses.clone() }
+            Some(BootstrapNodesConfig::PeerIps(peer_ips)) => {
+                peer_ips.iter()
+                    .map(|(peer_id, ip)| legacy_bootstrap_entry(peer_id, ip.as_str().unwrap_or_default()))
+                    .collect()
+            }
+            None => { env::var("BOOTSTRAP_NODES").map(|addresses| split_bootstrap_entries(&addresses)).unwrap_or_default() }
+        };
+
+        let mut bootstrap_nodes = vec![];
+        let mut errors = vec![];
+        for entry in entries {
+            match parse_bootstrap_node(&entry) {
+                Ok(bootstrap_node) => { bootstrap_nodes.push(bootstrap_node) }
+                Err(error) => { errors.push(format!("'{}': {}", entry, error)) }
             }
         }
+
+        if errors.is_empty() {
+            Ok(bootstrap_nodes)
+        } else {
+            Err(DisruptedDataError::new(ErrorKind::Config, format!("Invalid bootstrap nodes: {}", errors.join("; "))))
+        }
     }
     pub fn log_file(&self) -> String {
         if self.log_file.is_some() {
             let log_file_clone = self.log_file.clone().unwrap();
             log_file_clone
         } else {
-            env::var("LOG_FILE").unwrap_or_else(|e| { "C:\\Nostr\\disrupted-data\\disrupted-data.log".to_string() })
+            env::var("LOG_FILE").unwrap_or_else(|_| { "C:\\Nostr\\disrupted-data\\disrupted-data.log".to_string() })
+        }
+    }
+    /// Where the node keeps its records. Fails on a store kind other than `memory` or `file`, or a journal sync
+    /// policy other than `always` or `os`.
+    pub fn record_store(&self) -> Result<RecordStoreKind, DisruptedDataError> {
+        let record_store = self.record_store.clone()
+            .or_else(|| env::var("RECORD_STORE").ok())
+            .unwrap_or_else(|| "memory".to_string());
+
+        match record_store.trim().to_lowercase().as_str() {
+            "file" => {
+                let record_store_location = self.record_store_location.clone()
+                    .or_else(|| env::var("RECORD_STORE_LOCATION").ok().map(PathBuf::from))
+                    .filter(|location| !location.as_os_str().is_empty())
+                    .unwrap_or_else(|| PathBuf::from("disrupted-data.records"));
+                Ok(RecordStoreKind::File(record_store_location, self.record_store_sync()?))
+            }
+            "memory" | "" => { Ok(RecordStoreKind::Memory) }
+            unknown => { Err(DisruptedDataError::new(ErrorKind::Config, format!("Unknown record store '{}'. Expected 'memory' or 'file'", unknown))) }
+        }
+    }
+    fn record_store_sync(&self) -> Result<JournalSync, DisruptedDataError> {
+        let record_store_sync = self.record_store_sync.clone()
+            .or_else(|| env::var("RECORD_STORE_SYNC").ok())
+            .unwrap_or_default();
+
+        match record_store_sync.trim().to_lowercase().as_str() {
+            "always" | "" => { Ok(JournalSync::Always) }
+            "os" => { Ok(JournalSync::Os) }
+            unknown => { Err(DisruptedDataError::new(ErrorKind::Config, format!("Unknown record store sync '{}'. Expected 'always' or 'os'", unknown))) }
         }
     }
 }
 
-fn parse_string_to_table(env_bootstrap_nodes: String) -> Table {
-    let mut boostrap_nodes_map: Table = Map::new();
+/// Reads a numeric environment variable. Unset or invalid values are `None`.
+fn env_usize(name: &str) -> Option<usize> {
+    env::var(name).ok().and_then(|value| value.trim().parse().ok())
+}
 
-    let cleaned_str = env_bootstrap_nodes.trim_matches(|c| c == '{' || c == '}');
-    let parts: Vec<&str> = cleaned_str.split("=").collect();
+/// Reads a boolean environment variable such as `QUIC=true`. Unset or unrecognised values are false.
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| matches!(value.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+}
 
-    if parts.len() == 2 {
-        println!("part[0]: {}", parts[0]);
-        println!("part[1]: {}", parts[1]);
-        let key = parts[0].trim().trim_matches('"').to_string();
-        let value = parts[1].trim().trim_matches('"').to_string();
-        boostrap_nodes_map.insert(key, Value::String(value));
+/// Splits a comma or whitespace separated list of multiaddrs. The legacy `{<peer id>=<ip>}` form is still accepted.
+fn split_bootstrap_entries(addresses: &str) -> Vec<String> {
+    let addresses = addresses.trim();
+    if let Some(peer_ips) = addresses.strip_prefix('{').and_then(|peer_ips| peer_ips.strip_suffix('}')) {
+        return peer_ips.split(',')
+            .filter(|peer_ip| !peer_ip.trim().is_empty())
+            .map(|peer_ip| match peer_ip.split_once('=') {
+                Some((peer_id, ip)) => { legacy_bootstrap_entry(peer_id.trim().trim_matches('"'), ip.trim().trim_matches('"')) }
+                None => { peer_ip.trim().to_string() }
+            })
+            .collect();
+    }
+
+    addresses.split(|character: char| character == ',' || character.is_whitespace())
+        .filter(|address| !address.is_empty())
+        .map(|address| address.to_string())
+        .collect()
+}
+
+fn legacy_bootstrap_entry(peer_id: &str, host: &str) -> String {
+    let host_protocol = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => { "ip4" }
+        Ok(IpAddr::V6(_)) => { "ip6" }
+        Err(_) => { "dns4" }
     };
-    boostrap_nodes_map
-}
\ No newline at end of file
+    format!("/{}/{}/tcp/{}/p2p/{}", host_protocol, host, DEFAULT_PORT, peer_id)
+}
+
+fn parse_bootstrap_node(entry: &str) -> Result<BootstrapNode, String> {
+    let mut address: Multiaddr = entry.parse().map_err(|error| format!("not a valid multiaddr ({})", error))?;
+
+    let peer_id = match address.pop() {
+        Some(Protocol::P2p(peer_id)) => { peer_id }
+        _ => { return Err("must end with /p2p/<peer id>".to_string()) }
+    };
+    match address.iter().next() {
+        Some(Protocol::Ip4(_) | Protocol::Ip6(_) | Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_)) => {}
+        _ => { return Err("must start with /ip4, /ip6, /dns, /dns4 or /dns6".to_string()) }
+    }
+    if !address.iter().any(|protocol| matches!(protocol, Protocol::Tcp(_) | Protocol::QuicV1)) {
+        return Err("must contain a /tcp/<port> or /udp/<port>/quic-v1".to_string());
+    }
+
+    Ok(BootstrapNode { peer_id, address })
+}